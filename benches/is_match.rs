@@ -0,0 +1,134 @@
+//! 公開 API の Regex::is_match と Regex::find のベンチマーク
+//!
+//! 各ベンチマークを深さ優先探索と幅優先探索の両方の評価器で計測する。
+//! cargo bench で実行する。
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rs_regex::{Engine, Regex, RegexBuilder};
+
+const ENGINES: [(&str, Engine); 2] = [("depth", Engine::DepthFirst), ("width", Engine::WidthFirst)];
+
+/// 指定した評価器で正規表現をコンパイル
+fn compile(expr: &str, engine: Engine) -> Regex {
+    RegexBuilder::new(expr).engine(engine).build().unwrap()
+}
+
+/// 英小文字と空白からなる、擬似乱数で生成した長い文字列
+///
+/// 毎回同じ入力になるように、シードを固定した線形合同法で生成する
+fn haystack(len: usize) -> String {
+    let mut x: u32 = 12345;
+    (0..len)
+        .map(|_| {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            match (x >> 16) % 27 {
+                26 => ' ',
+                n => (b'a' + n as u8) as char,
+            }
+        })
+        .collect()
+}
+
+/// リテラルの完全一致
+fn literal(c: &mut Criterion) {
+    let mut group = c.benchmark_group("literal");
+    for (name, engine) in ENGINES {
+        let re = compile("^hello world$", engine);
+        group.bench_function(name, |b| b.iter(|| re.is_match(black_box("hello world"))));
+    }
+    group.finish();
+}
+
+/// 選択を多く含むパターン
+fn alternation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("alternation");
+    let line = "the quick brown fox jumps over the lazy dog";
+    for (name, engine) in ENGINES {
+        let re = compile("(cat|dog|bird|fish|horse|cow)$", engine);
+        group.bench_function(name, |b| b.iter(|| re.is_match(black_box(line))));
+    }
+    group.finish();
+}
+
+/// * を多く含むパターン
+fn star(c: &mut Criterion) {
+    let mut group = c.benchmark_group("star");
+    let line = "a".repeat(100) + "b";
+    for (name, engine) in ENGINES {
+        let re = compile("^a*a*a*b$", engine);
+        group.bench_function(name, |b| b.iter(|| re.is_match(black_box(&line))));
+    }
+    group.finish();
+}
+
+/// 長い入力の途中にあるマッチの探索
+fn unanchored(c: &mut Criterion) {
+    let mut group = c.benchmark_group("unanchored");
+    for len in [1_000, 10_000] {
+        let line = haystack(len) + "needle";
+        for (name, engine) in ENGINES {
+            let re = compile("ne+dle", engine);
+            group.bench_with_input(BenchmarkId::new(name, len), &line, |b, line| {
+                b.iter(|| re.find(black_box(line)))
+            });
+        }
+    }
+    group.finish();
+}
+
+/// 先頭の文字が入力にほとんど現れないパターンによる、長い入力の探索
+///
+/// 開始位置の候補の探索が大半を占めるため、cargo bench --features simd で
+/// x86_64 の SSE2 による探索と、フィーチャなしの1文字ずつの探索を比べる
+fn rare_prefix(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rare_prefix");
+    let line = haystack(100_000) + "Zebra";
+    for (name, engine) in ENGINES {
+        let re = compile("Ze+bra", engine);
+        group.bench_function(name, |b| b.iter(|| re.find(black_box(&line))));
+    }
+    group.finish();
+}
+
+/// 命令列が長いパターンによる、長い入力の探索
+///
+/// 評価器が命令を振り分ける処理の費用を測るため、文字列の選択の後に文字クラスを続けて
+/// トライ木による探索を用いないようにする
+fn large_program(c: &mut Criterion) {
+    let mut group = c.benchmark_group("large_program");
+    let words: Vec<String> = (0..100).map(|i| format!("w{i}rd")).collect();
+    let expr = format!("(?:{})[0-9]", words.join("|"));
+    let line = haystack(1_000);
+    for (name, engine) in ENGINES {
+        let re = compile(&expr, engine);
+        group.bench_function(name, |b| b.iter(|| re.is_match(black_box(&line))));
+    }
+    group.finish();
+}
+
+/// (a?){n}a{n} に a を n 個並べた入力を与える、バックトラックで指数時間となる例
+fn pathological(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pathological");
+    for n in [8, 16] {
+        let expr = format!("^(a?){{{n}}}a{{{n}}}$");
+        let line = "a".repeat(n);
+        for (name, engine) in ENGINES {
+            let re = compile(&expr, engine);
+            group.bench_with_input(BenchmarkId::new(name, n), &line, |b, line| {
+                b.iter(|| re.is_match(black_box(line)))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    literal,
+    alternation,
+    star,
+    unanchored,
+    rare_prefix,
+    large_program,
+    pathological
+);
+criterion_main!(benches);
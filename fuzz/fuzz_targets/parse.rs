@@ -0,0 +1,49 @@
+//! パーサーのファジング
+//!
+//! 任意のバイト列のうち UTF-8 として正しいものを正規表現としてコンパイルし、
+//! パニックせずに Ok か Err を返すことを確かめる。
+//! 先頭のバイトでパーサーのオプションを選び、残りをパターンとする。
+//! cargo-fuzz をインストールし、リポジトリのルートで次のように実行する。
+//!
+//! ```sh
+//! cargo install cargo-fuzz
+//! cargo +nightly fuzz run parse
+//! ```
+//!
+//! パニックした入力は fuzz/artifacts/parse/ に保存され、
+//! cargo +nightly fuzz run parse <ファイル> で再現できる。
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rs_regex::{Regex, RegexBuilder, RegexError};
+
+fuzz_target!(|data: &[u8]| {
+    let (options, expr) = if let Some((options, expr)) = data.split_first() {
+        (*options, expr)
+    } else {
+        return;
+    };
+    let expr = if let Ok(expr) = std::str::from_utf8(expr) {
+        expr
+    } else {
+        return;
+    };
+
+    // 限量子の連続を許すオプションでパースする
+    let _ = Regex::lint(expr);
+
+    let result = RegexBuilder::new(expr)
+        .ignore_whitespace(options & 1 != 0)
+        .case_insensitive(options & 2 != 0)
+        .allow_missing_min(options & 4 != 0)
+        .build();
+    // DFA に変換できない場合もパニックせずにエラーを返す
+    let result = result.and_then(|re| re.compile_dfa());
+    match result {
+        Ok(_)
+        | Err(RegexError::Parse(_))
+        | Err(RegexError::CodeGen(_))
+        | Err(RegexError::DfaUnsupported(_))
+        | Err(RegexError::DfaTooLarge { .. }) => (),
+    }
+});
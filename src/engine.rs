@@ -1,18 +1,3326 @@
-#[derive(Debug)]
+//! 正規表現エンジン
+#[cfg(feature = "std")]
+mod cache;
+mod codegen;
+mod dfa;
+mod dot;
+mod evaluator;
+mod literal;
+mod parser;
+mod program;
+mod scan;
+mod set;
+#[cfg(feature = "std")]
+mod stream;
+#[cfg(feature = "unicode")]
+mod unicode_tables;
+mod visit;
+
+use std::{
+    borrow::Cow,
+    convert::Infallible,
+    error::Error,
+    fmt::{self, Display},
+    ops::Range,
+};
+
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+
+pub use codegen::CodeGenError;
+pub use dfa::Dfa;
+pub use evaluator::EvalError;
+pub use parser::{Features, Lint, ParseError, ParseErrorCode};
+pub use program::Program;
+pub use set::{Lexer, RegexSet};
+
+#[derive(Debug, Clone)]
 pub enum Instruction {
     Char(char),
+    Range(char, char),                    // 範囲内の文字にマッチ
+    AsciiClass([u64; 2]),                 // ビットが立っている ASCII 文字にマッチ
+    Class(Vec<(char, char)>), // 昇順に並び、互いに重ならない範囲のいずれかに含まれる文字にマッチ
+    AnyChar(bool), // 任意の1文字 (書記素クラスタ単位の場合は1クラスタ) にマッチ。偽の場合は改行を除く
+    Predicate(String, parser::Predicate), // 述語が真となる文字にマッチ
     Match,
+    MatchId(usize), // 複数のパターンを結合した命令列で、何番目のパターンにマッチしたかを表す match
     Jump(usize),
     Split(usize, usize),
+    RepeatInit(usize),                                  // カウンタを 0 に初期化
+    RepeatCheck(usize, usize, Option<usize>, usize),    // カウンタ、下限、上限、ループの脱出先
+    RepeatInc(usize, usize),                            // カウンタ、カウンタの最大値
+    AnchorStart,                                        // 入力の先頭であるかを検査
+    AnchorEnd,                                          // 入力の末尾であるかを検査
+    AnchorLineStart,                                    // 入力の先頭か、改行の直後であるかを検査
+    AnchorLineEnd,                                      // 入力の末尾か、改行の直前であるかを検査
+    WordBoundary(bool, bool), // 単語境界であるかを検査。否定 (\B) であるかと、Unicode の \w で判定するか
+    AnchorContinue,           // 走査の開始位置であるかを検査
+    Save(usize),              // 現在の位置をキャプチャグループのスロットに保存
+    CheckGroup(usize, bool), // キャプチャグループがマッチしたか (偽の場合はマッチしていないか) を検査
+    LookBehind(bool, usize, Vec<Instruction>), // 否定であるか、後読みする文字数、後読みする式の命令列
+    JumpRel(isize),                            // 飛び先を命令自身からの相対アドレスで表す jump
+    SplitRel(isize, isize),                    // 飛び先を命令自身からの相対アドレスで表す split
+    RepeatCheckRel(usize, usize, Option<usize>, isize), // 脱出先を相対アドレスで表す repeat_check
+    Nop, // 何もせず次の命令へ進む。コード生成中に飛び先が決まるまでの仮の命令
+}
+
+impl Instruction {
+    /// pc 番目にある相対アドレスの命令を、絶対アドレスの命令に変換
+    ///
+    /// 相対アドレスの命令でない場合はそのまま返す。飛び先が負となる場合は None を返す
+    fn to_absolute(&self, pc: usize) -> Option<Cow<'_, Instruction>> {
+        let addr = |offset: isize| pc.checked_add_signed(offset);
+        let inst = match self {
+            Instruction::JumpRel(offset) => Instruction::Jump(addr(*offset)?),
+            Instruction::SplitRel(offset1, offset2) => {
+                Instruction::Split(addr(*offset1)?, addr(*offset2)?)
+            }
+            Instruction::RepeatCheckRel(reg, min, max, offset) => {
+                Instruction::RepeatCheck(*reg, *min, *max, addr(*offset)?)
+            }
+            _ => return Some(Cow::Borrowed(self)),
+        };
+        Some(Cow::Owned(inst))
+    }
 }
 
 impl Display for Instruction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Instruction::Char(c) => write!(f, "char {}", c),
+            Instruction::Range(lo, hi) => write!(f, "range {}, {}", lo, hi),
+            Instruction::AsciiClass(bits) => {
+                write!(f, "ascii_class {:016x}{:016x}", bits[1], bits[0])
+            }
+            Instruction::Class(ranges) => write!(f, "class {} ranges", ranges.len()),
+            Instruction::AnyChar(false) => write!(f, "any_char"),
+            Instruction::AnyChar(true) => write!(f, "any_char_nl"),
+            Instruction::Predicate(name, _) => write!(f, "predicate {}", name),
             Instruction::Match => write!(f, "match"),
+            Instruction::MatchId(id) => write!(f, "match_id {}", id),
             Instruction::Jump(addr) => write!(f, "jump {:>04}", addr),
             Instruction::Split(addr1, addr2) => write!(f, "split {:>04}, {:>04}", addr1, addr2),
+            Instruction::RepeatInit(reg) => write!(f, "repeat_init r{}", reg),
+            Instruction::RepeatCheck(reg, min, Some(max), exit) => {
+                write!(f, "repeat_check r{}, {}, {}, {:>04}", reg, min, max, exit)
+            }
+            Instruction::RepeatCheck(reg, min, None, exit) => {
+                write!(f, "repeat_check r{}, {}, inf, {:>04}", reg, min, exit)
+            }
+            Instruction::RepeatInc(reg, limit) => write!(f, "repeat_inc r{}, {}", reg, limit),
+            Instruction::AnchorStart => write!(f, "anchor_start"),
+            Instruction::AnchorEnd => write!(f, "anchor_end"),
+            Instruction::AnchorLineStart => write!(f, "anchor_line_start"),
+            Instruction::AnchorLineEnd => write!(f, "anchor_line_end"),
+            Instruction::WordBoundary(negate, unicode) => {
+                let name = if *negate {
+                    "not_word_boundary"
+                } else {
+                    "word_boundary"
+                };
+                let kind = if *unicode { "unicode" } else { "ascii" };
+                write!(f, "{} {}", name, kind)
+            }
+            Instruction::AnchorContinue => write!(f, "anchor_continue"),
+            Instruction::Save(slot) => write!(f, "save {}", slot),
+            Instruction::CheckGroup(group, true) => write!(f, "check_group {}", group),
+            Instruction::CheckGroup(group, false) => write!(f, "check_no_group {}", group),
+            Instruction::LookBehind(negate, len, insts) => {
+                let name = if *negate {
+                    "neg_look_behind"
+                } else {
+                    "look_behind"
+                };
+                let insts: Vec<String> = insts.iter().map(|inst| inst.to_string()).collect();
+                write!(f, "{} {} ({})", name, len, insts.join("; "))
+            }
+            Instruction::JumpRel(offset) => write!(f, "jump_rel {:+}", offset),
+            Instruction::SplitRel(offset1, offset2) => {
+                write!(f, "split_rel {:+}, {:+}", offset1, offset2)
+            }
+            Instruction::RepeatCheckRel(reg, min, Some(max), offset) => {
+                write!(
+                    f,
+                    "repeat_check_rel r{}, {}, {}, {:+}",
+                    reg, min, max, offset
+                )
+            }
+            Instruction::RepeatCheckRel(reg, min, None, offset) => {
+                write!(f, "repeat_check_rel r{}, {}, inf, {:+}", reg, min, offset)
+            }
+            Instruction::Nop => write!(f, "nop"),
+        }
+    }
+}
+
+/// 正規表現のコンパイル時のエラーを表す型
+#[derive(Debug)]
+pub enum RegexError {
+    Parse(ParseError),             // パース中のエラー
+    CodeGen(CodeGenError),         // コード生成中のエラー
+    DfaUnsupported(&'static str),  // DFA に変換できない構文や設定を含む。その構文や設定の名前
+    DfaTooLarge { states: usize }, // DFA の状態の数が上限を超える。上限を超えた時点の状態の数
+}
+
+impl Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegexError::Parse(e) => write!(f, "{e}"),
+            RegexError::CodeGen(e) => write!(f, "{e}"),
+            RegexError::DfaUnsupported(name) => {
+                write!(f, "RegexError: unsupported in DFA: {name}")
+            }
+            RegexError::DfaTooLarge { states } => {
+                write!(f, "RegexError: too many DFA states: states = {states}")
+            }
+        }
+    }
+}
+
+impl Error for RegexError {}
+
+/// ? 演算子でパースのエラーを RegexError に変換するため、From トレイトを実装
+impl From<ParseError> for RegexError {
+    fn from(e: ParseError) -> Self {
+        RegexError::Parse(e)
+    }
+}
+
+/// ? 演算子でコード生成のエラーを RegexError に変換するため、From トレイトを実装
+impl From<CodeGenError> for RegexError {
+    fn from(e: CodeGenError) -> Self {
+        RegexError::CodeGen(e)
+    }
+}
+
+/// マッチングに用いる評価器の種類
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    DepthFirst, // 深さ優先探索（バックトラック）
+    #[default]
+    WidthFirst, // 幅優先探索
+    Auto,       // 幅優先探索に加え、is_match では DFA に変換できる場合は DFA を用いる
+}
+
+/// マッチした範囲を表す型
+///
+/// start, end は既定では入力文字列のバイト単位のインデックスで、end は含まない。
+/// RegexBuilder::byte_offsets に偽を指定した場合は、文字単位のインデックスとなる。
+/// 't は入力文字列のライフタイムで、マッチした部分文字列は入力文字列を借用する
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match<'t> {
+    start: usize,                  // 文字単位の開始位置
+    end: usize,                    // 文字単位の終了位置
+    bytes: Option<(usize, usize)>, // バイト単位で報告する場合の、バイト単位の開始位置と終了位置
+    pattern: usize,
+    text: &'t str, // マッチした部分文字列
+}
+
+impl<'t> Match<'t> {
+    /// マッチの開始位置
+    pub fn start(&self) -> usize {
+        self.bytes.map_or(self.start, |(start, _)| start)
+    }
+
+    /// マッチの終了位置
+    pub fn end(&self) -> usize {
+        self.bytes.map_or(self.end, |(_, end)| end)
+    }
+
+    /// マッチしたパターンの番号
+    ///
+    /// Regex::union で結合した正規表現では、どちらの正規表現にマッチしたかを表す。
+    /// 単一のパターンから生成した正規表現では常に 0
+    pub fn pattern(&self) -> usize {
+        self.pattern
+    }
+
+    /// マッチした部分文字列
+    ///
+    /// 新たな文字列は確保せず、入力文字列の一部を借用して返す
+    pub fn as_str(&self) -> &'t str {
+        self.text
+    }
+}
+
+/// Regex::find_traced で記録する、評価器が実行した命令
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEvent {
+    pc: usize,           // 実行した命令のアドレス
+    sp: usize,           // 実行時の入力の文字単位の位置
+    instruction: String, // 実行した命令の、disassemble と同じ形式の表現
+}
+
+impl TraceEvent {
+    /// 実行した命令のアドレス
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// 実行時の入力の位置
+    pub fn sp(&self) -> usize {
+        self.sp
+    }
+
+    /// 実行した命令を、char a や split 0001, 0003 のような文字列で返す
+    pub fn instruction(&self) -> &str {
+        &self.instruction
+    }
+}
+
+/// キャプチャグループごとのマッチした範囲
+///
+/// 0 番目のグループはマッチ全体を表す
+#[derive(Debug, Clone)]
+pub struct Captures<'r, 't> {
+    groups: Vec<Option<Match<'t>>>, // グループ番号順のマッチした範囲
+    names: &'r [Option<String>],    // グループ番号順のグループ名
+}
+
+impl<'t> Captures<'_, 't> {
+    /// i 番目のグループのマッチした範囲
+    ///
+    /// グループが存在しない場合や、(a)|b の b や (a)?b の b にマッチした場合のように
+    /// グループがマッチに関与しなかった場合は None を返す。
+    /// スロットはバックトラックで試した分岐ごとに複製するため、失敗した分岐で保存した位置は残らない。
+    /// (?:(a)|b)+ のように繰り返すグループは、最後にマッチした範囲を返す
+    pub fn get(&self, i: usize) -> Option<Match<'t>> {
+        self.groups.get(i).copied().flatten()
+    }
+
+    /// 名前付きグループのマッチした範囲
+    pub fn name(&self, name: &str) -> Option<Match<'t>> {
+        let i = self.names.iter().position(|n| n.as_deref() == Some(name))?;
+        self.get(i)
+    }
+
+    /// テンプレート中のグループへの参照を、このキャプチャのマッチした文字列に展開して dst に追加
+    ///
+    /// 書式は Regex::replacen の置換文字列と同じ。
+    /// 存在しないグループやマッチに関与しなかったグループへの参照は空文字列に展開する
+    pub fn expand(&self, template: &str, dst: &mut String) {
+        for piece in pieces(template.chars()) {
+            match piece {
+                Piece::Char(c) => dst.push(c),
+                Piece::Text(text) => dst.push_str(&text),
+                Piece::Group(name) => {
+                    if let Some(m) = group_index(self.names, &name).and_then(|i| self.get(i)) {
+                        dst.push_str(m.as_str());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// マッチを置き換える文字列を生成するためのトレイト
+///
+/// &str と &String は置換文字列中の $1 や $name を展開し、NoExpand は展開しない
+pub trait Replacer {
+    /// caps のマッチを置き換える文字列を dst に追加
+    fn replace_append(&self, caps: &Captures, dst: &mut String);
+}
+
+impl Replacer for &str {
+    fn replace_append(&self, caps: &Captures, dst: &mut String) {
+        caps.expand(self, dst);
+    }
+}
+
+impl Replacer for &String {
+    fn replace_append(&self, caps: &Captures, dst: &mut String) {
+        caps.expand(self, dst);
+    }
+}
+
+/// $ を展開せず、そのままの文字列で置き換えるための型
+///
+/// 例えば NoExpand("$1") は、マッチを $1 という文字列に置き換える
+#[derive(Debug, Clone, Copy)]
+pub struct NoExpand<'a>(pub &'a str);
+
+impl Replacer for NoExpand<'_> {
+    fn replace_append(&self, _caps: &Captures, dst: &mut String) {
+        dst.push_str(self.0);
+    }
+}
+
+/// キャプチャグループのマッチした範囲を格納する、再利用可能なバッファ
+///
+/// Regex::capture_locations で生成し、Regex::captures_into に繰り返し渡すことで、
+/// マッチごとに Captures を確保せずにグループの範囲を得る
+#[derive(Debug, Clone)]
+pub struct CaptureLocations {
+    slots: Vec<Option<usize>>, // 2i 番目と 2i + 1 番目が i 番目のグループの開始位置と終了位置
+}
+
+impl CaptureLocations {
+    /// i 番目のグループのマッチした範囲
+    ///
+    /// 範囲の単位は Match と同じく、既定ではバイト単位、RegexBuilder::byte_offsets に偽を
+    /// 指定した場合は文字単位とする。
+    ///
+    /// グループが存在しない場合や、グループがマッチに関与しなかった場合は None を返す
+    pub fn get(&self, i: usize) -> Option<(usize, usize)> {
+        let slot = |n| self.slots.get(n).copied().flatten();
+        Some((slot(2 * i)?, slot(2 * i + 1)?))
+    }
+
+    /// マッチ全体を表す 0 番目を含めた、グループの数を返す
+    pub fn len(&self) -> usize {
+        self.slots.len() / 2
+    }
+
+    /// グループが1つもないかを判定
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+}
+
+/// 入力文字列中の、互いに重ならないマッチを左から順に探索する状態
+///
+/// Matches, CaptureMatches, Split が共有する
+#[derive(Debug)]
+struct Searcher<'r, 't> {
+    regex: &'r Regex,
+    chars: Vec<char>,
+    boundaries: Option<Vec<bool>>,
+    hay: Haystack<'t>,
+    from: Option<usize>,     // 次に探索を始める位置。None の場合は探索を終えた
+    last_end: Option<usize>, // 直前のマッチの終了位置
+}
+
+impl<'r, 't> Searcher<'r, 't> {
+    fn new(regex: &'r Regex, line: &'t str) -> Self {
+        let (chars, boundaries) = regex.to_units(line);
+        Searcher {
+            regex,
+            chars,
+            boundaries,
+            hay: Haystack::new(line, regex.byte_offsets),
+            from: Some(0),
+            last_end: None,
+        }
+    }
+
+    /// 次のマッチの開始位置と評価結果を返す
+    fn next_match(&mut self) -> Option<(usize, evaluator::Matched)> {
+        // \G は直前のマッチの終了位置にマッチする
+        let input = evaluator::Input {
+            chars: &self.chars,
+            boundaries: self.boundaries.as_deref(),
+            scan_start: self.last_end.unwrap_or(0),
+            window_start: 0,
+            max_len: self.regex.max_match_len,
+        };
+        loop {
+            let (start, m) = self.regex.search_at(input, self.from?)?;
+            self.from = next_start(input, (start, m.end));
+            if !is_adjacent_empty((start, m.end), self.last_end) {
+                self.last_end = Some(m.end);
+                return Some((start, m));
+            }
+        }
+    }
+}
+
+/// Regex::find_iter が返す、互いに重ならないマッチのイテレータ
+///
+/// 'r は正規表現の、't は入力文字列のライフタイム
+#[derive(Debug)]
+pub struct Matches<'r, 't>(Searcher<'r, 't>);
+
+impl<'t> Iterator for Matches<'_, 't> {
+    type Item = Match<'t>;
+
+    fn next(&mut self) -> Option<Match<'t>> {
+        let (start, m) = self.0.next_match()?;
+        Some(self.0.hay.new_match(start, m.end, m.id))
+    }
+}
+
+/// Regex::captures_iter が返す、互いに重ならないマッチのキャプチャグループのイテレータ
+#[derive(Debug)]
+pub struct CaptureMatches<'r, 't>(Searcher<'r, 't>);
+
+impl<'r, 't> Iterator for CaptureMatches<'r, 't> {
+    type Item = Captures<'r, 't>;
+
+    fn next(&mut self) -> Option<Captures<'r, 't>> {
+        let (start, m) = self.0.next_match()?;
+        Some(self.0.regex.to_captures(&self.0.hay, start, m))
+    }
+}
+
+/// Regex::split が返す、マッチの間の部分文字列のイテレータ
+#[derive(Debug)]
+pub struct Split<'r, 't> {
+    matches: Matches<'r, 't>,
+    last: Option<usize>, // 次の部分文字列の開始位置。None の場合は最後の部分文字列を返した
+}
+
+impl<'t> Iterator for Split<'_, 't> {
+    type Item = &'t str;
+
+    fn next(&mut self) -> Option<&'t str> {
+        let last = self.last?;
+        if let Some(m) = self.matches.next() {
+            self.last = Some(m.end);
+            Some(self.matches.0.hay.slice(last, m.start))
+        } else {
+            self.last = None;
+            Some(self.matches.0.hay.rest(last))
+        }
+    }
+}
+
+/// コンパイル済みの正規表現
+///
+/// 既定では最左最初 (Perl 互換) のマッチを返す。
+/// RegexBuilder::longest_match を指定すると最左最長 (POSIX 互換) のマッチを返し、
+/// 例えば a|ab|abc は abc に対して Perl 互換では a に、POSIX 互換では abc にマッチする。
+///
+/// unicode フィーチャを有効にして RegexBuilder::grapheme を指定すると、. とマッチの開始位置を
+/// 書記素クラスタ単位で扱う。ただし文字や文字クラスとの比較は従来通り char 単位で行うため、
+/// [é] は e と結合文字の2文字からなる é にはマッチしない。
+///
+/// (?(n)yes|no) は n 番目のグループがマッチした場合に yes を、そうでない場合に no をマッチする。
+/// 条件分岐は深さ優先探索でのみ評価できる。is_full_match は条件分岐を含む場合に深さ優先探索で判定するが、
+/// 幅優先探索のみを用いる find_overlapping と is_match_reader では EvalError::UnsupportedConditional となる。
+/// find_overlapping はマッチしないものとして扱い、is_match_reader はそのエラーを io::Error として返す。
+///
+/// Regex は Send + Sync であり、コンパイルした正規表現を複数のスレッドで共有できる。
+/// 評価中の状態はマッチングの呼び出しごとに確保し、Regex 自体は変更しない。
+#[derive(Clone)]
+pub struct Regex {
+    pattern: String,                     // コンパイル元の正規表現
+    ast: parser::AST,                    // パースした抽象構文木
+    program: Program,                    // コード生成した命令列とキャプチャグループの名前
+    reverse: Option<Vec<Instruction>>,   // 逆順の入力にマッチする命令列。生成できない場合は None
+    literals: Option<literal::Literals>, // 文字列の選択のみからなるパターンのトライ木
+    first_char: Option<char>, // 評価を始める位置に必ずある文字。開始位置の候補の探索に用いる
+    engine: Engine,           // 評価器の種類
+    longest: bool,            // 最左最長マッチを行うか
+    step_limit: Option<usize>, // 1回の探索で実行できる命令の数の上限
+    alphabet: Option<Vec<char>>, // DFA の入力となり得る文字。None の場合はすべての文字
+    max_dfa_states: usize,    // DFA の状態の数の上限
+    dfa: Option<Dfa>,         // Engine::Auto の is_match で用いる DFA。生成できない場合は None
+    max_match_len: Option<usize>, // 1つのマッチが消費できる文字数の上限
+    byte_offsets: bool,       // マッチの位置をバイト単位で報告するか
+    #[cfg(feature = "unicode")]
+    grapheme: bool, // 書記素クラスタ単位でマッチするか
+}
+
+impl Regex {
+    /// 正規表現をコンパイル
+    pub fn new(expr: &str) -> Result<Regex, RegexError> {
+        RegexBuilder::new(expr).build()
+    }
+
+    /// 最近コンパイルした正規表現のキャッシュを用いてコンパイル
+    ///
+    /// 同じ正規表現を繰り返しコンパイルする場合に、以前のコンパイル結果の複製を返す。
+    /// キャッシュはすべてのスレッドで共有され、Mutex により排他制御する。
+    /// 保持するのは最近使用した 16 個までで、それを超えると最も長く使用されていないものを削除する。
+    #[cfg(feature = "std")]
+    pub fn new_cached(expr: &str) -> Result<Regex, RegexError> {
+        cache::get_or_compile(expr)
+    }
+
+    /// 部分集合構成法で DFA に変換
+    ///
+    /// DFA はキャプチャグループや位置を報告せず、入力のいずれかの位置にマッチするかのみを判定する。
+    /// RegexBuilder::alphabet を指定した場合は、その文字のみを入力とする DFA を生成する。
+    /// 後読み、条件分岐、\b、(?m) の ^ と $ を含む場合や、書記素クラスタ単位の場合、
+    /// マッチの長さに上限がある場合は RegexError::DfaUnsupported となる。
+    /// 状態の数が RegexBuilder::max_dfa_states を超える場合は RegexError::DfaTooLarge となる
+    pub fn compile_dfa(&self) -> Result<Dfa, RegexError> {
+        #[cfg(feature = "unicode")]
+        if self.grapheme {
+            return Err(RegexError::DfaUnsupported("grapheme"));
+        }
+        if self.max_match_len.is_some() {
+            return Err(RegexError::DfaUnsupported("max_match_len"));
+        }
+        Dfa::new(&self.ast, self.alphabet.as_deref(), self.max_dfa_states)
+    }
+
+    /// Engine::Auto の場合に、is_match で用いる DFA を生成
+    ///
+    /// compile_dfa と異なり、変換できない場合はエラーとせずに DFA を用いない
+    fn with_auto_dfa(mut self) -> Regex {
+        if self.engine == Engine::Auto {
+            self.dfa = self.compile_dfa().ok();
+        }
+        self
+    }
+
+    /// is_match で用いる DFA を返す
+    ///
+    /// DFA は alphabet にない文字にマッチしないため、そのような文字を含む入力では None とする
+    fn dfa_for(&self, line: &str) -> Option<&Dfa> {
+        let dfa = self.dfa.as_ref()?;
+        match &self.alphabet {
+            Some(alphabet) if !line.chars().all(|c| alphabet.contains(&c)) => None,
+            _ => Some(dfa),
+        }
+    }
+
+    /// パターンをパースし、エラーではないが誤りや冗長である可能性が高い構文を返す
+    ///
+    /// a** のような限量子の連続、a|a のような同じ選択肢、[\s\S] のような
+    /// 任意の1文字にマッチする文字クラス、() のような空のグループを、出現順に報告する。
+    /// 限量子の連続はエラーとせずに報告し、それ以外のパースに失敗する場合は空とする
+    pub fn lint(pattern: &str) -> Vec<Lint> {
+        let flags = parser::Flags {
+            allow_nested_quantifier: true,
+            ..Default::default()
+        };
+        match parser::parse(pattern, flags) {
+            Ok(parsed) => parsed.lints,
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// コンパイル元の正規表現を返す
+    ///
+    /// 正規表現は Regex ごとに複製して保持するため、パターンの長さ分のメモリを余分に使う。
+    /// concat や union で結合した正規表現では、(?:a)(?:b) のように結合後のパターンを返す
+    pub fn as_pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// コード生成した命令列を返す
+    ///
+    /// 入力中のマッチの探索を行わずに、命令列を直接評価する場合に用いる
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// 命令列の長さを返す
+    pub fn program_len(&self) -> usize {
+        self.program.len()
+    }
+
+    /// マッチ全体を表す 0 番目を含めた、キャプチャグループの数を返す
+    pub fn captures_len(&self) -> usize {
+        self.program.captures_len()
+    }
+
+    /// キャプチャグループの名前をグループ番号順に返す
+    ///
+    /// 0 番目のグループと、名前のないグループは None となる
+    pub fn capture_names(&self) -> impl Iterator<Item = Option<&str>> {
+        self.program.capture_names()
+    }
+
+    /// 空文字列にマッチし得るかを判定
+    pub fn matches_empty(&self) -> bool {
+        self.ast.is_nullable()
+    }
+
+    /// パターン中で最も多くの選択肢を持つ | の、選択肢の数を返す
+    ///
+    /// 例えば a|b|c|d は 4、(a|b)c|d は 2 となり、| を含まない場合は 1 となる。
+    /// 選択肢が非常に多い場合は、文字クラスや RegexSet で表した方がよいことが多い
+    pub fn max_alternation_width(&self) -> usize {
+        let mut width = 1;
+        visit::walk(&self.ast, &mut |e| width = width.max(e.or_width()));
+        width
+    }
+
+    /// パターンが ^ から始まり、入力の先頭にのみマッチするかを判定
+    ///
+    /// 偽の場合は、入力の各位置からマッチを試す必要がある
+    pub fn is_anchored_start(&self) -> bool {
+        self.program.is_anchored_start()
+    }
+
+    /// パターンが $ で終わり、入力の末尾でのみマッチが終わるかを判定
+    pub fn is_anchored_end(&self) -> bool {
+        self.program.is_anchored_end()
+    }
+
+    /// マッチが必ず先頭に含む文字列と、パターンが ^ から始まるかを返す
+    ///
+    /// 例えば foo.* は ("foo", false)、^bar は ("bar", true) となり、
+    /// a*b や (?i)foo のように先頭の文字列が決まらない場合は None となる。
+    /// str::find などで候補の位置を先に探すために使える
+    pub fn literal_prefix(&self) -> Option<(String, bool)> {
+        let mut prefix = String::new();
+        self.ast.literal_prefix(&mut prefix);
+        if prefix.is_empty() {
+            None
+        } else {
+            // \K を含む場合は、入力の先頭から始まるマッチでも開始位置が先頭とは限らない
+            let anchored = self.ast.is_anchored_start() && !self.ast.has_reset_start();
+            Some((prefix, anchored))
+        }
+    }
+
+    /// 命令列を Graphviz の DOT 形式の有向グラフとして出力
+    pub fn to_dot(&self) -> String {
+        dot::to_dot(&self.program.insts)
+    }
+
+    /// 命令列を、1行に1命令のテキストとして出力
+    ///
+    /// 各行は 4 桁に 0 埋めしたアドレスと命令からなり、アドレスの昇順に並ぶ。
+    /// 名前付きグループがある場合は、その前にグループ番号の昇順に名前を出力する。
+    /// 同じパターンとオプションからは常に同じ文字列を生成する。
+    pub fn disassemble(&self) -> String {
+        use std::fmt::Write;
+
+        let mut text = String::new();
+        for (i, name) in self.program.names.iter().enumerate() {
+            if let Some(name) = name {
+                let _ = writeln!(text, "; group {i}: {name}");
+            }
+        }
+        for (pc, inst) in self.program.insts.iter().enumerate() {
+            let _ = writeln!(text, "{pc:>04}: {inst}");
+        }
+        text
+    }
+
+    /// この正規表現にマッチした直後に、other にマッチする正規表現を生成
+    ///
+    /// パターンを再度パースせず、2つの命令列を連結する。
+    /// other のキャプチャグループは、この正規表現のグループに続く番号に付け替える。
+    /// 同じ名前のグループがある場合、Captures::name ではこの正規表現のグループを返す。
+    /// 評価器の種類などのオプションは、この正規表現のものを用いる。
+    pub fn concat(&self, other: &Regex) -> Result<Regex, CodeGenError> {
+        let program = self.program.concat(&other.program)?;
+
+        let mut second = other.ast.clone();
+        second.shift_captures(self.captures_len() - 1);
+        let ast = parser::AST::Seq(vec![self.ast.clone(), second]);
+
+        Ok(Regex {
+            pattern: format!("(?:{})(?:{})", self.pattern, other.pattern),
+            ast,
+            program,
+            reverse: None, // パターンの番号を保つため、逆順の命令列は生成しない
+            literals: None,
+            first_char: self.first_char,
+            engine: self.engine_with(other),
+            longest: self.longest,
+            step_limit: self.step_limit,
+            alphabet: self.alphabet.clone(),
+            max_dfa_states: self.max_dfa_states,
+            dfa: None,
+            max_match_len: self.max_match_len,
+            byte_offsets: self.byte_offsets,
+            #[cfg(feature = "unicode")]
+            grapheme: self.grapheme,
+        }
+        .with_auto_dfa())
+    }
+
+    /// この正規表現と other のいずれかにマッチする正規表現を生成
+    ///
+    /// パターンを再度パースせず、2つの命令列を split で結合する。
+    /// 両方にマッチする場合は、この正規表現を優先する。
+    /// Match::pattern は、この正規表現にマッチした場合は 0 から、
+    /// other にマッチした場合はこの正規表現のパターンの数から始まる番号となる。
+    /// キャプチャグループやオプションの扱いは Regex::concat と同じ。
+    pub fn union(&self, other: &Regex) -> Result<Regex, CodeGenError> {
+        let program = self.program.union(&other.program)?;
+
+        let mut second = other.ast.clone();
+        second.shift_captures(self.captures_len() - 1);
+        let ast = parser::AST::Or(Box::new(self.ast.clone()), Box::new(second));
+
+        Ok(Regex {
+            pattern: format!("(?:{})|(?:{})", self.pattern, other.pattern),
+            ast,
+            program,
+            reverse: None, // パターンの番号を保つため、逆順の命令列は生成しない
+            literals: None,
+            first_char: self.first_char.filter(|c| other.first_char == Some(*c)),
+            engine: self.engine_with(other),
+            longest: self.longest,
+            step_limit: self.step_limit,
+            alphabet: self.alphabet.clone(),
+            max_dfa_states: self.max_dfa_states,
+            dfa: None,
+            max_match_len: self.max_match_len,
+            byte_offsets: self.byte_offsets,
+            #[cfg(feature = "unicode")]
+            grapheme: self.grapheme,
+        }
+        .with_auto_dfa())
+    }
+
+    /// concat と union で生成する正規表現の評価器の種類
+    ///
+    /// other が条件分岐を含む場合は、深さ優先探索とする
+    fn engine_with(&self, other: &Regex) -> Engine {
+        if other.ast.has_conditional() {
+            Engine::DepthFirst
+        } else {
+            self.engine
+        }
+    }
+
+    /// 入力文字列を、評価器に与える文字の列と書記素クラスタの境界に変換
+    ///
+    /// 書記素クラスタ単位でマッチしない場合、境界は None
+    fn to_units(&self, line: &str) -> (Vec<char>, Option<Vec<bool>>) {
+        let chars = line.chars().collect();
+        #[cfg(feature = "unicode")]
+        if self.grapheme {
+            return (chars, Some(grapheme_boundaries(line)));
+        }
+        (chars, None)
+    }
+
+    /// 入力文字列のいずれかの位置にマッチするかを判定
+    ///
+    /// 空文字列に対しては開始位置 0 のみを試すため、^$ や a* のように
+    /// 空文字列にマッチするパターンは真、^a や a+ は偽となる。
+    /// 評価中にエラーが起きた場合は偽とする。エラーを区別する場合は try_is_match を用いる。
+    /// Engine::Auto で DFA を生成できた場合は DFA で判定し、実行する命令の数の上限は適用しない
+    pub fn is_match(&self, line: &str) -> bool {
+        if let Some(dfa) = self.dfa_for(line) {
+            return dfa.is_match(line);
+        }
+        self.find(line).is_some()
+    }
+
+    /// is_match と同じく判定し、評価中のエラーを返す
+    ///
+    /// RegexBuilder::step_limit で指定した上限を超えた場合は EvalError::StepLimitExceeded を返す。
+    /// 幅優先探索で、入れ子の {n,m} のカウンタの状態が多すぎる場合は EvalError::TooManyStates を返す
+    pub fn try_is_match(&self, line: &str) -> Result<bool, EvalError> {
+        Ok(self.try_find(line)?.is_some())
+    }
+
+    /// 入力文字列全体にマッチするかを判定
+    ///
+    /// パターンを \A(?:...)\z で囲んだ場合と同じく、先頭から始まり末尾で終わるマッチがあれば真
+    ///
+    /// 条件分岐を含む場合は、深さ優先探索ですべての選択肢を試し、最も長いマッチで判定する。
+    /// このとき RegexBuilder::step_limit の上限を超えた場合は偽とする
+    pub fn is_full_match(&self, line: &str) -> bool {
+        let (chars, boundaries) = self.to_units(line);
+        let input = evaluator::Input {
+            chars: &chars,
+            boundaries: boundaries.as_deref(),
+            scan_start: 0,
+            window_start: 0,
+            max_len: self.max_match_len,
+        };
+        if self.ast.has_conditional() {
+            let mut budget = evaluator::Budget::new(self.step_limit);
+            return match evaluator::eval(&self.program.insts, input, 0, true, true, &mut budget) {
+                Ok(m) => m.is_some_and(|m| m.end == chars.len()),
+                Err(_) => false,
+            };
+        }
+        match evaluator::eval_all(&self.program.insts, input, 0) {
+            Ok(ends) => ends.last().is_some_and(|(end, _)| *end == chars.len()),
+            Err(_) => false,
+        }
+    }
+
+    /// Read から読み込んだ入力のいずれかの位置にマッチするかを判定
+    ///
+    /// 入力全体をメモリに読み込まず、UTF-8 としてデコードした文字を幅優先探索の評価器に
+    /// 順に与えて判定する。RegexBuilder::engine の指定に関わらず幅優先探索で評価する。
+    /// 入力が UTF-8 として正しくない場合は ErrorKind::InvalidData のエラーを返す。
+    /// 書記素クラスタ単位でマッチする場合や、RegexBuilder::max_match_len を指定した場合は、
+    /// ErrorKind::Unsupported のエラーを返す。
+    /// 過去の入力を保持しないため、後読みを含む場合はエラーを返す。
+    #[cfg(feature = "std")]
+    pub fn is_match_reader<R: Read>(&self, reader: R) -> io::Result<bool> {
+        if self.max_match_len.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "max_match_len is not supported for streaming input",
+            ));
+        }
+        #[cfg(feature = "unicode")]
+        if self.grapheme {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "grapheme mode is not supported for streaming input",
+            ));
+        }
+
+        let mut read_err = None;
+        let chars = stream::Utf8Chars::new(reader).map_while(|c| match c {
+            Ok(c) => Some(c),
+            Err(e) => {
+                read_err = Some(e);
+                None
+            }
+        });
+
+        let result = evaluator::eval_stream(&self.program.insts, chars);
+        if let Some(e) = read_err {
+            return Err(e);
+        }
+        result.map_err(io::Error::other)
+    }
+
+    /// 入力文字列中で最も左にあるマッチを返す
+    ///
+    /// 評価中にエラーが起きた場合は、マッチしなかったものとして扱う
+    pub fn find<'t>(&self, line: &'t str) -> Option<Match<'t>> {
+        let (chars, boundaries) = self.to_units(line);
+        let input = evaluator::Input {
+            chars: &chars,
+            boundaries: boundaries.as_deref(),
+            scan_start: 0,
+            window_start: 0,
+            max_len: self.max_match_len,
+        };
+        self.find_at(input, &Haystack::new(line, self.byte_offsets), 0)
+    }
+
+    /// find と同じマッチを返し、評価中のエラーを返す
+    pub fn try_find<'t>(&self, line: &'t str) -> Result<Option<Match<'t>>, EvalError> {
+        let (chars, boundaries) = self.to_units(line);
+        let input = evaluator::Input {
+            chars: &chars,
+            boundaries: boundaries.as_deref(),
+            scan_start: 0,
+            window_start: 0,
+            max_len: self.max_match_len,
+        };
+        let hay = Haystack::new(line, self.byte_offsets);
+        let found = self.try_search_at(input, 0)?;
+        Ok(found.map(|(start, m)| hay.new_match(start, m.end, m.id)))
+    }
+
+    /// 入力の from 以降の位置から始まる、最も左にあるマッチを返す
+    fn find_at<'t>(
+        &self,
+        input: evaluator::Input,
+        hay: &Haystack<'t>,
+        from: usize,
+    ) -> Option<Match<'t>> {
+        self.search_at(input, from)
+            .map(|(start, m)| hay.new_match(start, m.end, m.id))
+    }
+
+    /// 入力文字列の range の範囲で最も左にあるマッチを返す
+    ///
+    /// range とマッチの位置の単位は Match と同じで、マッチの位置は入力文字列全体の先頭から数える。
+    /// マッチは range の中に収まるものに限り、^ は range.start に、$ は range.end にのみマッチする。
+    /// \G も range.start にマッチする。後読みは range.start より前の文字も参照するため、
+    /// (?<=b)a は bab の 1..2 の範囲でマッチする。range.end より後の文字は参照しない。
+    /// range が入力の範囲外の場合や、始点が終点より後ろの場合、
+    /// バイト単位で文字の境界でない場合は None を返す
+    pub fn find_in_window<'t>(&self, line: &'t str, range: Range<usize>) -> Option<Match<'t>> {
+        let hay = Haystack::new(line, self.byte_offsets);
+        let start = hay.index(range.start)?;
+        let end = hay.index(range.end)?;
+        if start > end {
+            return None;
+        }
+
+        // range.end より後を除き、$ が range.end にマッチするようにする
+        let (chars, boundaries) = self.to_units(line);
+        let input = evaluator::Input {
+            chars: &chars[..end],
+            boundaries: boundaries.as_deref(),
+            scan_start: start,
+            window_start: start,
+            max_len: self.max_match_len,
+        };
+        self.find_at(input, &hay, start)
+    }
+
+    /// find と同じマッチを返し、評価中に実行したすべての命令を実行順に記録する
+    ///
+    /// バックトラックの様子を確認するため、評価器の指定によらず深さ優先探索で評価する。
+    /// 各開始位置での評価を順に記録し、マッチした開始位置までの命令を返す。
+    /// 記録のための処理は find などの通常の評価には含まれない。
+    pub fn find_traced<'t>(&self, line: &'t str) -> (Option<Match<'t>>, Vec<TraceEvent>) {
+        let (chars, boundaries) = self.to_units(line);
+        let input = evaluator::Input {
+            chars: &chars,
+            boundaries: boundaries.as_deref(),
+            scan_start: 0,
+            window_start: 0,
+            max_len: self.max_match_len,
+        };
+        let hay = Haystack::new(line, self.byte_offsets);
+
+        let mut events = Vec::new();
+        let mut trace = |pc: usize, sp: usize| {
+            events.push(TraceEvent {
+                pc,
+                sp,
+                instruction: self.program.insts[pc].to_string(),
+            });
+        };
+
+        let mut found = None;
+        let mut budget = evaluator::Budget::new(self.step_limit);
+        for start in (0..=chars.len()).filter(|sp| input.is_boundary(*sp)) {
+            match evaluator::eval_traced(
+                &self.program.insts,
+                input,
+                start,
+                self.longest,
+                &mut budget,
+                &mut trace,
+            ) {
+                Ok(Some(m)) => {
+                    found = Some(hay.new_match(reset_start(start, &m), m.end, m.id));
+                    break;
+                }
+                Ok(None) => (),
+                Err(_) => break,
+            }
+        }
+
+        (found, events)
+    }
+
+    /// 入力の from 以降の位置から始まる、最も左にあるマッチの開始位置と評価結果を返す
+    ///
+    /// 評価中にエラーが起きた場合は、マッチしなかったものとして扱う
+    fn search_at(
+        &self,
+        input: evaluator::Input,
+        from: usize,
+    ) -> Option<(usize, evaluator::Matched)> {
+        self.try_search_at(input, from).ok().flatten()
+    }
+
+    /// search_at と同じく探索し、評価中のエラーを返す
+    ///
+    /// 実行できる命令の数の上限は、すべての開始位置での評価の合計に対して適用する。
+    /// foo|bar|baz のような文字列の選択のみからなるパターンは、命令列を評価せずに
+    /// トライ木で探索するため、実行できる命令の数の上限は適用しない
+    fn try_search_at(
+        &self,
+        input: evaluator::Input,
+        from: usize,
+    ) -> Result<Option<(usize, evaluator::Matched)>, EvalError> {
+        if let Some(literals) = &self.literals {
+            let found = literals.find_at(input.chars, from, self.longest);
+            return Ok(found.map(|(start, end)| {
+                let m = evaluator::Matched {
+                    end,
+                    id: 0,
+                    slots: Vec::new(),
+                };
+                (start, m)
+            }));
+        }
+
+        let insts = &self.program.insts;
+        let is_depth = self.engine == Engine::DepthFirst;
+        let mut budget = evaluator::Budget::new(self.step_limit);
+
+        // 先頭の文字が決まっている場合は、その文字の位置のみを開始位置の候補とする
+        if let Some(c) = self.first_char {
+            let mut from = from;
+            while let Some(i) = input
+                .chars
+                .get(from..)
+                .and_then(|rest| scan::find_char(rest, c))
+            {
+                let start = from + i;
+                if input.is_boundary(start) {
+                    let m =
+                        evaluator::eval(insts, input, start, is_depth, self.longest, &mut budget)?;
+                    if let Some(m) = m {
+                        return Ok(Some((reset_start(start, &m), m)));
+                    }
+                }
+                from = start + 1;
+            }
+            return Ok(None);
+        }
+
+        // 開始位置を1文字ずつずらしながらマッチングを行う
+        // 空文字列にマッチするパターンのため、末尾の位置も試す
+        // 書記素クラスタ単位の場合、クラスタの途中からは開始しない
+        for start in (from..=input.chars.len()).filter(|sp| input.is_boundary(*sp)) {
+            if let Some(m) =
+                evaluator::eval(insts, input, start, is_depth, self.longest, &mut budget)?
+            {
+                return Ok(Some((reset_start(start, &m), m)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// 入力文字列中で終了位置が最も右にあるマッチを返す
+    ///
+    /// 終了位置が同じマッチが複数ある場合は、最も長いものを返す。
+    /// 逆順の入力にマッチする命令列を用いて、入力の末尾から先頭に向かって探索する。
+    /// 後読みを含む場合や、concat, union で生成した正規表現では、逆順の命令列を生成しないため、
+    /// 重なりを許したすべてのマッチから選ぶ。\G や \K を含む場合も同様で、\K は考慮しない。
+    pub fn find_last<'t>(&self, line: &'t str) -> Option<Match<'t>> {
+        let reverse = if let Some(reverse) = &self.reverse {
+            reverse
+        } else {
+            return self
+                .find_overlapping(line)
+                .max_by_key(|m| (m.end, std::cmp::Reverse(m.start)));
+        };
+
+        let (mut chars, mut boundaries) = self.to_units(line);
+        chars.reverse();
+        if let Some(b) = boundaries.as_mut() {
+            b.reverse();
+        }
+        let input = evaluator::Input {
+            chars: &chars,
+            boundaries: boundaries.as_deref(),
+            scan_start: 0,
+            window_start: 0,
+            max_len: self.max_match_len,
+        };
+        let hay = Haystack::new(line, self.byte_offsets);
+        let is_depth = self.engine == Engine::DepthFirst;
+        let mut budget = evaluator::Budget::new(self.step_limit);
+
+        // 逆順の入力での最左最長のマッチが、元の入力での終了位置が最も右で最長のマッチとなる
+        let len = chars.len();
+        for start in (0..=len).filter(|sp| input.is_boundary(*sp)) {
+            match evaluator::eval(reverse, input, start, is_depth, true, &mut budget) {
+                Ok(Some(m)) => return Some(hay.new_match(len - m.end, len - start, m.id)),
+                Ok(None) => (),
+                Err(_) => return None,
+            }
+        }
+
+        None
+    }
+
+    /// 入力文字列中で最も左にあるマッチについて、各キャプチャグループのマッチした範囲を返す
+    ///
+    /// グループが繰り返しの中にある場合は、最後にマッチした範囲となる
+    pub fn captures<'t>(&self, line: &'t str) -> Option<Captures<'_, 't>> {
+        let (chars, boundaries) = self.to_units(line);
+        let input = evaluator::Input {
+            chars: &chars,
+            boundaries: boundaries.as_deref(),
+            scan_start: 0,
+            window_start: 0,
+            max_len: self.max_match_len,
+        };
+        self.captures_from(input, &Haystack::new(line, self.byte_offsets), 0)
+    }
+
+    /// 入力文字列の start 以降の位置から始まる、最も左にあるマッチのキャプチャグループを返す
+    ///
+    /// start と各グループの位置の単位は Match と同じで、位置は入力文字列全体の先頭から数える。
+    /// ^ は start ではなく入力の先頭にのみマッチし、後読みは start より前の文字も参照する。
+    /// \G は start にマッチするため、\G から始まるパターンで入力を先頭から順に読み進められる。
+    /// start が入力の範囲外の場合や、バイト単位で文字の境界でない場合は None を返す
+    pub fn captures_at<'t>(&self, line: &'t str, start: usize) -> Option<Captures<'_, 't>> {
+        let hay = Haystack::new(line, self.byte_offsets);
+        let from = hay.index(start)?;
+        let (chars, boundaries) = self.to_units(line);
+        let input = evaluator::Input {
+            chars: &chars,
+            boundaries: boundaries.as_deref(),
+            scan_start: from,
+            window_start: 0,
+            max_len: self.max_match_len,
+        };
+        self.captures_from(input, &hay, from)
+    }
+
+    /// captures と同じキャプチャグループを返し、評価中のエラーを返す
+    pub fn try_captures<'t>(&self, line: &'t str) -> Result<Option<Captures<'_, 't>>, EvalError> {
+        let (chars, boundaries) = self.to_units(line);
+        let input = evaluator::Input {
+            chars: &chars,
+            boundaries: boundaries.as_deref(),
+            scan_start: 0,
+            window_start: 0,
+            max_len: self.max_match_len,
+        };
+        let hay = Haystack::new(line, self.byte_offsets);
+        let found = self.try_search_at(input, 0)?;
+        Ok(found.map(|(start, m)| self.to_captures(&hay, start, m)))
+    }
+
+    /// captures_into に渡すための、この正規表現のグループ数の大きさのバッファを生成
+    pub fn capture_locations(&self) -> CaptureLocations {
+        CaptureLocations {
+            slots: vec![None; self.program.names.len() * 2],
+        }
+    }
+
+    /// 入力文字列中で最も左にあるマッチについて、各キャプチャグループの範囲を buf に格納
+    ///
+    /// マッチした場合は真を返す。マッチしなかった場合は偽を返し、buf のすべてのグループを None とする。
+    /// buf は以前の内容を上書きして再利用するため、繰り返し呼び出しても新たに確保しない
+    pub fn captures_into(&self, line: &str, buf: &mut CaptureLocations) -> bool {
+        let (chars, boundaries) = self.to_units(line);
+        let input = evaluator::Input {
+            chars: &chars,
+            boundaries: boundaries.as_deref(),
+            scan_start: 0,
+            window_start: 0,
+            max_len: self.max_match_len,
+        };
+
+        buf.slots.clear();
+        buf.slots.resize(self.program.names.len() * 2, None);
+        if let Some((start, m)) = self.search_at(input, 0) {
+            for (dst, src) in buf.slots.iter_mut().zip(&m.slots).skip(2) {
+                *dst = *src;
+            }
+            buf.slots[0] = Some(start);
+            buf.slots[1] = Some(m.end);
+            if self.byte_offsets {
+                let hay = Haystack::new(line, true);
+                for slot in buf.slots.iter_mut() {
+                    *slot = slot.map(|i| hay.offset(i));
+                }
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    /// 入力の from 以降の位置から始まる、最も左にあるマッチのキャプチャグループを返す
+    fn captures_from<'t>(
+        &self,
+        input: evaluator::Input,
+        hay: &Haystack<'t>,
+        from: usize,
+    ) -> Option<Captures<'_, 't>> {
+        let (start, m) = self.search_at(input, from)?;
+        Some(self.to_captures(hay, start, m))
+    }
+
+    /// start から始まる評価結果 m のスロットから、キャプチャグループを生成
+    fn to_captures<'t>(
+        &self,
+        hay: &Haystack<'t>,
+        start: usize,
+        m: evaluator::Matched,
+    ) -> Captures<'_, 't> {
+        let mut groups = vec![Some(hay.new_match(start, m.end, m.id))];
+        for i in 1..self.program.names.len() {
+            let slot = |n| m.slots.get(n).copied().flatten();
+            let group = match (slot(2 * i), slot(2 * i + 1)) {
+                (Some(start), Some(end)) => Some(hay.new_match(start, end, m.id)),
+                _ => None,
+            };
+            groups.push(group);
+        }
+
+        Captures {
+            groups,
+            names: &self.program.names,
+        }
+    }
+
+    /// 入力文字列中の、互いに重ならないすべてのマッチを左から順に返す
+    ///
+    /// マッチの終了位置から次のマッチを探索する。
+    /// 空文字列にマッチした場合は、同じ位置で繰り返しマッチしないよう次の文字から探索する。
+    /// regex クレートと同じく、直前のマッチの終了位置での空文字列へのマッチは返さないため、
+    /// 例えば a* は bab に対して 0..0, 1..2, 3..3 にマッチする。
+    ///
+    /// パターン中の \G は、直前のマッチの終了位置 (最初のマッチでは入力の先頭) にのみマッチする。
+    /// 常に入力の先頭にのみマッチする ^ と異なり、\G から始まるパターンは直前のマッチに
+    /// 隙間なく続くマッチのみを返し、続かない位置で列挙を終えるため、字句解析に使える。
+    /// find や captures のように1回だけ探索する場合は、\G は入力の先頭にのみマッチする。
+    pub fn find_iter<'r, 't>(&'r self, line: &'t str) -> Matches<'r, 't> {
+        Matches(Searcher::new(self, line))
+    }
+
+    /// find_iter と同じマッチのキャプチャグループを、左から順に返す
+    pub fn captures_iter<'r, 't>(&'r self, line: &'t str) -> CaptureMatches<'r, 't> {
+        CaptureMatches(Searcher::new(self, line))
+    }
+
+    /// find_iter のマッチの位置で入力文字列を分割し、マッチの間の部分文字列を順に返す
+    ///
+    /// 先頭や末尾のマッチ、隣接するマッチの間では空文字列を返す。
+    /// 分割する回数を制限する場合は splitn を用いる
+    pub fn split<'r, 't>(&'r self, line: &'t str) -> Split<'r, 't> {
+        Split {
+            matches: self.find_iter(line),
+            last: Some(0),
         }
     }
+
+    /// find_iter と同じマッチを、入力の末尾から先頭に向かって順に返す
+    ///
+    /// 返すマッチは常に直前に返したマッチより左にある。
+    /// 右から探索し直すのではなく find_iter のマッチを逆順にするため、a{2} は aaa に対して
+    /// 1..3 ではなく 0..2 にマッチする。最初のマッチを返す前にすべてのマッチを集める
+    pub fn rfind_iter<'t>(&self, line: &'t str) -> impl Iterator<Item = Match<'t>> {
+        let matches: Vec<Match<'t>> = self.find_iter(line).collect();
+        matches.into_iter().rev()
+    }
+
+    /// 入力文字列中の、互いに重ならないマッチの数を返す
+    ///
+    /// find_iter が返すマッチを、集めずに数える。空文字列へのマッチの扱いは find_iter と同じ
+    pub fn count(&self, line: &str) -> usize {
+        self.find_iter(line).count()
+    }
+
+    /// 入力文字列を行に分割し、各行のマッチを行番号とともに返す
+    ///
+    /// 行番号は 1 から数え、マッチの位置は行の先頭からのインデックスとする。
+    /// 行末の \n と \r\n は行に含めず、入力の末尾の改行の後には空の行を数えない。
+    /// 各行は別の入力として評価するため、^ と $ は行の先頭と末尾にマッチする。
+    pub fn find_lines<'r, 't>(
+        &'r self,
+        text: &'t str,
+    ) -> impl Iterator<Item = (usize, Match<'t>)> + use<'r, 't> {
+        text.lines()
+            .enumerate()
+            .flat_map(move |(i, line)| self.find_iter(line).map(move |m| (i + 1, m)))
+    }
+
+    /// 入力文字列中の最も左にあるマッチを、置換文字列で置き換える
+    ///
+    /// 置換文字列の書式は replacen を参照
+    pub fn replace<'t, R: Replacer>(&self, line: &'t str, rep: R) -> Cow<'t, str> {
+        self.replacen(line, 1, rep)
+    }
+
+    /// 入力文字列中の、互いに重ならないすべてのマッチを置換文字列で置き換える
+    ///
+    /// 置換文字列の書式は replacen を参照
+    pub fn replace_all<'t, R: Replacer>(&self, line: &'t str, rep: R) -> Cow<'t, str> {
+        self.replacen(line, 0, rep)
+    }
+
+    /// 入力文字列の先頭から始まるマッチのみを、置換文字列で置き換える
+    ///
+    /// replace と異なり、先頭以外の位置からは探索しない。
+    /// a* のように空文字列にマッチするパターンは、先頭に置換文字列を挿入する。
+    /// \K で開始位置を移したマッチでは、\K より前の部分を残す。
+    /// 置換文字列の書式は replacen を参照
+    pub fn replace_leading<'t, R: Replacer>(&self, line: &'t str, rep: R) -> Cow<'t, str> {
+        let (chars, boundaries) = self.to_units(line);
+        let input = evaluator::Input {
+            chars: &chars,
+            boundaries: boundaries.as_deref(),
+            scan_start: 0,
+            window_start: 0,
+            max_len: self.max_match_len,
+        };
+        let hay = Haystack::new(line, self.byte_offsets);
+
+        let is_depth = self.engine == Engine::DepthFirst;
+        let mut budget = evaluator::Budget::new(self.step_limit);
+        let insts = &self.program.insts;
+        let m = match evaluator::eval(insts, input, 0, is_depth, self.longest, &mut budget) {
+            Ok(Some(m)) => m,
+            _ => return Cow::Borrowed(line),
+        };
+
+        let (start, end) = (reset_start(0, &m), m.end);
+        let caps = self.to_captures(&hay, start, m);
+        let mut result = hay.slice(0, start).to_string();
+        rep.replace_append(&caps, &mut result);
+        result.push_str(hay.rest(end));
+
+        Cow::Owned(result)
+    }
+
+    /// 入力文字列中のマッチを左から最大 n 個、置換文字列で置き換える
+    ///
+    /// n が 0 の場合はすべてのマッチを置き換える。
+    /// 置換文字列中の $1 や ${1} はグループ番号の、$name や ${name} は名前付きグループの
+    /// マッチした文字列に置き換え、$$ は $ とする。
+    /// $name の名前は英数字と _ の最長の並びとするため、直後に文字が続く場合は ${name} と書く。
+    /// 存在しないグループや、マッチに関与しなかったグループは空文字列に置き換える。
+    /// $ の後に参照が続かない場合や、閉じ波括弧がない ${name のような場合は、そのまま出力する。
+    /// 置換文字列を展開しない場合は NoExpand で囲む。
+    ///
+    /// マッチしなかった場合は新たな文字列を確保せず、入力文字列をそのまま借用して返す。
+    pub fn replacen<'t, R: Replacer>(&self, line: &'t str, n: usize, rep: R) -> Cow<'t, str> {
+        self.replacen_count(line, n, rep).0
+    }
+
+    /// replace_all と同じく置き換え、置き換えた後の文字列と置き換えたマッチの数を返す
+    pub fn replace_all_count<R: Replacer>(&self, line: &str, rep: R) -> (String, usize) {
+        let (result, count) = self.replacen_count(line, 0, rep);
+        (result.into_owned(), count)
+    }
+
+    /// replace_all と同じく置き換えた文字列を、全体を一度に確保せずに順に w に書き出す
+    ///
+    /// マッチの間の文字列はそのまま書き出し、置換文字列は展開してから書き出す。
+    /// 置き換えたマッチの数を返す。書き出しに失敗した場合は、そこで打ち切ってエラーを返す
+    #[cfg(feature = "std")]
+    pub fn replace_all_to<R: Replacer, W: Write>(
+        &self,
+        line: &str,
+        rep: R,
+        w: &mut W,
+    ) -> io::Result<usize> {
+        let mut buf = String::new();
+        let (count, rest) = self.replace_each(line, 0, |gap, caps| {
+            w.write_all(gap.as_bytes())?;
+            buf.clear();
+            rep.replace_append(caps, &mut buf);
+            w.write_all(buf.as_bytes())
+        })?;
+        w.write_all(rest.as_bytes())?;
+        Ok(count)
+    }
+
+    /// replacen と同じく置き換え、置き換えたマッチの数とともに返す
+    fn replacen_count<'t, R: Replacer>(
+        &self,
+        line: &'t str,
+        n: usize,
+        rep: R,
+    ) -> (Cow<'t, str>, usize) {
+        let mut result = String::new();
+        let replaced = self.replace_each(line, n, |gap, caps| {
+            result.push_str(gap);
+            rep.replace_append(caps, &mut result);
+            Ok::<(), Infallible>(())
+        });
+        let (count, rest) = match replaced {
+            Ok(replaced) => replaced,
+            Err(e) => match e {},
+        };
+        if count == 0 {
+            return (Cow::Borrowed(line), 0);
+        }
+        result.push_str(rest);
+
+        (Cow::Owned(result), count)
+    }
+
+    /// 入力文字列中の置き換えるマッチを左から最大 n 個 (0 の場合はすべて) 探し、
+    /// 直前のマッチとの間の文字列とマッチのキャプチャを順に f に渡す
+    ///
+    /// 置き換えたマッチの数と、最後のマッチより後の残りの文字列を返す。
+    /// f がエラーを返した場合は、そこで探索を打ち切る
+    fn replace_each<'t, E>(
+        &self,
+        line: &'t str,
+        n: usize,
+        mut f: impl FnMut(&str, &Captures) -> Result<(), E>,
+    ) -> Result<(usize, &'t str), E> {
+        let (chars, boundaries) = self.to_units(line);
+        let input = evaluator::Input {
+            chars: &chars,
+            boundaries: boundaries.as_deref(),
+            scan_start: 0,
+            window_start: 0,
+            max_len: self.max_match_len,
+        };
+        let hay = Haystack::new(line, self.byte_offsets);
+
+        let mut last = 0;
+        let mut last_end = None; // 直前のマッチの終了位置
+        let mut from = Some(0);
+        let mut count = 0;
+        while let Some(caps) = from.and_then(|from| {
+            // \G は直前のマッチの終了位置にマッチする
+            let input = evaluator::Input {
+                scan_start: last_end.unwrap_or(0),
+                ..input
+            };
+            self.captures_from(input, &hay, from)
+        }) {
+            if n != 0 && count >= n {
+                break;
+            }
+            let m = caps.get(0).unwrap(); // 0 番目のグループは常にマッチしている
+            from = next_start(input, (m.start, m.end));
+            if is_adjacent_empty((m.start, m.end), last_end) {
+                continue;
+            }
+            last_end = Some(m.end);
+            f(hay.slice(last, m.start), &caps)?;
+            last = m.end;
+            count += 1;
+        }
+
+        Ok((count, hay.rest(last)))
+    }
+
+    /// 文字の列中の最も左にあるマッチを、置換文字列で置き換える
+    ///
+    /// 書式と位置の扱いは replacen_chars を参照
+    pub fn replace_chars(&self, line: &[char], rep: &[char]) -> Vec<char> {
+        self.replacen_chars(line, 1, rep)
+    }
+
+    /// 文字の列中の、互いに重ならないすべてのマッチを置換文字列で置き換える
+    ///
+    /// 書式と位置の扱いは replacen_chars を参照
+    pub fn replace_all_chars(&self, line: &[char], rep: &[char]) -> Vec<char> {
+        self.replacen_chars(line, 0, rep)
+    }
+
+    /// 文字の列中のマッチを左から最大 n 個、置換文字列で置き換える
+    ///
+    /// replacen の &[char] 版で、String に変換せずに文字の列のまま評価する。
+    /// 置換文字列の書式は replacen と同じで、マッチとグループの位置は文字単位のインデックスとなる。
+    /// ただし書記素クラスタ単位の場合は、境界を求めるために一時的に String に変換する。
+    pub fn replacen_chars(&self, line: &[char], n: usize, rep: &[char]) -> Vec<char> {
+        #[cfg(feature = "unicode")]
+        let boundaries = self
+            .grapheme
+            .then(|| grapheme_boundaries(&line.iter().collect::<String>()));
+        #[cfg(not(feature = "unicode"))]
+        let boundaries: Option<Vec<bool>> = None;
+        let input = evaluator::Input {
+            chars: line,
+            boundaries: boundaries.as_deref(),
+            scan_start: 0,
+            window_start: 0,
+            max_len: self.max_match_len,
+        };
+
+        let mut result = Vec::new();
+        let mut last = 0;
+        let mut last_end = None; // 直前のマッチの終了位置
+        let mut from = Some(0);
+        let mut count = 0;
+        while let Some((start, m)) = from.and_then(|from| {
+            // \G は直前のマッチの終了位置にマッチする
+            let input = evaluator::Input {
+                scan_start: last_end.unwrap_or(0),
+                ..input
+            };
+            self.search_at(input, from)
+        }) {
+            if n != 0 && count >= n {
+                break;
+            }
+            from = next_start(input, (start, m.end));
+            if is_adjacent_empty((start, m.end), last_end) {
+                continue;
+            }
+            last_end = Some(m.end);
+            result.extend_from_slice(&line[last..start]);
+
+            // 0 番目のグループはマッチ全体とし、それ以外はスロットから範囲を求める
+            let group = |i: usize| match i {
+                0 => Some((start, m.end)),
+                _ => Some((
+                    m.slots.get(2 * i).copied()??,
+                    m.slots.get(2 * i + 1).copied()??,
+                )),
+            };
+            for piece in pieces(rep.iter().copied()) {
+                match piece {
+                    Piece::Char(c) => result.push(c),
+                    Piece::Text(text) => result.extend(text.chars()),
+                    Piece::Group(name) => {
+                        if let Some((s, e)) =
+                            group_index(&self.program.names, &name).and_then(group)
+                        {
+                            result.extend_from_slice(&line[s..e]);
+                        }
+                    }
+                }
+            }
+            last = m.end;
+            count += 1;
+        }
+        result.extend_from_slice(&line[last..]);
+
+        result
+    }
+
+    /// マッチした位置で入力文字列を分割する
+    ///
+    /// 分割は最大 n - 1 回で、残りは分割せずに最後の要素とする。n が 0 の場合は無制限に分割する。
+    pub fn splitn<'t>(&self, line: &'t str, n: usize) -> Vec<&'t str> {
+        let hay = Haystack::new(line, self.byte_offsets);
+
+        let mut pieces = Vec::new();
+        let mut last = 0;
+        for m in self.find_iter(line) {
+            if n != 0 && pieces.len() + 1 >= n {
+                break;
+            }
+            pieces.push(hay.slice(last, m.start));
+            last = m.end;
+        }
+        pieces.push(hay.rest(last));
+
+        pieces
+    }
+
+    /// find_iter のマッチの位置で入力文字列を分割し、マッチの間とマッチを交互に返す
+    ///
+    /// 偶数番目の要素がマッチの間、奇数番目の要素がマッチで、先頭と末尾は常にマッチの間とする。
+    /// 先頭や末尾のマッチ、隣接するマッチの間では空文字列を返すため、
+    /// すべての要素を連結すると入力文字列に戻る。
+    /// \K を含む場合、\K より前にマッチした部分はマッチの間に含める
+    pub fn split_inclusive<'t>(&self, line: &'t str) -> Vec<&'t str> {
+        let hay = Haystack::new(line, self.byte_offsets);
+
+        let mut pieces = Vec::new();
+        let mut last = 0;
+        for m in self.find_iter(line) {
+            pieces.push(hay.slice(last, m.start));
+            pieces.push(hay.slice(m.start, m.end));
+            last = m.end;
+        }
+        pieces.push(hay.rest(last));
+
+        pieces
+    }
+
+    /// 重なりを許して、入力文字列中のすべてのマッチを返す
+    ///
+    /// 各開始位置について、そこから到達し得るすべての終了位置のマッチを
+    /// 開始位置、終了位置の昇順に返す。
+    /// 開始位置ごとに入力の末尾まで評価するため、入力長 n に対して O(n²) の時間がかかる。
+    /// スロットを保持しない評価のため \K は考慮せず、評価を始めた位置を開始位置とする。
+    pub fn find_overlapping<'t>(&self, line: &'t str) -> impl Iterator<Item = Match<'t>> {
+        let (chars, boundaries) = self.to_units(line);
+        let hay = Haystack::new(line, self.byte_offsets);
+        let input = evaluator::Input {
+            chars: &chars,
+            boundaries: boundaries.as_deref(),
+            scan_start: 0,
+            window_start: 0,
+            max_len: self.max_match_len,
+        };
+        let mut matches = Vec::new();
+
+        for start in (0..=chars.len()).filter(|sp| input.is_boundary(*sp)) {
+            match evaluator::eval_all(&self.program.insts, input, start) {
+                Ok(ends) => matches.extend(
+                    ends.into_iter()
+                        .map(|(end, pattern)| hay.new_match(start, end, pattern)),
+                ),
+                Err(_) => break,
+            }
+        }
+
+        matches.into_iter()
+    }
+}
+
+/// start から始まる評価結果 m の、報告するマッチの開始位置を返す
+///
+/// \K を実行した場合は 0 番目のスロットに保存した位置、それ以外の場合は start となる
+fn reset_start(start: usize, m: &evaluator::Matched) -> usize {
+    m.slots.first().copied().flatten().unwrap_or(start)
+}
+
+/// マッチ m の後に、次のマッチの探索を始める位置を返す
+///
+/// 空文字列にマッチした場合は、同じ位置で繰り返しマッチしないよう次の境界から探索する。
+/// 次の境界がない場合は None を返す
+fn next_start(input: evaluator::Input, (start, end): (usize, usize)) -> Option<usize> {
+    if end > start {
+        Some(end)
+    } else {
+        (end + 1..=input.chars.len()).find(|sp| input.is_boundary(*sp))
+    }
+}
+
+/// m が直前のマッチの終了位置 last_end で始まる、空文字列へのマッチであるかを判定
+fn is_adjacent_empty((start, end): (usize, usize), last_end: Option<usize>) -> bool {
+    start == end && Some(start) == last_end
+}
+
+/// 入力文字列と、文字単位のインデックスをバイト単位のインデックスに変換するための表
+#[derive(Debug)]
+struct Haystack<'t> {
+    line: &'t str,
+    offsets: Vec<usize>, // i 番目の要素は i 文字目の開始位置で、長さは文字数 + 1
+    byte_offsets: bool,  // マッチの位置をバイト単位で報告するか
+}
+
+impl<'t> Haystack<'t> {
+    fn new(line: &'t str, byte_offsets: bool) -> Self {
+        let offsets = line
+            .char_indices()
+            .map(|(pos, _)| pos)
+            .chain(std::iter::once(line.len()))
+            .collect();
+        Haystack {
+            line,
+            offsets,
+            byte_offsets,
+        }
+    }
+
+    /// 報告する単位のインデックスを、文字単位のインデックスに変換
+    ///
+    /// 入力の範囲外の場合や、バイト単位で文字の境界でない場合は None を返す
+    fn index(&self, offset: usize) -> Option<usize> {
+        if self.byte_offsets {
+            self.offsets.binary_search(&offset).ok()
+        } else {
+            (offset < self.offsets.len()).then_some(offset)
+        }
+    }
+
+    /// 文字単位のインデックスを、報告する単位のインデックスに変換
+    fn offset(&self, i: usize) -> usize {
+        if self.byte_offsets {
+            self.offsets[i]
+        } else {
+            i
+        }
+    }
+
+    /// 文字単位の範囲 start..end の部分文字列
+    fn slice(&self, start: usize, end: usize) -> &'t str {
+        &self.line[self.offsets[start]..self.offsets[end]]
+    }
+
+    /// start 文字目から末尾までの部分文字列
+    fn rest(&self, start: usize) -> &'t str {
+        &self.line[self.offsets[start]..]
+    }
+
+    /// 文字単位の範囲 start..end のマッチ
+    fn new_match(&self, start: usize, end: usize, pattern: usize) -> Match<'t> {
+        Match {
+            start,
+            end,
+            bytes: self
+                .byte_offsets
+                .then(|| (self.offsets[start], self.offsets[end])),
+            pattern,
+            text: self.slice(start, end),
+        }
+    }
+}
+
+/// 置換文字列の要素
+enum Piece {
+    Char(char),    // そのまま出力する文字
+    Text(String),  // そのまま出力する文字列。閉じていない ${name など
+    Group(String), // グループ番号またはグループ名による参照
+}
+
+/// 置換文字列を、そのまま出力する文字とグループへの参照に分解
+///
+/// 書式は Regex::replacen を参照
+fn pieces(rep: impl Iterator<Item = char>) -> impl Iterator<Item = Piece> {
+    let mut chars = rep.peekable();
+    std::iter::from_fn(move || {
+        let c = chars.next()?;
+        if c != '$' {
+            return Some(Piece::Char(c));
+        }
+
+        // $ の後の参照を読み込む。参照でない場合は $ をそのまま出力する
+        let mut name = String::new();
+        if chars.next_if_eq(&'$').is_some() {
+            return Some(Piece::Char('$'));
+        } else if chars.next_if_eq(&'{').is_some() {
+            let mut closed = false;
+            for c in chars.by_ref() {
+                if c == '}' {
+                    closed = true;
+                    break;
+                }
+                name.push(c);
+            }
+            if !closed || name.is_empty() {
+                let close = if closed { "}" } else { "" };
+                return Some(Piece::Text(format!("${{{name}{close}")));
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                name.push(c);
+            }
+            if name.is_empty() {
+                return Some(Piece::Char('$'));
+            }
+        }
+        Some(Piece::Group(name))
+    })
+}
+
+/// 置換文字列中の参照が指すグループの番号
+///
+/// 数字のみの場合はグループ番号とし、それ以外はグループ名とする
+fn group_index(names: &[Option<String>], name: &str) -> Option<usize> {
+    match name.parse::<usize>() {
+        Ok(i) => Some(i),
+        Err(_) => names.iter().position(|n| n.as_deref() == Some(name)),
+    }
+}
+
+/// 書記素クラスタの境界を、文字単位のインデックスで求める
+///
+/// 戻り値の i 番目の要素は i 文字目の直前がクラスタの境界であるかを表し、長さは文字数 + 1
+#[cfg(feature = "unicode")]
+fn grapheme_boundaries(line: &str) -> Vec<bool> {
+    use unicode_segmentation::UnicodeSegmentation;
+
+    let mut boundaries = Vec::new();
+    for g in line.graphemes(true) {
+        boundaries.push(true);
+        boundaries.extend(std::iter::repeat_n(false, g.chars().count() - 1));
+    }
+    boundaries.push(true);
+    boundaries
+}
+
+// Regex と RegexSet が Send + Sync であることをコンパイル時に検査
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Regex>();
+    assert_send_sync::<RegexSet>();
+};
+
+/// 命令列全体は出力せず、コンパイル元の正規表現と命令列の長さを出力する
+impl fmt::Debug for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Regex")
+            .field("pattern", &self.pattern)
+            .field("program_len", &self.program.len())
+            .finish()
+    }
+}
+
+/// 正規表現のコンパイル時のオプションを設定するためのビルダー
+pub struct RegexBuilder {
+    expr: String,
+    engine: Engine,
+    longest: bool,
+    step_limit: Option<usize>,
+    alphabet: Option<Vec<char>>,
+    max_dfa_states: usize,
+    max_match_len: Option<usize>,
+    byte_offsets: bool,
+    relative: bool,
+    peephole: bool,
+    flags: parser::Flags<'static>,
+    #[cfg(feature = "predicate")]
+    predicates: Vec<(String, parser::Predicate)>,
+    #[cfg(feature = "unicode")]
+    grapheme: bool,
+}
+
+impl RegexBuilder {
+    pub fn new(expr: &str) -> Self {
+        RegexBuilder {
+            expr: expr.to_string(),
+            engine: Engine::default(),
+            longest: false,
+            step_limit: None,
+            alphabet: None,
+            max_dfa_states: dfa::DEFAULT_MAX_STATES,
+            max_match_len: None,
+            byte_offsets: true,
+            relative: false,
+            peephole: true,
+            flags: parser::Flags::default(),
+            #[cfg(feature = "predicate")]
+            predicates: Vec::new(),
+            #[cfg(feature = "unicode")]
+            grapheme: false,
+        }
+    }
+
+    /// 評価器の種類を指定
+    ///
+    /// (?(n)yes|no) の条件分岐を含むパターンは、指定によらず深さ優先探索で評価する。
+    /// Engine::Auto は、コンパイル時に DFA への変換を試み、is_match のみ DFA で判定する。
+    /// 変換できない場合や、RegexBuilder::alphabet にない文字を含む入力では幅優先探索を用いる
+    pub fn engine(&mut self, engine: Engine) -> &mut Self {
+        self.engine = engine;
+        self
+    }
+
+    /// 最左最長 (POSIX 互換) のマッチを行うかを指定
+    ///
+    /// 真の場合、最も左の開始位置から始まるマッチのうち最も長いものを返す。
+    /// 幅優先探索では最初に Match に到達したスレッドで止めず、すべてのスレッドを最後まで進める。
+    pub fn longest_match(&mut self, yes: bool) -> &mut Self {
+        self.longest = yes;
+        self
+    }
+
+    /// 1回の探索で実行できる命令の数の上限を指定
+    ///
+    /// 深さ優先探索では実行した命令の数を、幅優先探索では各位置で進めたスレッドの数を数え、
+    /// 入力のすべての開始位置での評価の合計が上限を超えた場合は評価を打ち切る。
+    /// (a*)*b のような指数的なバックトラックを起こすパターンの評価時間を制限するために用いる。
+    /// 打ち切った場合、try_is_match などは EvalError::StepLimitExceeded を返し、
+    /// is_match などはマッチしなかったものとして扱う。既定は無制限。
+    pub fn step_limit(&mut self, limit: usize) -> &mut Self {
+        self.step_limit = Some(limit);
+        self
+    }
+
+    /// Regex::compile_dfa で生成する DFA の入力となり得る文字を指定
+    ///
+    /// DFA は指定した文字のみから文字クラスを作るため、DNA の ACGT のように文字の種類が少ない入力では
+    /// 遷移表が小さくなる。指定した文字以外はどの文字や文字クラスにもマッチしないものとして扱い、
+    /// . や [^A] にもマッチしない。\p{name} の述語は、指定した文字についてのみ評価して DFA に含められる。
+    /// 命令列による評価には影響しない。既定はすべての文字。
+    pub fn alphabet(&mut self, alphabet: &[char]) -> &mut Self {
+        self.alphabet = Some(alphabet.to_vec());
+        self
+    }
+
+    /// Regex::compile_dfa で生成する DFA の状態の数の上限を指定
+    ///
+    /// 上限を超える場合、compile_dfa は RegexError::DfaTooLarge のエラーとする。
+    /// Engine::Auto では、上限を超える場合はエラーとせずに DFA を用いない。既定は 10000。
+    pub fn max_dfa_states(&mut self, limit: usize) -> &mut Self {
+        self.max_dfa_states = limit;
+        self
+    }
+
+    /// 1つのマッチが消費できる文字数の上限を指定
+    ///
+    /// 評価器は上限の文字数を消費したスレッドをそれ以上進めず、上限内で得られるマッチを返す。
+    /// 貪欲な量指定子も上限を超えて繰り返さないため、a+ は aaaa に対して上限が 2 の場合 aa にマッチし、
+    /// 巨大な行で .* が行の残りをすべて消費することを防げる。
+    /// {3} のように上限を超える長さを必要とする式はマッチしない。
+    /// 文字数は評価を始めた位置から数えるため、\K で開始位置を移したマッチでは報告する範囲より長くなり得る。
+    /// 後読みする式の長さは含めない。既定は無制限。
+    pub fn max_match_len(&mut self, len: usize) -> &mut Self {
+        self.max_match_len = Some(len);
+        self
+    }
+
+    /// Match と CaptureLocations が報告する位置を、バイト単位とするかを指定
+    ///
+    /// 真の場合は regex クレートと同じくバイト単位のインデックスとし、偽の場合は文字単位とする。
+    /// 評価器は文字単位で動作するため、マッチごとに入力の文字単位からバイト単位への変換表を
+    /// 引いて変換する。変換表は部分文字列を切り出すために文字単位の場合も生成するため、
+    /// バイト単位とすることによる追加の費用は位置の変換のみとなる。既定は真。
+    pub fn byte_offsets(&mut self, yes: bool) -> &mut Self {
+        self.byte_offsets = yes;
+        self
+    }
+
+    /// パターン中の空白と # から行末までのコメントを無視するかを指定
+    ///
+    /// パターン中の (?x) と同じ。エスケープした空白 `\ ` は通常の文字として扱う。
+    pub fn ignore_whitespace(&mut self, yes: bool) -> &mut Self {
+        self.flags.ignore_whitespace = yes;
+        self
+    }
+
+    /// 大文字と小文字を区別せずにマッチするかを指定
+    ///
+    /// パターン中の (?i) と同じ。(?-i) で区別するように戻せる。
+    /// 1文字が1文字に対応する大文字と小文字のみを考慮し、ß と SS のような対応は扱わない。
+    pub fn case_insensitive(&mut self, yes: bool) -> &mut Self {
+        self.flags.ignore_case = yes;
+        self
+    }
+
+    /// ^ と $ を、入力の先頭と末尾に加えて各行の先頭と末尾にもマッチさせるかを指定
+    ///
+    /// パターン中の (?m) と同じ。行は \n で区切り、\r は通常の文字として扱う。
+    /// 切り替えはパース時に行うため、(?m) より前の ^ と $ は入力の先頭と末尾にのみマッチする。
+    pub fn multi_line(&mut self, yes: bool) -> &mut Self {
+        self.flags.multi_line = yes;
+        self
+    }
+
+    /// . を改行にもマッチさせるかを指定
+    ///
+    /// パターン中の (?s) と同じ。既定では . は \n 以外の1文字にマッチする。
+    pub fn dot_matches_new_line(&mut self, yes: bool) -> &mut Self {
+        self.flags.dot_matches_new_line = yes;
+        self
+    }
+
+    /// \d, \w, \s を Unicode の文字を含む文字クラスとするかを指定
+    ///
+    /// 既定は真で、\d は 10 進数字 (一般カテゴリ Nd)、\w は英字、結合文字、数字などの
+    /// 単語を構成する文字と _、\s は Unicode の空白文字にマッチする。
+    /// 偽の場合や unicode フィーチャを無効にした場合は、ASCII の文字のみを対象とする。
+    #[cfg(feature = "unicode")]
+    pub fn unicode(&mut self, yes: bool) -> &mut Self {
+        self.flags.unicode = yes;
+        self
+    }
+
+    /// . とマッチの開始位置を、char ではなく書記素クラスタ単位で扱うかを指定
+    ///
+    /// 真の場合、. は e と結合文字からなる é のような書記素クラスタ全体を1文字として消費し、
+    /// find などはクラスタの途中から始まるマッチを返さない。
+    #[cfg(feature = "unicode")]
+    pub fn grapheme(&mut self, yes: bool) -> &mut Self {
+        self.grapheme = yes;
+        self
+    }
+
+    /// jump, split, repeat_check 命令の飛び先を、命令自身からの相対アドレスで表すかを指定
+    ///
+    /// 真の場合、命令列を別の位置に移しても飛び先を補正せずにそのまま実行できる。
+    /// 既定は絶対アドレスで、どちらの場合もマッチの結果は変わらない。
+    pub fn relative_addressing(&mut self, yes: bool) -> &mut Self {
+        self.relative = yes;
+        self
+    }
+
+    /// 生成した命令列から nop 命令と不要な jump 命令を取り除くかを指定
+    ///
+    /// 既定は真で、jump 命令の連鎖は最終的な飛び先への1回の jump にまとめる。
+    /// 偽の場合はコード生成器が出力した命令列をそのまま用いる。どちらの場合もマッチの結果は変わらない。
+    pub fn peephole(&mut self, yes: bool) -> &mut Self {
+        self.peephole = yes;
+        self
+    }
+
+    /// a** のように、限量子の直後に限量子が続くパターンを許すかを指定
+    ///
+    /// 偽の場合は ParseError::NestedQuantifier のエラーとする。
+    /// 真の場合、a** は (a*)* と同じく、直前の限量子を含む式全体を限量する。
+    pub fn allow_nested_quantifier(&mut self, yes: bool) -> &mut Self {
+        self.flags.allow_nested_quantifier = yes;
+        self
+    }
+
+    /// {,m} のように、繰り返し回数の下限を省略したパターンを許すかを指定
+    ///
+    /// 真の場合、{,m} は {0,m} と同じく m 回までの繰り返しとする。{,} は常にエラー。
+    /// 既定は偽で、下限を省略すると ParseError::InvalidRepeat のエラーとする。
+    pub fn allow_missing_min(&mut self, yes: bool) -> &mut Self {
+        self.flags.allow_missing_min = yes;
+        self
+    }
+
+    /// {n,m} で指定できる繰り返し回数の上限を指定
+    ///
+    /// 上限を超える場合は ParseError::RepeatTooLarge のエラーとする。
+    /// {n,} のように上限がない場合は下限の回数で判定する。既定は 1000。
+    pub fn max_repeat(&mut self, limit: usize) -> &mut Self {
+        self.flags.max_repeat = limit;
+        self
+    }
+
+    /// キャプチャグループの数の上限を指定
+    ///
+    /// 上限を超える場合は、上限を超えたグループの開き括弧の位置を持つ
+    /// ParseError::TooManyGroups のエラーとする。既定は 1000。
+    pub fn max_captures(&mut self, limit: usize) -> &mut Self {
+        self.flags.max_captures = limit;
+        self
+    }
+
+    /// パターンの文字数の上限を指定
+    ///
+    /// 上限を超える場合は、パースを始める前にパターンの文字数を持つ
+    /// ParseError::PatternTooLong のエラーとする。既定は 1000000。
+    pub fn max_pattern_len(&mut self, limit: usize) -> &mut Self {
+        self.flags.max_len = limit;
+        self
+    }
+
+    /// グループの入れ子の深さの上限を指定
+    ///
+    /// コード生成などは AST を再帰的に処理するため、深い入れ子はスタックを使い切るおそれがある。
+    /// 上限を超える場合は、上限を超えたグループの開き括弧の位置を持つ
+    /// ParseError::NestingTooDeep のエラーとする。キャプチャしないグループや後読みも数える。
+    /// 既定は 250。
+    pub fn max_nest(&mut self, limit: usize) -> &mut Self {
+        self.flags.max_nest = limit;
+        self
+    }
+
+    /// パターン中の \p{name} で参照できる、文字がマッチするかを判定する述語を登録
+    ///
+    /// \p{name} は述語が真となる1文字にマッチする。文字クラスの中では使えない。
+    /// 同じ名前を複数回登録した場合は、最初に登録したものを用いる。
+    /// 述語は関数ポインタに限るため、クロージャで状態を持つことはできないが、
+    /// コンパイルした Regex は従来通り Clone, Send, Sync となる。
+    #[cfg(feature = "predicate")]
+    pub fn predicate(&mut self, name: &str, pred: fn(char) -> bool) -> &mut Self {
+        self.predicates.push((name.to_string(), pred));
+        self
+    }
+
+    /// パターン中で使用できる構文を制限
+    ///
+    /// 信頼できないパターンを受け付ける場合に、| やグループなどの構文を禁止できる。
+    /// 許されない構文の特殊文字が現れた場合は ParseError::FeatureDisabled のエラーとする。
+    /// 既定ではすべての構文を許す。
+    pub fn features(&mut self, features: Features) -> &mut Self {
+        self.flags.features = features;
+        self
+    }
+
+    /// 正規表現をコンパイル
+    pub fn build(&self) -> Result<Regex, RegexError> {
+        #[cfg(feature = "predicate")]
+        let flags = parser::Flags {
+            predicates: &self.predicates,
+            ..self.flags
+        };
+        #[cfg(not(feature = "predicate"))]
+        let flags = self.flags;
+
+        let parser::Parsed {
+            ast, group_count, ..
+        } = parser::parse(&self.expr, flags)?;
+        let program = Program::from_ast(&ast, group_count, self.peephole, self.relative)?;
+        // \G は走査の開始位置に依存し、\K は開始位置を変えるため、逆順の命令列では表せない
+        // 条件分岐もグループがマッチしたかが入力の向きで変わるため、同様とする
+        let mut continues = false;
+        visit::walk(&ast, &mut |e| {
+            continues |= matches!(
+                e,
+                parser::AST::AnchorContinue
+                    | parser::AST::ResetStart
+                    | parser::AST::Conditional { .. }
+            )
+        });
+        let reverse = if continues {
+            None
+        } else {
+            codegen::get_code(&ast.reversed(), self.peephole, false).ok()
+        };
+        // 書記素クラスタ単位の場合は、クラスタの途中から始まるマッチを除くため命令列で評価する
+        // マッチの長さに上限がある場合も、上限を超える選択肢を除くため命令列で評価する
+        let literals = literal::Literals::from_ast(&ast).filter(|_| self.max_match_len.is_none());
+        #[cfg(feature = "unicode")]
+        let literals = literals.filter(|_| !self.grapheme);
+        // \K より前の文字は報告するマッチの先頭に含まれないため、評価を始める位置の文字とは限らない
+        let mut prefix = String::new();
+        ast.literal_prefix(&mut prefix);
+        let first_char = prefix.chars().next().filter(|_| !ast.has_reset_start());
+        // 幅優先探索はスロットの異なるスレッドをまとめるため、条件分岐を評価できない
+        let engine = if ast.has_conditional() {
+            Engine::DepthFirst
+        } else {
+            self.engine
+        };
+
+        Ok(Regex {
+            pattern: self.expr.clone(),
+            ast,
+            program,
+            reverse,
+            literals,
+            first_char,
+            engine,
+            longest: self.longest,
+            step_limit: self.step_limit,
+            alphabet: self.alphabet.clone(),
+            max_dfa_states: self.max_dfa_states,
+            dfa: None,
+            max_match_len: self.max_match_len,
+            byte_offsets: self.byte_offsets,
+            #[cfg(feature = "unicode")]
+            grapheme: self.grapheme,
+        }
+        .with_auto_dfa())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// マッチした範囲を (開始位置, 終了位置) の組にする
+    fn span(m: Option<Match>) -> Option<(usize, usize)> {
+        m.map(|m| (m.start(), m.end()))
+    }
+
+    #[test]
+    fn longest_match() {
+        for engine in [Engine::DepthFirst, Engine::WidthFirst] {
+            let re = RegexBuilder::new("a|ab|abc")
+                .longest_match(true)
+                .engine(engine)
+                .build()
+                .unwrap();
+            assert_eq!(span(re.find("abc")), Some((0, 3)));
+        }
+        // 既定は最左最初
+        assert_eq!(
+            span(Regex::new("a|ab|abc").unwrap().find("abc")),
+            Some((0, 1))
+        );
+    }
+
+    #[test]
+    fn ignore_whitespace() {
+        let re = Regex::new("(?x) a b c").unwrap();
+        assert!(re.is_match("abc"));
+        assert!(!re.is_match("a b c"));
+
+        let re = RegexBuilder::new("a b # comment\n c")
+            .ignore_whitespace(true)
+            .build()
+            .unwrap();
+        assert!(re.is_match("abc"));
+
+        // エスケープした空白と文字クラス中の空白は通常の文字
+        let re = Regex::new(r"(?x) a\ b [ ]c").unwrap();
+        assert!(re.is_match("a b c"));
+        assert!(!re.is_match("abc"));
+    }
+
+    #[test]
+    fn matches_empty_and_program_len() {
+        assert!(Regex::new("a*").unwrap().matches_empty());
+        assert!(!Regex::new("a+").unwrap().matches_empty());
+        assert!(Regex::new("(a|)").unwrap().matches_empty());
+
+        // char a と match の2命令
+        assert_eq!(Regex::new("a").unwrap().program_len(), 2);
+    }
+
+    #[test]
+    fn find_overlapping() {
+        let re = Regex::new("a+").unwrap();
+        let spans: Vec<(usize, usize)> = re
+            .find_overlapping("aa")
+            .map(|m| (m.start(), m.end()))
+            .collect();
+        assert_eq!(spans, [(0, 1), (0, 2), (1, 2)]);
+    }
+
+    #[test]
+    fn is_full_match() {
+        let re = Regex::new("a+").unwrap();
+        assert!(re.is_full_match("aaa"));
+        assert!(!re.is_full_match("aaab"));
+        assert!(!re.is_full_match("baaa"));
+        assert!(!re.is_full_match(""));
+
+        // 最左最初では a にマッチするが、全体にマッチする選択肢がある
+        assert!(Regex::new("a|ab").unwrap().is_full_match("ab"));
+    }
+
+    #[test]
+    fn counted_repetition() {
+        let re = RegexBuilder::new("a{0,100000}")
+            .max_repeat(100000)
+            .build()
+            .unwrap();
+        // repeat_init, repeat_check, char, repeat_inc, jump, match
+        assert_eq!(re.program_len(), 6);
+        let line = "a".repeat(3000);
+        assert!(re.is_full_match(&line));
+        assert_eq!(re.find(&line).unwrap().end(), 3000);
+
+        let re = Regex::new("a{2,3}").unwrap();
+        assert!(!re.is_full_match("a"));
+        assert!(re.is_full_match("aa"));
+        assert!(re.is_full_match("aaa"));
+        assert!(!re.is_full_match("aaaa"));
+    }
+
+    #[test]
+    fn nested_counted_repetition() {
+        // 入れ子の {n,m} でも、幅優先探索のスレッドの追加がスタックを溢れさせない
+        for expr in ["((a?){60}){60}", "((a?){100}){100}"] {
+            let re = RegexBuilder::new(expr)
+                .engine(Engine::WidthFirst)
+                .build()
+                .unwrap();
+            assert_eq!(span(re.find("aaab")), Some((0, 3)), "{expr}");
+            assert!(re.is_match(""), "{expr}");
+        }
+
+        // カウンタの取り得る値の積が上限を超える場合は、メモリを使い果たす前にエラーとする
+        let re = RegexBuilder::new("(((a?){100}){100}){100}")
+            .engine(Engine::WidthFirst)
+            .build()
+            .unwrap();
+        assert!(matches!(re.try_find("a"), Err(EvalError::TooManyStates)));
+    }
+
+    #[test]
+    fn escaped_anchor() {
+        let re = Regex::new(r"a\$b").unwrap();
+        assert!(re.is_match("a$b"));
+        assert!(!re.is_match("ab"));
+
+        let re = Regex::new("a$").unwrap();
+        assert!(re.is_match("ba"));
+        assert!(!re.is_match("ab"));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn match_reader() {
+        // 読み込みの区切りをまたぐ文字の扱いは stream のテストで確認する
+        let re = Regex::new("日本+語$").unwrap();
+        assert!(re.is_match_reader("xx日本本語".as_bytes()).unwrap());
+        assert!(!re.is_match_reader("xx日本語x".as_bytes()).unwrap());
+        assert!(re.is_match_reader(&b"\xff"[..]).is_err());
+    }
+
+    #[test]
+    fn clone_and_debug() {
+        let re = Regex::new("(a|b)+c").unwrap();
+        let copy = re.clone();
+        for line in ["abac", "ab", "c", "xxbc"] {
+            assert_eq!(re.find(line), copy.find(line));
+        }
+        assert_eq!(
+            format!("{re:?}"),
+            format!(
+                r#"Regex {{ pattern: "(a|b)+c", program_len: {} }}"#,
+                re.program_len()
+            )
+        );
+    }
+
+    #[test]
+    fn empty_loop() {
+        for engine in [Engine::DepthFirst, Engine::WidthFirst] {
+            let re = RegexBuilder::new("(a?)*b").engine(engine).build().unwrap();
+            assert_eq!(span(re.find("b")), Some((0, 1)));
+            assert_eq!(span(re.find("aaab")), Some((0, 4)));
+            assert!(!re.is_match("aaa"));
+        }
+    }
+
+    #[test]
+    fn class_range() {
+        let re = Regex::new("^[a-z]$").unwrap();
+        assert!(re.is_match("m"));
+        assert!(!re.is_match("M"));
+    }
+
+    #[test]
+    fn ascii_class() {
+        let re = Regex::new("^[a-zA-Z0-9_]+$").unwrap();
+        assert!(re.is_match("Foo_42"));
+        assert!(!re.is_match("foo-bar"));
+        assert!(!re.is_match("café"));
+    }
+
+    #[cfg(feature = "unicode")]
+    #[test]
+    fn grapheme() {
+        let line = "e\u{301}x";
+        let re = RegexBuilder::new("^.x$").grapheme(true).build().unwrap();
+        assert_eq!(span(re.find(line)), Some((0, line.len())));
+
+        // 既定では結合文字も1文字となる
+        assert!(!Regex::new("^.x$").unwrap().is_match(line));
+    }
+
+    #[test]
+    fn splitn() {
+        let re = Regex::new(",").unwrap();
+        assert_eq!(re.splitn("a,b,c,d", 2), vec!["a", "b,c,d"]);
+        assert_eq!(re.splitn("a,b,c,d", 1), vec!["a,b,c,d"]);
+        assert_eq!(re.splitn("a,b", 5), vec!["a", "b"]);
+        assert_eq!(re.splitn("a,b,c", 0), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn named_groups() {
+        let re = Regex::new("(a)(?P<x>b)(c)").unwrap();
+        assert_eq!(re.captures_len(), 4);
+        assert_eq!(
+            re.capture_names().collect::<Vec<_>>(),
+            vec![None, None, Some("x"), None]
+        );
+        let caps = re.captures("abc").unwrap();
+        assert_eq!(span(caps.name("x")), Some((1, 2)));
+        assert_eq!(span(caps.get(3)), Some((2, 3)));
+    }
+
+    #[test]
+    fn relative_addressing() {
+        for engine in [Engine::DepthFirst, Engine::WidthFirst] {
+            let absolute = RegexBuilder::new("(ab|c)*d{2,3}")
+                .engine(engine)
+                .build()
+                .unwrap();
+            let relative = RegexBuilder::new("(ab|c)*d{2,3}")
+                .engine(engine)
+                .relative_addressing(true)
+                .build()
+                .unwrap();
+            for line in ["abcdd", "xcddd", "abd", "dddd"] {
+                assert_eq!(absolute.find(line), relative.find(line));
+            }
+        }
+    }
+
+    #[test]
+    fn concat() {
+        let re = Regex::new("a+")
+            .unwrap()
+            .concat(&Regex::new("b+").unwrap())
+            .unwrap();
+        assert_eq!(span(re.find("xaaabbby")), Some((1, 7)));
+        assert!(!re.is_match("bbbaaa"));
+
+        // キャプチャグループの番号は後ろの式の分をずらす
+        let re = Regex::new("(a)")
+            .unwrap()
+            .concat(&Regex::new("(b)").unwrap())
+            .unwrap();
+        let caps = re.captures("ab").unwrap();
+        assert_eq!(span(caps.get(2)), Some((1, 2)));
+    }
+
+    #[test]
+    fn union() {
+        let re = Regex::new("f(o)o")
+            .unwrap()
+            .union(&Regex::new("b(a)r").unwrap())
+            .unwrap();
+        assert_eq!(span(re.find("xfoo")), Some((1, 4)));
+        assert_eq!(span(re.find("xbar")), Some((1, 4)));
+        assert!(!re.is_match("baz"));
+
+        // 両方のキャプチャグループを参照できる
+        assert_eq!(re.captures_len(), 3);
+        let caps = re.captures("bar").unwrap();
+        assert!(caps.get(1).is_none());
+        assert_eq!(span(caps.get(2)), Some((1, 2)));
+    }
+
+    #[test]
+    fn pattern_id() {
+        let foo = Regex::new("foo").unwrap();
+        assert_eq!(foo.find("foo").unwrap().pattern(), 0);
+
+        let re = foo.union(&Regex::new("bar").unwrap()).unwrap();
+        assert_eq!(re.find("xbar").unwrap().pattern(), 1);
+        assert_eq!(re.find("xfoo").unwrap().pattern(), 0);
+
+        let re = re.union(&Regex::new("baz").unwrap()).unwrap();
+        assert_eq!(re.find("baz").unwrap().pattern(), 2);
+    }
+
+    #[test]
+    fn quote() {
+        let re = Regex::new(r"^\Qa+b\E$").unwrap();
+        assert!(re.is_match("a+b"));
+        assert!(!re.is_match("aab"));
+        assert!(!re.is_match("ab"));
+    }
+
+    #[test]
+    fn look_behind() {
+        for engine in [Engine::DepthFirst, Engine::WidthFirst] {
+            let re = RegexBuilder::new("(?<=foo)bar")
+                .engine(engine)
+                .build()
+                .unwrap();
+            assert_eq!(span(re.find("foobar")), Some((3, 6)));
+            assert!(!re.is_match("bazbar"));
+
+            let re = RegexBuilder::new("(?<!foo)bar")
+                .engine(engine)
+                .build()
+                .unwrap();
+            assert!(!re.is_match("foobar"));
+            assert!(re.is_match("bazbar"));
+        }
+
+        assert!(matches!(
+            Regex::new("(?<=a+)b"),
+            Err(RegexError::Parse(ParseError::VariableLookBehind(_)))
+        ));
+        // 過去の入力を保持しない Read からの入力では後読みできない
+        #[cfg(feature = "std")]
+        assert!(Regex::new("(?<=a)b")
+            .unwrap()
+            .is_match_reader("ab".as_bytes())
+            .is_err());
+    }
+
+    #[test]
+    fn replace_named() {
+        let re = Regex::new(r"(?P<y>\d{4})-(?P<m>\d{2})").unwrap();
+        assert_eq!(re.replace_all("2024-01", "${m}/${y}"), "01/2024");
+        assert_eq!(re.replace_all("2024-01", "$2.$1"), "01.2024");
+        // 存在しない名前や番号は空文字列となる
+        assert_eq!(re.replace_all("2024-01", "[${d}$9]"), "[]");
+    }
+
+    #[test]
+    fn empty_input() {
+        for engine in [Engine::DepthFirst, Engine::WidthFirst] {
+            let build = |expr| RegexBuilder::new(expr).engine(engine).build().unwrap();
+            assert!(build("^$").is_match(""));
+            assert!(build("a*").is_match(""));
+            assert!(!build("a+").is_match(""));
+            assert!(!build("^a").is_match(""));
+            assert_eq!(build("a*").find("").map(|m| m.end()), Some(0));
+        }
+    }
+
+    #[test]
+    fn find_iter_empty() {
+        let re = Regex::new("a*").unwrap();
+        let found: Vec<_> = re.find_iter("bab").map(|m| (m.start(), m.end())).collect();
+        assert_eq!(found, vec![(0, 0), (1, 2), (3, 3)]);
+    }
+
+    #[test]
+    fn replace_borrowed() {
+        let re = Regex::new("a+").unwrap();
+        assert!(matches!(re.replace_all("xyz", "-"), Cow::Borrowed("xyz")));
+        let replaced = re.replace_all("xaay", "-");
+        assert!(matches!(replaced, Cow::Owned(_)));
+        assert_eq!(replaced, "x-y");
+    }
+
+    #[cfg(feature = "predicate")]
+    #[test]
+    fn predicate() {
+        fn vowel(c: char) -> bool {
+            "aeiou".contains(c)
+        }
+
+        let re = RegexBuilder::new(r"\p{vowel}+")
+            .predicate("vowel", vowel)
+            .build()
+            .unwrap();
+        assert_eq!(span(re.find("queue")), Some((1, 5)));
+        assert!(!re.is_match("rhythm"));
+
+        assert!(matches!(
+            Regex::new(r"\p{vowel}"),
+            Err(RegexError::Parse(ParseError::UnknownPredicate(_)))
+        ));
+    }
+
+    #[test]
+    fn disassemble() {
+        let re = Regex::new("(?P<x>a)(b|c)*").unwrap();
+        let golden = "\
+; group 1: x
+0000: save 2
+0001: char a
+0002: save 3
+0003: split 0004, 0011
+0004: save 4
+0005: split 0006, 0008
+0006: char b
+0007: jump 0009
+0008: char c
+0009: save 5
+0010: jump 0003
+0011: match
+";
+        assert_eq!(re.disassemble(), golden);
+        let again = Regex::new("(?P<x>a)(b|c)*").unwrap();
+        assert_eq!(again.disassemble(), golden);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Regex>();
+        assert_send_sync::<RegexSet>();
+
+        let re = std::sync::Arc::new(Regex::new("a+b").unwrap());
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let re = re.clone();
+                std::thread::spawn(move || re.is_match("xaab"))
+            })
+            .collect();
+        for handle in handles {
+            assert!(handle.join().unwrap());
+        }
+    }
+
+    #[test]
+    fn match_as_str() {
+        let line = "日本語 foobar です";
+        let re = Regex::new("o+b").unwrap();
+        let m = re.find(line).unwrap();
+        assert_eq!(m.as_str(), "oob");
+        // 入力文字列の一部を指している
+        let offset = line.find("oob").unwrap();
+        assert_eq!(m.as_str().as_ptr(), line[offset..].as_ptr());
+    }
+
+    #[test]
+    fn alternation_width() {
+        let width = |expr| Regex::new(expr).unwrap().max_alternation_width();
+        assert_eq!(width("a|b|c|d"), 4);
+        assert_eq!(width("x(a|b)|y"), 2);
+        assert_eq!(width("abc"), 1);
+    }
+
+    #[test]
+    fn captures_into() {
+        let re = Regex::new(r"(\d+)-(x)?(\d+)").unwrap();
+        let mut buf = re.capture_locations();
+        assert_eq!(buf.len(), 4);
+        for line in ["1-2", "a12-x34", "no", "5-6"] {
+            let found = re.captures_into(line, &mut buf);
+            let caps = re.captures(line);
+            assert_eq!(found, caps.is_some());
+            for i in 0..buf.len() {
+                let fresh = caps.as_ref().and_then(|c| c.get(i));
+                assert_eq!(buf.get(i), fresh.map(|m| (m.start(), m.end())));
+            }
+        }
+    }
+
+    #[test]
+    fn find_last() {
+        let re = Regex::new("a+").unwrap();
+        let m = re.find_last("aXaaXa").unwrap();
+        assert_eq!((m.start(), m.end()), (5, 6));
+        let m = re.find_last("aXaaX").unwrap();
+        assert_eq!((m.start(), m.end()), (2, 4));
+        assert!(re.find_last("XX").is_none());
+
+        let re = Regex::new("^a").unwrap();
+        assert_eq!(re.find_last("aXa").map(|m| m.start()), Some(0));
+    }
+
+    #[test]
+    fn error_conversion() {
+        assert!(matches!(
+            Regex::new(r"a\qb"),
+            Err(RegexError::Parse(ParseError::InvalidEscape(_, 'q')))
+        ));
+
+        // スロット番号が溢れるグループ番号は、コード生成のエラーとなる
+        fn compile(ast: &parser::AST) -> Result<Vec<Instruction>, RegexError> {
+            let code = codegen::get_code(ast, true, false)?;
+            Ok(code)
+        }
+        let ast = parser::AST::Capture(Box::new(parser::AST::Char('a')), usize::MAX, None);
+        assert!(matches!(
+            compile(&ast),
+            Err(RegexError::CodeGen(CodeGenError::FailCapture(usize::MAX)))
+        ));
+    }
+
+    #[test]
+    fn inline_flags() {
+        let re = Regex::new("(?i)ab(?-i)cd").unwrap();
+        assert!(re.is_match("ABcd"));
+        assert!(!re.is_match("ABCD"));
+
+        // m: ^ と $ は行の先頭と末尾にもマッチする
+        let line = "a\nbc\nd";
+        for engine in [Engine::DepthFirst, Engine::WidthFirst] {
+            let build = |expr| RegexBuilder::new(expr).engine(engine).build().unwrap();
+            let found = |re: &Regex| -> Vec<_> { re.find_iter(line).map(|m| m.as_str()).collect() };
+            assert_eq!(found(&build("(?m)^\\w+$")), vec!["a", "bc", "d"]);
+            assert!(found(&build("^\\w+$")).is_empty());
+            assert_eq!(found(&build("(?m)^\\w(?-m)$")), vec!["d"]);
+            assert_eq!(found(&build("(?m)$")).len(), 3);
+
+            // s: . は改行にもマッチする
+            assert!(!build("a.b").is_match("a\nb"));
+            assert!(build("(?s)a.b").is_match("a\nb"));
+            assert!(!build("(?ms-s)a.b").is_match("a\nb"));
+        }
+
+        let re = RegexBuilder::new("^b").multi_line(true).build().unwrap();
+        assert_eq!(re.find_last("b\nb\nab").map(|m| m.start()), Some(2));
+        let re = RegexBuilder::new("a.b")
+            .dot_matches_new_line(true)
+            .build()
+            .unwrap();
+        assert!(re.is_match("a\nb"));
+        assert_eq!(
+            re.disassemble(),
+            "0000: char a\n0001: any_char_nl\n0002: char b\n0003: match\n"
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn inline_flags_reader() {
+        let re = Regex::new("(?m)^b$").unwrap();
+        assert!(re.is_match_reader("a\nb\nc".as_bytes()).unwrap());
+        assert!(!re.is_match_reader("a\nbb\nc".as_bytes()).unwrap());
+        let re = Regex::new("(?m)a$").unwrap();
+        assert!(re.is_match_reader("a\nb".as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn literal_prefix() {
+        let prefix = |expr| Regex::new(expr).unwrap().literal_prefix();
+        assert_eq!(prefix("foo.*"), Some(("foo".to_string(), false)));
+        assert_eq!(prefix("^bar"), Some(("bar".to_string(), true)));
+        assert_eq!(prefix("a*b"), None);
+    }
+
+    #[test]
+    fn word_boundary() {
+        assert!(Regex::new(r"^[\b]$").unwrap().is_match("\u{8}"));
+        assert!(!Regex::new(r"[\b]").unwrap().is_match("b"));
+
+        for engine in [Engine::DepthFirst, Engine::WidthFirst] {
+            let build = |expr| RegexBuilder::new(expr).engine(engine).build().unwrap();
+            let starts =
+                |re: &Regex, line| -> Vec<_> { re.find_iter(line).map(|m| m.start()).collect() };
+
+            assert_eq!(starts(&build(r"\b"), "ab cd"), vec![0, 2, 3, 5]);
+            assert_eq!(starts(&build(r"\B"), "ab cd"), vec![1, 4]);
+            let re = build(r"\bfoo\b");
+            assert!(re.is_match("a foo b"));
+            assert!(re.is_match("foo"));
+            assert!(!re.is_match("afoo"));
+            assert!(!re.is_match("foo_"));
+        }
+
+        let re = Regex::new(r"\bis\b").unwrap();
+        assert_eq!(re.find_last("this is it").map(|m| m.start()), Some(5));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn word_boundary_reader() {
+        let re = Regex::new(r"\bcd\b").unwrap();
+        assert!(re.is_match_reader("ab cd ef".as_bytes()).unwrap());
+        assert!(!re.is_match_reader("abcdef".as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn simplified_repeat() {
+        for (expr, simple) in [
+            ("a{1,1}b", "ab"),
+            ("a{0,1}b", "a?b"),
+            ("a{0,}b", "a*b"),
+            ("a{1,}b", "a+b"),
+        ] {
+            let re = Regex::new(expr).unwrap();
+            let simple = Regex::new(simple).unwrap();
+            assert_eq!(re.program_len(), simple.program_len());
+            for line in ["b", "ab", "aab", "a"] {
+                assert_eq!(re.find(line), simple.find(line));
+            }
+        }
+    }
+
+    #[test]
+    fn anchored() {
+        for (expr, start, end) in [
+            ("^a", true, false),
+            ("a$", false, true),
+            ("^a$", true, true),
+            ("a", false, false),
+        ] {
+            let re = Regex::new(expr).unwrap();
+            assert_eq!(re.is_anchored_start(), start, "{expr}");
+            assert_eq!(re.is_anchored_end(), end, "{expr}");
+        }
+        // 行の先頭と末尾は入力の先頭と末尾に固定しない
+        let re = Regex::new("(?m)^a$").unwrap();
+        assert!(!re.is_anchored_start());
+        assert!(!re.is_anchored_end());
+    }
+
+    #[test]
+    fn lint() {
+        let lints = Regex::lint("a**|a|a");
+        assert!(lints.iter().any(|l| matches!(l, Lint::NestedQuantifier(2))));
+        assert!(lints.iter().any(|l| matches!(l, Lint::DuplicateBranch(_))));
+        assert!(Regex::lint("ab|cd").is_empty());
+        assert!(matches!(Regex::lint("()")[..], [Lint::EmptyGroup(0)]));
+    }
+
+    #[test]
+    fn replace_dollar() {
+        let re = Regex::new("(?<n>[0-9]+)").unwrap();
+        assert_eq!(re.replace_all("a1b22", "$$"), "a$b$");
+        assert_eq!(re.replace_all("a1b22", "<$1>"), "a<1>b<22>");
+        assert_eq!(re.replace_all("a1", "${n}x"), "a1x");
+        assert_eq!(re.replace_all("a1", "$n"), "a1");
+        // 参照として解釈できない $ はそのまま残す
+        assert_eq!(re.replace_all("a1", "$"), "a$");
+        assert_eq!(re.replace_all("a1", "$-"), "a$-");
+        assert_eq!(re.replace_all("a1b22", NoExpand("$1")), "a$1b$1");
+    }
+
+    #[test]
+    fn replace_chars() {
+        let re = Regex::new("a+").unwrap();
+        let line = "baaacaé";
+        let chars: Vec<char> = line.chars().collect();
+        let replaced = re.replace_all_chars(&chars, &['X']);
+        assert_eq!(
+            replaced.iter().collect::<String>(),
+            re.replace_all(line, "X")
+        );
+        assert_eq!(
+            re.replace_chars(&chars, &['X']),
+            "bXcaé".chars().collect::<Vec<_>>()
+        );
+
+        let re = Regex::new("(a)(b)").unwrap();
+        let chars: Vec<char> = "xab".chars().collect();
+        let rep: Vec<char> = "$2$1".chars().collect();
+        assert_eq!(re.replace_chars(&chars, &rep), vec!['x', 'b', 'a']);
+    }
+
+    #[test]
+    fn max_captures() {
+        assert!(RegexBuilder::new("(a)(b)").max_captures(2).build().is_ok());
+        assert!(matches!(
+            RegexBuilder::new("(a)(b)(c)").max_captures(2).build(),
+            Err(RegexError::Parse(ParseError::TooManyGroups(6)))
+        ));
+        // 非キャプチャグループは数えない
+        assert!(RegexBuilder::new("(?:a)(b)")
+            .max_captures(1)
+            .build()
+            .is_ok());
+        assert!(Regex::new(&"(a)".repeat(1001)).is_err());
+    }
+
+    #[test]
+    fn anchor_continue() {
+        let words = |re: &Regex, line| re.find_iter(line).map(|m| m.as_str()).collect::<Vec<_>>();
+        let re = Regex::new("\\G\\w+").unwrap();
+        assert_eq!(words(&re, "foo bar"), ["foo"]);
+        assert_eq!(words(&re, " foo"), Vec::<&str>::new());
+        let re = Regex::new("\\G\\w").unwrap();
+        assert_eq!(words(&re, "abc d"), ["a", "b", "c"]);
+
+        // 直前のトークンの終了位置から読み進める
+        let re = Regex::new("\\G(\\w+)\\s*").unwrap();
+        let line = "let x = 1";
+        let mut tokens = Vec::new();
+        let mut pos = 0;
+        while let Some(caps) = re.captures_at(line, pos) {
+            tokens.push(caps.get(1).unwrap().as_str());
+            pos = caps.get(0).unwrap().end();
+        }
+        assert_eq!(tokens, ["let", "x"]);
+        assert_eq!(pos, 6);
+    }
+
+    #[test]
+    fn find_traced() {
+        let re = Regex::new("a+").unwrap();
+        let (m, events) = re.find_traced("aab");
+        let m = m.unwrap();
+        assert_eq!((m.start(), m.end()), (0, 2));
+        let steps: Vec<(usize, &str)> = events.iter().map(|e| (e.sp(), e.instruction())).collect();
+        // sp = 2 の char a は失敗し、split の2番目の飛び先へバックトラックする
+        assert_eq!(
+            steps,
+            [
+                (0, "char a"),
+                (1, "split 0000, 0002"),
+                (1, "char a"),
+                (2, "split 0000, 0002"),
+                (2, "char a"),
+                (2, "match"),
+            ]
+        );
+        let pcs: Vec<usize> = events.iter().map(|e| e.pc()).collect();
+        assert_eq!(pcs, [0, 1, 0, 1, 0, 2]);
+    }
+
+    #[cfg(feature = "unicode")]
+    #[test]
+    fn unicode_classes() {
+        let re = Regex::new("\\w+").unwrap();
+        assert_eq!(re.find("café").unwrap().as_str(), "café");
+        assert_eq!(re.find(" Москва!").unwrap().as_str(), "Москва");
+        assert!(Regex::new("^\\d+$").unwrap().is_match("١٢٣"));
+        assert!(Regex::new("^\\s$").unwrap().is_match("\u{3000}"));
+
+        // ASCII の文字のみを対象とする
+        let re = RegexBuilder::new("\\w+").unicode(false).build().unwrap();
+        assert_eq!(re.find("café").unwrap().as_str(), "caf");
+        assert!(!re.is_match("Москва"));
+        let re = RegexBuilder::new("\\d").unicode(false).build().unwrap();
+        assert!(!re.is_match("١"));
+    }
+
+    #[cfg(feature = "unicode")]
+    #[test]
+    fn word_boundary_unicode() {
+        let re = Regex::new(r"\bcaf\b").unwrap();
+        assert!(!re.is_match("café"));
+        let re = RegexBuilder::new(r"\bcaf\b")
+            .unicode(false)
+            .build()
+            .unwrap();
+        assert!(re.is_match("café"));
+    }
+
+    #[test]
+    fn find_lines() {
+        let re = Regex::new("o+").unwrap();
+        let found: Vec<(usize, usize, &str)> = re
+            .find_lines("foo\r\nbar\nboo zoo\n")
+            .map(|(n, m)| (n, m.start(), m.as_str()))
+            .collect();
+        assert_eq!(found, [(1, 1, "oo"), (3, 1, "oo"), (3, 5, "oo")]);
+
+        let re = Regex::new("^b.*$").unwrap();
+        let found: Vec<(usize, &str)> = re
+            .find_lines("foo\r\nbar\r\nbaz")
+            .map(|(n, m)| (n, m.as_str()))
+            .collect();
+        assert_eq!(found, [(2, "bar"), (3, "baz")]);
+    }
+
+    #[test]
+    fn as_pattern() {
+        let re = Regex::new("a+b").unwrap();
+        assert_eq!(re.as_pattern(), "a+b");
+        assert_eq!(re.clone().as_pattern(), "a+b");
+        let re = RegexBuilder::new("a b")
+            .ignore_whitespace(true)
+            .build()
+            .unwrap();
+        assert_eq!(re.as_pattern(), "a b");
+        let c = Regex::new("x").unwrap();
+        assert_eq!(
+            Regex::new("a|b").unwrap().concat(&c).unwrap().as_pattern(),
+            "(?:a|b)(?:x)"
+        );
+    }
+
+    #[test]
+    fn optional_group() {
+        for engine in [Engine::DepthFirst, Engine::WidthFirst] {
+            let groups = |expr: &str, line: &str| {
+                let re = RegexBuilder::new(expr).engine(engine).build().unwrap();
+                let caps = re.captures(line).unwrap();
+                (0..re.captures_len())
+                    .map(|i| caps.get(i).map(|m| m.as_str().to_string()))
+                    .collect::<Vec<_>>()
+            };
+            let s = |s: &str| Some(s.to_string());
+            assert_eq!(groups("(a)?b", "b"), [s("b"), None]);
+            assert_eq!(groups("(a)?b", "ab"), [s("ab"), s("a")]);
+            assert_eq!(groups("(a)?(b)", "b"), [s("b"), None, s("b")]);
+            assert_eq!(groups("(a|b)?c", "c"), [s("c"), None]);
+            assert_eq!(groups("(a|b)?c", "bc"), [s("bc"), s("b")]);
+            // 前の開始位置で保存した範囲を、次の開始位置のマッチに残さない
+            assert_eq!(groups("(a)?bc", "abd bc"), [s("bc"), None]);
+        }
+    }
+
+    #[test]
+    fn peephole() {
+        for expr in ["a|b", "(?:a|b)|c", "(?:a|b)*c", "(a)?(?(1)b|c)"] {
+            let re = Regex::new(expr).unwrap();
+            let raw = RegexBuilder::new(expr).peephole(false).build().unwrap();
+            assert!(re.program_len() <= raw.program_len(), "{expr}");
+            for line in ["a", "b", "c", "ab", "abc", "x"] {
+                assert_eq!(span(re.find(line)), span(raw.find(line)), "{expr} {line}");
+            }
+        }
+    }
+
+    #[test]
+    fn missing_min() {
+        let re = RegexBuilder::new("^a{,3}$")
+            .allow_missing_min(true)
+            .build()
+            .unwrap();
+        assert!(re.is_match(""));
+        assert!(re.is_match("aaa"));
+        assert!(!re.is_match("aaaa"));
+        assert!(matches!(
+            Regex::new("a{,3}"),
+            Err(RegexError::Parse(ParseError::InvalidRepeat(_)))
+        ));
+        assert!(RegexBuilder::new("a{,}")
+            .allow_missing_min(true)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn try_is_match() {
+        let line = "a".repeat(25);
+        for engine in [Engine::DepthFirst, Engine::WidthFirst] {
+            let re = RegexBuilder::new("(a|aa)*c")
+                .engine(engine)
+                .step_limit(1000)
+                .build()
+                .unwrap();
+            assert!(matches!(
+                re.try_is_match(&line),
+                Err(EvalError::StepLimitExceeded)
+            ));
+            assert!(matches!(
+                re.try_find(&line),
+                Err(EvalError::StepLimitExceeded)
+            ));
+            assert!(matches!(
+                re.try_captures(&line),
+                Err(EvalError::StepLimitExceeded)
+            ));
+            // エラーはマッチしなかったものとして扱う
+            assert!(!re.is_match(&line));
+            assert!(matches!(re.try_is_match("ac"), Ok(true)));
+        }
+    }
+
+    #[test]
+    fn literal_alternation() {
+        // [z] は文字クラスのため、トライ木を用いずに命令列で評価する
+        let line = "foobar baz fo bazfoo barbaz";
+        for (expr, general) in [("foo|bar|baz", "foo|bar|ba[z]"), ("ab|abc|b", "ab|abc|[b]")] {
+            let re = Regex::new(expr).unwrap();
+            let general = Regex::new(general).unwrap();
+            let found: Vec<_> = re.find_iter(line).map(|m| (m.start(), m.end())).collect();
+            let expected: Vec<_> = general
+                .find_iter(line)
+                .map(|m| (m.start(), m.end()))
+                .collect();
+            assert_eq!(found, expected, "{expr}");
+            assert_eq!(re.is_match("xabcx"), general.is_match("xabcx"));
+        }
+        let re = Regex::new("foo|bar|baz").unwrap();
+        assert_eq!(re.find_iter(line).count(), 7);
+    }
+
+    #[test]
+    fn concat_reset_start() {
+        let concat = |a: &str, b: &str| {
+            Regex::new(a)
+                .unwrap()
+                .concat(&Regex::new(b).unwrap())
+                .unwrap()
+        };
+        let span = |re: &Regex, line| span(re.find(line));
+
+        let re = concat("foo\\K", "bar");
+        assert_eq!(span(&re, "xfoobar"), Some((4, 7)));
+        let re = concat("a", "b\\Kc");
+        assert_eq!(span(&re, "abc"), Some((2, 3)));
+        let re = concat("(a)", "(b)\\Kc");
+        let caps = re.captures("abc").unwrap();
+        assert_eq!(caps.get(0).map(|m| m.as_str()), Some("c"));
+        assert_eq!(caps.get(2).map(|m| m.as_str()), Some("b"));
+
+        let re = Regex::new("x")
+            .unwrap()
+            .union(&Regex::new("a\\Kb").unwrap())
+            .unwrap();
+        assert_eq!(span(&re, "ab"), Some((1, 2)));
+    }
+
+    #[test]
+    fn count() {
+        assert_eq!(Regex::new("a+").unwrap().count("aXaaXaaa"), 3);
+        assert_eq!(Regex::new("b").unwrap().count("aaa"), 0);
+        // 空文字列へのマッチは find_iter と同じく数える
+        let re = Regex::new("a*").unwrap();
+        assert_eq!(re.count("baa"), re.find_iter("baa").count());
+    }
+
+    #[test]
+    fn captures_expand() {
+        let re = Regex::new("(\\d+)x(?<h>\\d+)").unwrap();
+        let caps = re.captures("3x4").unwrap();
+        let mut dst = String::from(">");
+        caps.expand("${1}-${2}", &mut dst);
+        assert_eq!(dst, ">3-4");
+
+        let mut dst = String::new();
+        caps.expand("$h$$ $0 [$9]", &mut dst);
+        assert_eq!(dst, "4$ 3x4 []");
+    }
+
+    #[test]
+    fn max_match_len() {
+        for engine in [Engine::DepthFirst, Engine::WidthFirst] {
+            let build = |expr: &str| {
+                RegexBuilder::new(expr)
+                    .engine(engine)
+                    .max_match_len(2)
+                    .build()
+                    .unwrap()
+            };
+            let span = |re: &Regex, line| span(re.find(line));
+            assert_eq!(span(&build("a+"), "aaaa"), Some((0, 2)));
+            assert_eq!(span(&build("a{3}"), "aaaa"), None);
+            assert_eq!(span(&build(".*"), "xyz"), Some((0, 2)));
+            assert_eq!(build("a+").find_iter("aaaaa").count(), 3);
+        }
+    }
+
+    #[test]
+    fn anchor_alternation() {
+        let re = Regex::new("^a|b$").unwrap();
+        assert!(re.is_match("ax"));
+        assert!(re.is_match("xb"));
+        assert!(!re.is_match("xa"));
+        assert!(!re.is_match("bx"));
+
+        let re = Regex::new("^(a|b)$").unwrap();
+        assert!(re.is_match("a"));
+        assert!(!re.is_match("ax"));
+        assert!(!re.is_match("xb"));
+        assert!(Regex::new("a|b").unwrap().is_match("xbx"));
+    }
+
+    #[test]
+    fn replace_leading() {
+        let re = Regex::new("a+").unwrap();
+        assert_eq!(re.replace_leading("aaaXaaa", "b"), "bXaaa");
+        assert_eq!(re.replace_leading("XaaaX", "b"), "XaaaX");
+        assert!(matches!(re.replace_leading("XaaaX", "b"), Cow::Borrowed(_)));
+        assert_eq!(
+            Regex::new("(x)+").unwrap().replace_leading("xxy", "[$1]"),
+            "[x]y"
+        );
+    }
+
+    #[test]
+    fn named_iterators() {
+        // 具体的な型を持つため、構造体のフィールドに保持できる
+        struct Words<'r, 't> {
+            matches: Matches<'r, 't>,
+            captures: CaptureMatches<'r, 't>,
+            fields: Split<'r, 't>,
+        }
+        let re = Regex::new("[a-z]+").unwrap();
+        let mut words = Words {
+            matches: re.find_iter("ab cd"),
+            captures: re.captures_iter("ef"),
+            fields: re.split("1a2"),
+        };
+        assert_eq!(words.matches.next().map(|m| m.as_str()), Some("ab"));
+        assert_eq!(words.matches.next().map(|m| m.as_str()), Some("cd"));
+        assert!(words.matches.next().is_none());
+        assert_eq!(
+            words
+                .captures
+                .next()
+                .and_then(|c| c.get(0))
+                .map(|m| m.as_str()),
+            Some("ef")
+        );
+        assert_eq!(words.fields.collect::<Vec<_>>(), ["1", "2"]);
+    }
+
+    #[test]
+    fn replace_all_count() {
+        let re = Regex::new("a").unwrap();
+        assert_eq!(re.replace_all_count("aaa", "b"), ("bbb".to_string(), 3));
+        assert_eq!(re.replace_all_count("xyz", "b"), ("xyz".to_string(), 0));
+        let re = Regex::new("(\\d)").unwrap();
+        assert_eq!(
+            re.replace_all_count("a1b2", "<$1>"),
+            ("a<1>b<2>".to_string(), 2)
+        );
+    }
+
+    #[test]
+    fn byte_offsets() {
+        let line = "あいaaう";
+        let re = Regex::new("a+").unwrap();
+        let m = re.find(line).unwrap();
+        assert_eq!((m.start(), m.end()), (6, 8));
+        assert_eq!(&line[m.start()..m.end()], "aa");
+
+        let re = RegexBuilder::new("(a+)(う)")
+            .byte_offsets(false)
+            .build()
+            .unwrap();
+        let caps = re.captures(line).unwrap();
+        let m = caps.get(0).unwrap();
+        assert_eq!((m.start(), m.end()), (2, 5));
+        assert_eq!(m.as_str(), "aaう");
+        let m = caps.get(2).unwrap();
+        assert_eq!((m.start(), m.end()), (4, 5));
+        let mut locs = re.capture_locations();
+        re.captures_into(line, &mut locs);
+        assert_eq!(locs.get(1), Some((2, 4)));
+    }
+
+    #[test]
+    fn rfind_iter() {
+        let re = Regex::new("a+").unwrap();
+        let found: Vec<(usize, usize)> = re
+            .rfind_iter("aXaaXa")
+            .map(|m| (m.start(), m.end()))
+            .collect();
+        assert_eq!(found, [(5, 6), (2, 4), (0, 1)]);
+        let mut forward: Vec<_> = re
+            .find_iter("aXaaXa")
+            .map(|m| (m.start(), m.end()))
+            .collect();
+        forward.reverse();
+        assert_eq!(found, forward);
+        assert_eq!(re.rfind_iter("XYZ").count(), 0);
+        // 右から探索し直さず、find_iter のマッチを逆順にする
+        let re = Regex::new("a{2}").unwrap();
+        assert_eq!(
+            re.rfind_iter("aaa").map(|m| m.start()).collect::<Vec<_>>(),
+            [0]
+        );
+    }
+
+    #[test]
+    fn find_in_window() {
+        let re = Regex::new("^a$").unwrap();
+        let span =
+            |re: &Regex, line, range| re.find_in_window(line, range).map(|m| (m.start(), m.end()));
+        assert_eq!(span(&re, "bab", 1..2), Some((1, 2)));
+        assert_eq!(span(&re, "bab", 0..2), None);
+        assert_eq!(span(&re, "bab", 1..3), None);
+        assert!(!re.is_match("bab"));
+        // 後読みは範囲の外の文字も参照する
+        let re = Regex::new("(?<=b)a").unwrap();
+        assert_eq!(span(&re, "bab", 1..2), Some((1, 2)));
+        assert_eq!(span(&re, "bab", Range { start: 2, end: 1 }), None);
+        assert_eq!(span(&re, "bab", 1..9), None);
+    }
+
+    #[test]
+    fn split_inclusive() {
+        let re = Regex::new(",+").unwrap();
+        let parts = re.split_inclusive("a,,b");
+        assert_eq!(parts, ["a", ",,", "b"]);
+        assert_eq!(parts.concat(), "a,,b");
+        // 先頭と末尾のマッチの外側は空文字列とする
+        let parts = re.split_inclusive(",a,");
+        assert_eq!(parts, ["", ",", "a", ",", ""]);
+        assert_eq!(parts.concat(), ",a,");
+        assert_eq!(re.split_inclusive("abc"), ["abc"]);
+    }
+
+    #[test]
+    fn pattern_limits() {
+        let long = "a".repeat(1_000_001);
+        assert!(matches!(
+            Regex::new(&long),
+            Err(RegexError::Parse(ParseError::PatternTooLong(1_000_001)))
+        ));
+        // 文字数で数えるため、マルチバイト文字もそれぞれ1文字とする
+        assert!(matches!(
+            RegexBuilder::new("ああa").max_pattern_len(2).build(),
+            Err(RegexError::Parse(ParseError::PatternTooLong(3)))
+        ));
+        assert!(RegexBuilder::new("ああ").max_pattern_len(2).build().is_ok());
+
+        assert!(matches!(
+            RegexBuilder::new("((a))").max_nest(1).build(),
+            Err(RegexError::Parse(ParseError::NestingTooDeep(1)))
+        ));
+        assert!(RegexBuilder::new("(a)(b)").max_nest(1).build().is_ok());
+        let deep = format!("{}a{}", "(".repeat(100_000), ")".repeat(100_000));
+        assert!(matches!(
+            Regex::new(&deep),
+            Err(RegexError::Parse(ParseError::NestingTooDeep(_)))
+        ));
+    }
+
+    #[test]
+    fn control_escape() {
+        let re = Regex::new("a\\cIb").unwrap();
+        assert!(re.is_match("a\tb"));
+        assert!(!re.is_match("aIb"));
+    }
+
+    #[test]
+    fn captures_at() {
+        let re = Regex::new("(\\w)(\\d)").unwrap();
+        let line = "a1 b2 c3";
+        let caps = re.captures_at(line, 1).unwrap();
+        let m = caps.get(0).unwrap();
+        assert_eq!((m.start(), m.end()), (3, 5));
+        let m = caps.get(2).unwrap();
+        assert_eq!((m.start(), m.end(), m.as_str()), (4, 5, "2"));
+        assert!(re.captures_at(line, 7).is_none());
+        assert!(re.captures_at(line, 99).is_none());
+
+        // ^ は start ではなく入力の先頭にのみマッチする
+        let re = Regex::new("^(b)").unwrap();
+        assert!(re.captures_at("ab", 1).is_none());
+        // バイト単位で文字の境界でない位置は None
+        assert!(Regex::new("a").unwrap().captures_at("éa", 1).is_none());
+    }
+
+    #[test]
+    fn balanced_or_priority() {
+        // 木の形によらず、先に現れる選択肢を優先する
+        let branches: Vec<String> = (1..=20).map(|n| "a".repeat(n)).collect();
+        for engine in [Engine::DepthFirst, Engine::WidthFirst] {
+            let re = RegexBuilder::new(&format!("(?:{})b?", branches.join("|")))
+                .engine(engine)
+                .build()
+                .unwrap();
+            assert_eq!(re.find("aaaab").unwrap().as_str(), "a");
+        }
+        let re = Regex::new("x|ab|abc|a|abcd").unwrap();
+        assert_eq!(re.find("abcd").unwrap().as_str(), "ab");
+        let re = RegexBuilder::new("x|ab|abc|a|abcd")
+            .longest_match(true)
+            .build()
+            .unwrap();
+        assert_eq!(re.find("abcd").unwrap().as_str(), "abcd");
+    }
+
+    #[test]
+    fn conditional_full_match() {
+        let re = Regex::new("(a)?(?(1)b|c)").unwrap();
+        assert!(re.is_match("ab"));
+        assert!(re.is_match("c"));
+        assert_eq!(re.find("b"), None);
+        assert_eq!(re.find("ac").map(|m| m.as_str()), Some("c"));
+
+        assert!(re.is_full_match("ab"));
+        assert!(re.is_full_match("c"));
+        assert!(!re.is_full_match("b"));
+        assert!(!re.is_full_match("ac"));
+        assert!(!re.is_full_match("abc"));
+
+        // 最初に見つかるマッチより長い選択肢で全体にマッチする
+        let re = Regex::new("(?:(a)|ab)(?(1)b|c)").unwrap();
+        assert_eq!(re.find("abc").map(|m| m.as_str()), Some("ab"));
+        assert!(re.is_full_match("abc"));
+        assert!(re.is_full_match("ab"));
+        assert!(!re.is_full_match("abb"));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn conditional_reader() {
+        let re = Regex::new("(a)?(?(1)b|c)").unwrap();
+        let err = re.is_match_reader("ab".as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(re.find_overlapping("ab").count(), 0);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn replace_all_to() {
+        let re = Regex::new("(\\d+)").unwrap();
+        for line in ["a1b22c333", "abc", "", "12"] {
+            let mut out: Vec<u8> = Vec::new();
+            let count = re.replace_all_to(line, "<$1>", &mut out).unwrap();
+            assert_eq!(
+                String::from_utf8(out).unwrap(),
+                re.replace_all(line, "<$1>")
+            );
+            assert_eq!(count, re.replace_all_count(line, "<$1>").1);
+        }
+
+        let mut out: Vec<u8> = Vec::new();
+        assert_eq!(
+            re.replace_all_to("x1y2", NoExpand("$1"), &mut out).unwrap(),
+            2
+        );
+        assert_eq!(out, b"x$1y$1");
+
+        // 書き出しに失敗した場合はエラーを返す
+        let mut buf = [0u8; 3];
+        let mut w = &mut buf[..];
+        assert!(re.replace_all_to("a1b22c333", "<$1>", &mut w).is_err());
+    }
 }
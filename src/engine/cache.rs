@@ -0,0 +1,53 @@
+//! コンパイル済みの正規表現のキャッシュ
+use super::{Regex, RegexError};
+use std::sync::Mutex;
+
+/// キャッシュに保持する正規表現の最大数
+const CACHE_SIZE: usize = 16;
+
+/// 最近使用した順に並べた、正規表現とコンパイル結果の組
+///
+/// 末尾ほど最近使用したものとなる
+static CACHE: Mutex<Vec<(String, Regex)>> = Mutex::new(Vec::new());
+
+/// キャッシュを参照して正規表現をコンパイル
+///
+/// キャッシュにある場合はその複製を返し、ない場合はコンパイルしてキャッシュに追加する。
+/// キャッシュが CACHE_SIZE を超える場合は、最も長く使用されていないものを削除する。
+pub fn get_or_compile(expr: &str) -> Result<Regex, RegexError> {
+    // 他のスレッドがロック中にパニックした場合も、キャッシュの内容は壊れないため使い続ける
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+
+    if let Some(pos) = cache.iter().position(|(e, _)| e == expr) {
+        let entry = cache.remove(pos);
+        let regex = entry.1.clone();
+        cache.push(entry);
+        return Ok(regex);
+    }
+
+    let regex = Regex::new(expr)?;
+    if cache.len() >= CACHE_SIZE {
+        cache.remove(0);
+    }
+    cache.push((expr.to_string(), regex.clone()));
+
+    Ok(regex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounded() {
+        let a = get_or_compile("a+b").unwrap();
+        let b = get_or_compile("a+b").unwrap();
+        assert_eq!(format!("{a:?}"), format!("{b:?}"));
+        assert_eq!(a.find("xaab"), b.find("xaab"));
+
+        for i in 0..CACHE_SIZE * 2 {
+            get_or_compile(&format!("a{{{i}}}")).unwrap();
+        }
+        assert!(CACHE.lock().unwrap().len() <= CACHE_SIZE);
+    }
+}
@@ -1,25 +1,937 @@
 // super:: 現在のコードの1つ上を表すパス
 use super::{parser::AST, Instruction};
-// crete:: 現在のクレートのトップを表すパス
-use crete::helper::safe_add;
+// crate:: 現在のクレートのトップを表すパス
+use crate::helper::safe_add;
 use std::{
     error::Error,
     fmt::{self, Display},
 };
 
+/// 文字クラスを range 命令と split 命令の連鎖で生成する、範囲の数の上限
+///
+/// これより多い範囲からなる文字クラスは class 命令とする
+const CLASS_SPLIT_LIMIT: usize = 16;
+
 /// コード生成のエラーを表す型
+///
+/// AST はパターン中の位置を持たないため、失敗した構文の種類とともに、
+/// 生成中の命令のアドレスやグループ番号を保持する
 #[derive(Debug)]
 pub enum CodeGenError {
-    PCoverFlow, // コード生成中にオーバーフローが起きた場合
-    FailStar,
-    FailOr,
-    FailQuestion,
+    PCoverFlow,             // コード生成中にオーバーフローが起きた場合
+    FailStar(usize),        // * の split 命令を設定できない。その命令のアドレス
+    FailOr(usize),          // | の split 命令か jump 命令を設定できない。その命令のアドレス
+    FailQuestion(usize),    // ? の split 命令を設定できない。その命令のアドレス
+    FailRepeat(usize),      // {n,m} の repeat_check 命令を設定できない。その命令のアドレス
+    FailClass(usize),       // 文字クラスを生成できない。残りの範囲の数
+    FailCapture(usize),     // キャプチャグループのスロット番号が溢れる。グループ番号
+    FailConcat,             // 連結する命令列が match 命令で終わらない
+    FailUnion,              // 合併する命令列が match 命令で終わらないか、パターンの番号が溢れる
+    FailLook(bool, bool),   // 先読みまたは固定長でない後読み。先読みであるかと、否定であるか
+    FailConditional(usize), // 条件分岐の split 命令か jump 命令を設定できない。その命令のアドレス
 }
 
 impl Display for CodeGenError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "CodeGenError: {:?}", self)
+        match self {
+            CodeGenError::PCoverFlow => write!(f, "CodeGenError: program counter overflow"),
+            CodeGenError::FailStar(pc) => write!(f, "CodeGenError: failed star (*): pc = {pc}"),
+            CodeGenError::FailOr(pc) => {
+                write!(f, "CodeGenError: failed alternation (|): pc = {pc}")
+            }
+            CodeGenError::FailQuestion(pc) => {
+                write!(f, "CodeGenError: failed question (?): pc = {pc}")
+            }
+            CodeGenError::FailRepeat(pc) => {
+                write!(f, "CodeGenError: failed repetition ({{n,m}}): pc = {pc}")
+            }
+            CodeGenError::FailClass(ranges) => {
+                write!(f, "CodeGenError: failed class: ranges = {ranges}")
+            }
+            CodeGenError::FailCapture(index) => {
+                write!(f, "CodeGenError: failed capture group: index = {index}")
+            }
+            CodeGenError::FailConcat => {
+                write!(
+                    f,
+                    "CodeGenError: failed concat: program does not end with match"
+                )
+            }
+            CodeGenError::FailUnion => write!(f, "CodeGenError: failed union"),
+            CodeGenError::FailConditional(pc) => {
+                write!(f, "CodeGenError: failed conditional (?(n)...): pc = {pc}")
+            }
+            CodeGenError::FailLook(ahead, negate) => {
+                let kind = match (ahead, negate) {
+                    (true, false) => "lookahead (?=...)",
+                    (true, true) => "negative lookahead (?!...)",
+                    (false, false) => "lookbehind (?<=...)",
+                    (false, true) => "negative lookbehind (?<!...)",
+                };
+                if *ahead {
+                    write!(f, "CodeGenError: unsupported {kind}")
+                } else {
+                    write!(f, "CodeGenError: variable-length {kind}")
+                }
+            }
+        }
     }
 }
 
 impl Error for CodeGenError {}
+
+/// コード生成器
+#[derive(Default, Debug)]
+struct Generator {
+    pc: usize,               // 次に生成する命令のアドレス
+    insts: Vec<Instruction>, // 生成した命令列
+    counters: usize,         // {n,m} のループで用いるカウンタの数
+}
+
+/// コード生成を行う関数
+///
+/// optimize が真の場合は、生成した命令列に peephole を適用する。
+/// relative が真の場合は、飛び先を相対アドレスで表す命令列を生成する
+pub fn get_code(
+    ast: &AST,
+    optimize: bool,
+    relative: bool,
+) -> Result<Vec<Instruction>, CodeGenError> {
+    let mut generator = Generator::default();
+    generator.gen_code(ast)?;
+    let insts = if optimize {
+        peephole(generator.insts)
+    } else {
+        generator.insts
+    };
+    if relative {
+        to_relative(insts)
+    } else {
+        Ok(insts)
+    }
+}
+
+/// 絶対アドレスの命令列から nop 命令と不要な jump 命令を取り除く
+///
+/// jump 命令への飛び先は、jump 命令をたどった最終的な飛び先に置き換える。
+/// その上で nop 命令と、取り除く命令を挟んで次の命令へ飛ぶだけの jump 命令を取り除き、
+/// 残った命令の飛び先を詰めた後のアドレスに補正する。
+/// 後読みの命令列は、後読みの式のコード生成時に適用済みとする。
+pub fn peephole(insts: Vec<Instruction>) -> Vec<Instruction> {
+    // addr 番目から nop 命令と jump 命令をたどった先のアドレス
+    // jump 命令が循環する場合に止まるよう、たどる回数は命令列の長さまでとする
+    let resolve = |mut addr: usize| {
+        for _ in 0..insts.len() {
+            match insts.get(addr) {
+                Some(Instruction::Jump(next)) => addr = *next,
+                Some(Instruction::Nop) => addr += 1,
+                _ => break,
+            }
+        }
+        addr
+    };
+
+    // 後ろの命令から順に、取り除くかを決める
+    let mut removed = vec![false; insts.len()];
+    for pc in (0..insts.len()).rev() {
+        removed[pc] = match &insts[pc] {
+            Instruction::Nop => true,
+            Instruction::Jump(_) => {
+                let addr = resolve(pc);
+                addr > pc && removed[pc + 1..addr.min(insts.len())].iter().all(|r| *r)
+            }
+            _ => false,
+        };
+    }
+
+    // 各アドレスの、取り除いた後のアドレス。取り除く命令は次に残る命令のアドレスとなる
+    let mut new_addr = Vec::with_capacity(insts.len() + 1);
+    let mut n = 0;
+    for r in &removed {
+        new_addr.push(n);
+        if !r {
+            n += 1;
+        }
+    }
+    new_addr.push(n);
+    let map = |addr: usize| new_addr.get(resolve(addr)).copied().unwrap_or(n);
+
+    insts
+        .iter()
+        .zip(removed.iter())
+        .filter(|(_, r)| !**r)
+        .map(|(inst, _)| match inst {
+            Instruction::Jump(addr) => Instruction::Jump(map(*addr)),
+            Instruction::Split(addr1, addr2) => Instruction::Split(map(*addr1), map(*addr2)),
+            Instruction::RepeatCheck(reg, min, max, exit) => {
+                Instruction::RepeatCheck(*reg, *min, *max, map(*exit))
+            }
+            inst => inst.clone(),
+        })
+        .collect()
+}
+
+/// 命令列中の jump, split, repeat_check 命令を、相対アドレスの命令に変換
+pub fn to_relative(insts: Vec<Instruction>) -> Result<Vec<Instruction>, CodeGenError> {
+    // pc 番目の命令から addr 番目の命令への相対アドレス
+    let offset = |pc: usize, addr: usize| -> Result<isize, CodeGenError> {
+        let pc = isize::try_from(pc).map_err(|_| CodeGenError::PCoverFlow)?;
+        let addr = isize::try_from(addr).map_err(|_| CodeGenError::PCoverFlow)?;
+        addr.checked_sub(pc).ok_or(CodeGenError::PCoverFlow)
+    };
+
+    insts
+        .into_iter()
+        .enumerate()
+        .map(|(pc, inst)| match inst {
+            Instruction::Jump(addr) => Ok(Instruction::JumpRel(offset(pc, addr)?)),
+            Instruction::Split(addr1, addr2) => Ok(Instruction::SplitRel(
+                offset(pc, addr1)?,
+                offset(pc, addr2)?,
+            )),
+            Instruction::RepeatCheck(reg, min, max, exit) => Ok(Instruction::RepeatCheckRel(
+                reg,
+                min,
+                max,
+                offset(pc, exit)?,
+            )),
+            inst => Ok(inst),
+        })
+        .collect()
+}
+
+/// 命令列が用いる {n,m} のループのカウンタの数
+fn num_counters(insts: &[Instruction]) -> Result<usize, CodeGenError> {
+    let mut num = 0;
+    for inst in insts {
+        if let Instruction::RepeatInit(reg)
+        | Instruction::RepeatCheck(reg, _, _, _)
+        | Instruction::RepeatCheckRel(reg, _, _, _)
+        | Instruction::RepeatInc(reg, _) = inst
+        {
+            let mut n = *reg;
+            safe_add(&mut n, &1, || CodeGenError::PCoverFlow)?;
+            num = num.max(n);
+        }
+    }
+    Ok(num)
+}
+
+/// 命令列を offset 番目から始まる位置に移す
+///
+/// 絶対アドレスの飛び先は offset だけずらし、相対アドレスの飛び先はそのまま用いる。
+/// 同時に、キャプチャグループのスロットを slots だけ、カウンタを counters だけずらす。
+/// \K が保存するマッチ全体の開始位置のスロットはずらさない。
+fn relocate(
+    insts: &[Instruction],
+    offset: usize,
+    slots: usize,
+    counters: usize,
+) -> Result<Vec<Instruction>, CodeGenError> {
+    let shift = |n: usize, by: usize| n.checked_add(by).ok_or(CodeGenError::PCoverFlow);
+
+    insts
+        .iter()
+        .map(|inst| {
+            let inst = match inst {
+                Instruction::Jump(addr) => Instruction::Jump(shift(*addr, offset)?),
+                Instruction::Split(addr1, addr2) => {
+                    Instruction::Split(shift(*addr1, offset)?, shift(*addr2, offset)?)
+                }
+                Instruction::RepeatInit(reg) => Instruction::RepeatInit(shift(*reg, counters)?),
+                Instruction::RepeatCheck(reg, min, max, exit) => Instruction::RepeatCheck(
+                    shift(*reg, counters)?,
+                    *min,
+                    *max,
+                    shift(*exit, offset)?,
+                ),
+                Instruction::RepeatCheckRel(reg, min, max, exit) => {
+                    Instruction::RepeatCheckRel(shift(*reg, counters)?, *min, *max, *exit)
+                }
+                Instruction::RepeatInc(reg, limit) => {
+                    Instruction::RepeatInc(shift(*reg, counters)?, *limit)
+                }
+                // \K が生成する 0 番目のスロットはマッチ全体の開始位置のため、ずらさない
+                Instruction::Save(0) => Instruction::Save(0),
+                Instruction::Save(slot) => Instruction::Save(shift(*slot, slots)?),
+                Instruction::CheckGroup(group, matched) => {
+                    Instruction::CheckGroup(shift(*group, slots / 2)?, *matched)
+                }
+                inst => inst.clone(),
+            };
+            Ok(inst)
+        })
+        .collect()
+}
+
+/// 命令列が区別するパターンの数
+///
+/// match_id 命令を含まない命令列は、単一のパターンとする
+fn num_patterns(insts: &[Instruction]) -> Result<usize, CodeGenError> {
+    let mut num = 1;
+    for inst in insts {
+        if let Instruction::MatchId(id) = inst {
+            let mut n = *id;
+            safe_add(&mut n, &1, || CodeGenError::FailUnion)?;
+            num = num.max(n);
+        }
+    }
+    Ok(num)
+}
+
+/// 命令列中の match 命令を、パターンの番号を base だけずらした match_id 命令に置き換える
+fn tag_matches(insts: &mut [Instruction], base: usize) -> Result<(), CodeGenError> {
+    for inst in insts.iter_mut() {
+        match inst {
+            Instruction::Match => *inst = Instruction::MatchId(base),
+            Instruction::MatchId(id) => {
+                safe_add(id, &base, || CodeGenError::FailUnion)?;
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// 2つの命令列を連結し、first にマッチした直後に second にマッチする命令列を生成
+///
+/// first の末尾の match 命令を取り除き、その位置に second を移して続ける。
+/// first の途中にある match 命令は、second の先頭への jump 命令に置き換える。
+/// slots は first が用いるキャプチャグループのスロットの数で、second のスロットはその後ろにずらす。
+pub fn concat(
+    first: &[Instruction],
+    second: &[Instruction],
+    slots: usize,
+) -> Result<Vec<Instruction>, CodeGenError> {
+    let body = match first.split_last() {
+        Some((Instruction::Match | Instruction::MatchId(_), body)) => body,
+        _ => return Err(CodeGenError::FailConcat),
+    };
+
+    let start = body.len();
+    let mut insts: Vec<Instruction> = body
+        .iter()
+        .map(|inst| match inst {
+            Instruction::Match | Instruction::MatchId(_) => Instruction::Jump(start),
+            inst => inst.clone(),
+        })
+        .collect();
+    insts.extend(relocate(second, start, slots, num_counters(first)?)?);
+    Ok(insts)
+}
+
+/// 2つの命令列から、first と second のいずれかにマッチする命令列を生成
+///
+/// 以下のように、2つの命令列を split で結合する。
+/// どちらにマッチしたかを区別するため、match 命令は first では 0 から、
+/// second では first のパターンの数から始まる番号の match_id 命令に置き換える。
+/// slots は first が用いるキャプチャグループのスロットの数で、second のスロットはその後ろにずらす。
+///
+/// ```text
+///     split L1, L2
+/// L1: first のコード
+///     match_id 0
+/// L2: second のコード
+///     match_id 1
+/// ```
+pub fn union(
+    first: &[Instruction],
+    second: &[Instruction],
+    slots: usize,
+) -> Result<Vec<Instruction>, CodeGenError> {
+    let l1 = 1;
+    let mut l2 = l1;
+    safe_add(&mut l2, &first.len(), || CodeGenError::PCoverFlow)?;
+
+    let mut body1 = relocate(first, l1, 0, 0)?;
+    tag_matches(&mut body1, 0)?;
+    let mut body2 = relocate(second, l2, slots, num_counters(first)?)?;
+    tag_matches(&mut body2, num_patterns(first)?)?;
+
+    let mut insts = vec![Instruction::Split(l1, l2)];
+    insts.extend(body1);
+    insts.extend(body2);
+    Ok(insts)
+}
+
+/// 複数の命令列から、いずれかにマッチする命令列を生成
+///
+/// i 番目の命令列の末尾の match 命令を match_id i に置き換え、以下のように split で結合する。
+/// どのパターンにマッチしたかを区別するため、match 命令は1つにまとめない。
+///
+/// ```text
+///     split L1, L2
+/// L1: 0 番目のコード
+///     match_id 0
+/// L2: split L3, L4
+/// L3: 1 番目のコード
+///     match_id 1
+/// L4: 2 番目のコード
+///     match_id 2
+/// ```
+pub fn union_set(programs: &[Vec<Instruction>]) -> Result<Vec<Instruction>, CodeGenError> {
+    let mut insts = Vec::new();
+    let mut counters = 0;
+
+    for (id, program) in programs.iter().enumerate() {
+        let body = match program.split_last() {
+            Some((Instruction::Match, body)) => body,
+            _ => return Err(CodeGenError::FailUnion),
+        };
+
+        // 最後の命令列以外は、split で次の命令列へも分岐する
+        let is_last = id + 1 == programs.len();
+        let mut start = insts.len();
+        if !is_last {
+            safe_add(&mut start, &1, || CodeGenError::PCoverFlow)?;
+        }
+        let mut next = start;
+        safe_add(&mut next, &body.len(), || CodeGenError::PCoverFlow)?;
+        safe_add(&mut next, &1, || CodeGenError::PCoverFlow)?;
+
+        if !is_last {
+            insts.push(Instruction::Split(start, next));
+        }
+        insts.extend(relocate(body, start, 0, counters)?);
+        insts.push(Instruction::MatchId(id));
+        safe_add(&mut counters, &num_counters(program)?, || {
+            CodeGenError::PCoverFlow
+        })?;
+    }
+
+    Ok(insts)
+}
+
+impl Generator {
+    /// コード生成を行う関数の入り口
+    fn gen_code(&mut self, ast: &AST) -> Result<(), CodeGenError> {
+        self.gen_expr(ast)?;
+        self.inc_pc()?;
+        self.insts.push(Instruction::Match);
+        Ok(())
+    }
+
+    /// AST をパターン分けし、コード生成を行う関数
+    fn gen_expr(&mut self, ast: &AST) -> Result<(), CodeGenError> {
+        match ast {
+            AST::Char(c) => self.gen_char(*c)?,
+            AST::AnyChar(new_line) => self.gen_inst(Instruction::AnyChar(*new_line))?,
+            AST::Class(ranges) => self.gen_class(ranges)?,
+            AST::Predicate(name, pred) => {
+                self.gen_inst(Instruction::Predicate(name.clone(), *pred))?
+            }
+            AST::Or(e1, e2) => self.gen_or(e1, e2)?,
+            AST::Plus(e) => self.gen_plus(e)?,
+            AST::Star(e) => self.gen_star(e)?,
+            AST::Question(e) => self.gen_question(e)?,
+            AST::Repeat(e, min, max) => self.gen_repeat(e, *min, *max)?,
+            AST::Seq(v) => self.gen_seq(v)?,
+            AST::Capture(e, index, _) => self.gen_capture(e, *index)?,
+            AST::Look {
+                ahead,
+                negate,
+                inner,
+            } => self.gen_look(*ahead, *negate, inner)?,
+            AST::Conditional { group, yes, no } => self.gen_conditional(*group, yes, no)?,
+            AST::Empty => (), // 空の正規表現は命令を生成しない
+            AST::AnchorStart => self.gen_inst(Instruction::AnchorStart)?,
+            AST::AnchorContinue => self.gen_inst(Instruction::AnchorContinue)?,
+            // マッチ全体の開始位置を保存する 0 番目のスロットに、現在の位置を保存する
+            AST::ResetStart => self.gen_inst(Instruction::Save(0))?,
+            AST::AnchorEnd => self.gen_inst(Instruction::AnchorEnd)?,
+            AST::AnchorLineStart => self.gen_inst(Instruction::AnchorLineStart)?,
+            AST::AnchorLineEnd => self.gen_inst(Instruction::AnchorLineEnd)?,
+            AST::WordBoundary(negate, unicode) => {
+                self.gen_inst(Instruction::WordBoundary(*negate, *unicode))?
+            }
+        }
+
+        Ok(())
+    }
+
+    /// プログラムカウンタをインクリメント
+    fn inc_pc(&mut self) -> Result<(), CodeGenError> {
+        safe_add(&mut self.pc, &1, || CodeGenError::PCoverFlow)
+    }
+
+    /// 飛び先が決まるまでの仮の nop 命令を生成し、そのアドレスを返す
+    fn gen_placeholder(&mut self) -> Result<usize, CodeGenError> {
+        let addr = self.pc;
+        self.gen_inst(Instruction::Nop)?;
+        Ok(addr)
+    }
+
+    /// addr 番目の仮の nop 命令を inst に置き換える
+    ///
+    /// addr 番目が nop 命令でない場合は err を返す
+    fn patch(
+        &mut self,
+        addr: usize,
+        inst: Instruction,
+        err: CodeGenError,
+    ) -> Result<(), CodeGenError> {
+        if let Some(placeholder @ Instruction::Nop) = self.insts.get_mut(addr) {
+            *placeholder = inst;
+            Ok(())
+        } else {
+            Err(err)
+        }
+    }
+
+    /// char 命令生成関数
+    fn gen_char(&mut self, c: char) -> Result<(), CodeGenError> {
+        let inst = Instruction::Char(c);
+        self.insts.push(inst);
+        self.inc_pc()?;
+        Ok(())
+    }
+
+    /// オペランドの補正が不要な、単一の命令を生成する関数
+    fn gen_inst(&mut self, inst: Instruction) -> Result<(), CodeGenError> {
+        self.insts.push(inst);
+        self.inc_pc()?;
+        Ok(())
+    }
+
+    /// 文字クラスのコード生成器
+    ///
+    /// 複数の範囲からなり、すべての文字が ASCII の場合は、ビット集合で判定する ascii_class 命令を生成する。
+    /// 範囲が CLASS_SPLIT_LIMIT 個より多い場合は、二分探索で判定する class 命令を生成する。
+    /// それ以外の場合は範囲ごとに range 命令を生成し、以下のように split で結合する
+    ///
+    /// ```text
+    ///     split L1, L2
+    /// L1: range r1
+    ///     jmp L3
+    /// L2: 残りの範囲のコード
+    /// L3:
+    /// ```
+    fn gen_class(&mut self, ranges: &[(char, char)]) -> Result<(), CodeGenError> {
+        if ranges.len() > 1 && ranges.iter().all(|(_, hi)| hi.is_ascii()) {
+            let mut bits = [0u64; 2];
+            for &(lo, hi) in ranges {
+                for c in lo as usize..=hi as usize {
+                    bits[c / 64] |= 1 << (c % 64);
+                }
+            }
+            self.insts.push(Instruction::AsciiClass(bits));
+            self.inc_pc()?;
+            return Ok(());
+        }
+
+        // Unicode の \w のように範囲が多い場合、split の連鎖では評価のたびに
+        // 範囲の数だけ分岐し、深さ優先探索のバックトラックの点も増える
+        if ranges.len() > CLASS_SPLIT_LIMIT {
+            self.insts.push(Instruction::Class(ranges.to_vec()));
+            self.inc_pc()?;
+            return Ok(());
+        }
+
+        self.gen_ranges(ranges)
+    }
+
+    /// 文字の範囲の列を、range 命令と split 命令で生成
+    fn gen_ranges(&mut self, ranges: &[(char, char)]) -> Result<(), CodeGenError> {
+        match ranges {
+            [] => Err(CodeGenError::FailClass(0)),
+            [(lo, hi)] => {
+                let inst = Instruction::Range(*lo, *hi);
+                self.insts.push(inst);
+                self.inc_pc()?;
+                Ok(())
+            }
+            [(lo, hi), rest @ ..] => {
+                // split L1, L2
+                let split_addr = self.gen_placeholder()?;
+                let l1 = self.pc;
+
+                // L1: range r1
+                self.gen_ranges(&[(*lo, *hi)])?;
+
+                // jmp L3
+                let jmp_addr = self.gen_placeholder()?;
+
+                // L2: 残りの範囲のコード
+                let l2 = self.pc;
+                self.gen_ranges(rest)?;
+
+                // L2 と L3 の値を設定
+                self.patch(
+                    split_addr,
+                    Instruction::Split(l1, l2),
+                    CodeGenError::FailClass(ranges.len()),
+                )?;
+                self.patch(
+                    jmp_addr,
+                    Instruction::Jump(self.pc),
+                    CodeGenError::FailClass(ranges.len()),
+                )
+            }
+        }
+    }
+
+    /// OR 演算子のコード生成器
+    ///
+    /// 以下のようなコードを生成
+    ///
+    /// ```text
+    ///     split L1, L2
+    /// L1: e1 のコード
+    ///     jmp L3
+    /// L2: e2 のコード
+    /// L3:
+    /// ```
+    fn gen_or(&mut self, e1: &AST, e2: &AST) -> Result<(), CodeGenError> {
+        // split L1, L2
+        let split_addr = self.gen_placeholder()?;
+        let l1 = self.pc;
+
+        // L1: e1 のコード
+        self.gen_expr(e1)?;
+
+        // jmp L3
+        let jmp_addr = self.gen_placeholder()?;
+
+        // L2: e2 のコード
+        let l2 = self.pc;
+        self.gen_expr(e2)?;
+
+        // L2 と L3 の値を設定
+        self.patch(
+            split_addr,
+            Instruction::Split(l1, l2),
+            CodeGenError::FailOr(split_addr),
+        )?;
+        self.patch(
+            jmp_addr,
+            Instruction::Jump(self.pc),
+            CodeGenError::FailOr(jmp_addr),
+        )
+    }
+
+    /// ? 限量子のコード生成器
+    ///
+    /// ```text
+    ///     split L1, L2
+    /// L1: e のコード
+    /// L2:
+    /// ```
+    fn gen_question(&mut self, e: &AST) -> Result<(), CodeGenError> {
+        // split L1, L2
+        let split_addr = self.gen_placeholder()?;
+        let l1 = self.pc;
+
+        // L1: e のコード
+        self.gen_expr(e)?;
+
+        // L2 の値を設定
+        let split = Instruction::Split(l1, self.pc);
+        self.patch(split_addr, split, CodeGenError::FailQuestion(split_addr))
+    }
+
+    /// + 限量子のコード生成器
+    ///
+    /// ```text
+    /// L1: e のコード
+    ///     split L1, L2
+    /// L2:
+    /// ```
+    fn gen_plus(&mut self, e: &AST) -> Result<(), CodeGenError> {
+        // L1: e のコード
+        let l1 = self.pc;
+        self.gen_expr(e)?;
+
+        // split L1, L2
+        self.inc_pc()?;
+        let split = Instruction::Split(l1, self.pc); // L2 = self.pc
+        self.insts.push(split);
+
+        Ok(())
+    }
+
+    /// * 限量子のコード生成器
+    ///
+    /// ```text
+    /// L1: split L2, L3
+    /// L2: e のコード
+    ///     jmp L1
+    /// L3:
+    /// ```
+    fn gen_star(&mut self, e: &AST) -> Result<(), CodeGenError> {
+        // L1: split L2, L3
+        let l1 = self.gen_placeholder()?;
+        let l2 = self.pc;
+
+        // L2: e のコード
+        self.gen_expr(e)?;
+
+        // jmp L1
+        self.gen_inst(Instruction::Jump(l1))?;
+
+        // L3 の値を設定
+        self.patch(
+            l1,
+            Instruction::Split(l2, self.pc),
+            CodeGenError::FailStar(l1),
+        )
+    }
+
+    /// {n,m} のコード生成器
+    ///
+    /// 式を n..=m 回複製するのではなく、カウンタを用いたループとして以下のようなコードを生成
+    ///
+    /// ```text
+    ///     repeat_init r
+    /// L1: repeat_check r, n, m, L2
+    ///     e のコード
+    ///     repeat_inc r, m
+    ///     jmp L1
+    /// L2:
+    /// ```
+    ///
+    /// 上限がない場合はカウンタを下限で飽和させ、カウンタの取り得る値が増え続けないようにする
+    fn gen_repeat(&mut self, e: &AST, min: usize, max: Option<usize>) -> Result<(), CodeGenError> {
+        let reg = self.counters;
+        safe_add(&mut self.counters, &1, || CodeGenError::PCoverFlow)?;
+
+        // repeat_init r
+        self.inc_pc()?;
+        self.insts.push(Instruction::RepeatInit(reg));
+
+        // L1: repeat_check r, n, m, L2
+        let l1 = self.gen_placeholder()?;
+
+        // e のコード
+        self.gen_expr(e)?;
+
+        // repeat_inc r, m
+        self.inc_pc()?;
+        self.insts
+            .push(Instruction::RepeatInc(reg, max.unwrap_or(min)));
+
+        // jmp L1
+        self.inc_pc()?;
+        self.insts.push(Instruction::Jump(l1));
+
+        // L2 の値を設定
+        let check = Instruction::RepeatCheck(reg, min, max, self.pc);
+        self.patch(l1, check, CodeGenError::FailRepeat(l1))
+    }
+
+    /// キャプチャグループのコード生成器
+    ///
+    /// n 番目のグループは、開始位置を 2n 番目、終了位置を 2n+1 番目のスロットに保存する
+    ///
+    /// ```text
+    ///     save 2n
+    ///     e のコード
+    ///     save 2n+1
+    /// ```
+    fn gen_capture(&mut self, e: &AST, index: usize) -> Result<(), CodeGenError> {
+        let start = index
+            .checked_mul(2)
+            .ok_or(CodeGenError::FailCapture(index))?;
+        let mut end = start;
+        safe_add(&mut end, &1, || CodeGenError::FailCapture(index))?;
+
+        self.gen_inst(Instruction::Save(start))?;
+        self.gen_expr(e)?;
+        self.gen_inst(Instruction::Save(end))?;
+
+        Ok(())
+    }
+
+    /// 後読みのコード生成器
+    ///
+    /// 後読みの中の式は、末尾に match 命令を持つ独立した命令列として生成し、
+    /// 後読みする文字数とともに1つの look_behind 命令に格納する。
+    /// 先読みには未対応のため、エラーとする
+    fn gen_look(&mut self, ahead: bool, negate: bool, inner: &AST) -> Result<(), CodeGenError> {
+        if ahead {
+            return Err(CodeGenError::FailLook(ahead, negate));
+        }
+        let len = inner
+            .fixed_len()
+            .ok_or(CodeGenError::FailLook(ahead, negate))?;
+        let insts = get_code(inner, true, false)?;
+        self.gen_inst(Instruction::LookBehind(negate, len, insts))
+    }
+
+    /// (?(n)yes|no) の条件分岐のコード生成器
+    ///
+    /// 両方の分岐の先頭でグループがマッチしたかを検査し、条件を満たす方の分岐のみを続ける
+    ///
+    /// ```text
+    ///     split L1, L2
+    /// L1: check_group n
+    ///     yes のコード
+    ///     jmp L3
+    /// L2: check_no_group n
+    ///     no のコード
+    /// L3:
+    /// ```
+    fn gen_conditional(&mut self, group: usize, yes: &AST, no: &AST) -> Result<(), CodeGenError> {
+        // split L1, L2
+        let split_addr = self.gen_placeholder()?;
+        let l1 = self.pc;
+
+        // L1: check_group n と yes のコード
+        self.gen_inst(Instruction::CheckGroup(group, true))?;
+        self.gen_expr(yes)?;
+
+        // jmp L3
+        let jmp_addr = self.gen_placeholder()?;
+
+        // L2: check_no_group n と no のコード
+        let l2 = self.pc;
+        self.gen_inst(Instruction::CheckGroup(group, false))?;
+        self.gen_expr(no)?;
+
+        // L2 と L3 の値を設定
+        self.patch(
+            split_addr,
+            Instruction::Split(l1, l2),
+            CodeGenError::FailConditional(split_addr),
+        )?;
+        self.patch(
+            jmp_addr,
+            Instruction::Jump(self.pc),
+            CodeGenError::FailConditional(jmp_addr),
+        )
+    }
+
+    /// 連続する正規表現のコード生成器
+    fn gen_seq(&mut self, exprs: &[AST]) -> Result<(), CodeGenError> {
+        for e in exprs {
+            self.gen_expr(e)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::parser::{self, Flags};
+
+    fn code(expr: &str) -> Vec<Instruction> {
+        let ast = parser::parse(expr, Flags::default()).unwrap().ast;
+        get_code(&ast, true, false).unwrap()
+    }
+
+    #[test]
+    fn single_range() {
+        let insts = code("[a-z]");
+        assert_eq!(insts.len(), 2);
+        assert!(matches!(insts[0], Instruction::Range('a', 'z')));
+        assert!(matches!(insts[1], Instruction::Match));
+    }
+
+    #[test]
+    fn ascii_class() {
+        let insts = code("[a-zA-Z0-9_]");
+        assert_eq!(insts.len(), 2);
+        if let Instruction::AsciiClass(bits) = insts[0] {
+            for c in 0..128u8 {
+                let set = bits[c as usize / 64] & (1 << (c % 64)) != 0;
+                assert_eq!(set, c.is_ascii_alphanumeric() || c == b'_');
+            }
+        } else {
+            panic!("expected ascii_class, got {}", insts[0]);
+        }
+
+        // ASCII 以外の文字を含む場合はビット集合を使わない
+        let insts = code("[a-zé]");
+        assert!(!insts
+            .iter()
+            .any(|inst| matches!(inst, Instruction::AsciiClass(_))));
+    }
+
+    #[test]
+    fn relative() {
+        let ast = parser::parse("(ab|c)*d{2,3}", Flags::default())
+            .unwrap()
+            .ast;
+        let absolute = get_code(&ast, true, false).unwrap();
+        let relative = get_code(&ast, true, true).unwrap();
+        assert_eq!(absolute.len(), relative.len());
+        for (pc, (a, r)) in absolute.iter().zip(&relative).enumerate() {
+            assert_eq!(a.to_string(), r.to_absolute(pc).unwrap().to_string());
+        }
+        assert!(relative
+            .iter()
+            .any(|inst| matches!(inst, Instruction::SplitRel(..))));
+    }
+
+    #[test]
+    fn pc_overflow() {
+        let ast = parser::parse("(a|b)*c{2,3}", Flags::default()).unwrap().ast;
+        // 生成に必要な命令数より少ない余裕しかない場合は、アドレスが溢れる前にエラーとする
+        for room in 0..8 {
+            let mut generator = Generator {
+                pc: usize::MAX - room,
+                ..Generator::default()
+            };
+            assert!(generator.gen_code(&ast).is_err());
+        }
+    }
+
+    #[test]
+    fn peephole_len() {
+        let ast = parser::parse("a|b", Flags::default()).unwrap().ast;
+        let raw = get_code(&ast, false, false).unwrap();
+        let optimized = code("a|b");
+        assert!(optimized.len() <= raw.len());
+        assert_eq!(optimized.len(), 5);
+
+        // nop 命令と次の命令へ飛ぶだけの jump 命令を取り除き、飛び先を詰める
+        let insts = vec![
+            Instruction::Split(1, 3),
+            Instruction::Char('a'),
+            Instruction::Jump(4),
+            Instruction::Char('b'),
+            Instruction::Nop,
+            Instruction::Jump(6),
+            Instruction::Match,
+        ];
+        let optimized = peephole(insts);
+        assert_eq!(optimized.len(), 5);
+        assert_eq!(
+            optimized.iter().map(|i| i.to_string()).collect::<Vec<_>>(),
+            code("a|b")
+                .iter()
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn error_construct() {
+        // パーサーは先読みを生成しないため、AST を直接組み立てる
+        let look = AST::Look {
+            ahead: true,
+            negate: true,
+            inner: Box::new(AST::Char('a')),
+        };
+        let err = get_code(&look, true, false).unwrap_err();
+        assert!(matches!(err, CodeGenError::FailLook(true, true)));
+        assert_eq!(
+            err.to_string(),
+            "CodeGenError: unsupported negative lookahead (?!...)"
+        );
+
+        let capture = AST::Capture(Box::new(AST::Char('a')), usize::MAX, None);
+        let err = get_code(&capture, true, false).unwrap_err();
+        assert!(err.to_string().contains("capture group"));
+
+        assert!(CodeGenError::FailStar(3).to_string().contains("star (*)"));
+        assert!(CodeGenError::FailOr(3)
+            .to_string()
+            .contains("alternation (|)"));
+        assert!(CodeGenError::FailRepeat(3).to_string().contains("{n,m}"));
+        assert!(CodeGenError::FailConditional(3)
+            .to_string()
+            .contains("(?(n)...)"));
+    }
+}
@@ -0,0 +1,596 @@
+//! 命令列を部分集合構成法で DFA に変換し、マッチするかを判定
+use super::{codegen, evaluator, parser::AST, visit, Instruction, RegexError};
+use std::collections::HashMap;
+
+/// DFA の状態の数の上限の既定値
+pub const DEFAULT_MAX_STATES: usize = 10_000;
+
+/// {n,m} を展開した後の式の節の数の上限
+const MAX_EXPANDED: usize = 100_000;
+
+/// 昇順に並ぶ文字の範囲と、その文字クラスの番号の列
+type Classes = Vec<(char, char, usize)>;
+
+/// 正規表現を変換した DFA
+///
+/// 各状態は、幅優先探索で同時に実行中のスレッドの pc の集合を表し、
+/// 入力のいずれかの位置にマッチするかを1文字につき1回の遷移で判定する。
+/// どの命令にマッチするかが同じ文字は、1つの文字クラスにまとめて遷移表を共有する。
+/// 0 番目の状態を初期状態とする
+#[derive(Debug, Clone)]
+pub struct Dfa {
+    classes: Classes,      // 昇順に並ぶ文字の範囲と、その文字クラスの番号
+    class_len: usize,      // 文字クラスの数
+    trans: Vec<usize>,     // 状態と文字クラスごとの遷移先。state * class_len + class 番目
+    restart: usize,        // どの文字クラスにも属さない文字での遷移先
+    matched: Vec<bool>,    // 状態ごとの、match 命令に到達したか
+    accept_end: Vec<bool>, // 状態ごとの、入力の末尾であればマッチするか
+}
+
+impl Dfa {
+    /// 正規表現の AST から DFA を生成
+    ///
+    /// alphabet を指定した場合は、その文字のみから文字クラスを作り、それ以外の文字は
+    /// どの命令にもマッチしない文字として扱う。\p{name} は alphabet を指定した場合のみ使用できる。
+    /// 後読みや条件分岐のように、現在の位置と pc の集合だけでは判定できない構文を含む場合や、
+    /// 状態の数が max_states を超える場合はエラー
+    pub(crate) fn new(
+        ast: &AST,
+        alphabet: Option<&[char]>,
+        max_states: usize,
+    ) -> Result<Dfa, RegexError> {
+        let mut size = 0;
+        let ast = expand(ast, &mut size)?;
+        let insts = codegen::get_code(&ast, true, false)?;
+        check(&insts, alphabet.is_some())?;
+        Builder::new(&insts, alphabet, max_states).build()
+    }
+
+    /// 入力文字列のいずれかの位置にマッチするかを判定
+    pub fn is_match(&self, line: &str) -> bool {
+        let mut state = 0;
+        for c in line.chars() {
+            if self.matched[state] {
+                return true;
+            }
+            state = match self.class_of(c) {
+                Some(class) => self.trans[state * self.class_len + class],
+                None => self.restart,
+            };
+        }
+        self.matched[state] || self.accept_end[state]
+    }
+
+    /// 状態の数
+    pub fn state_len(&self) -> usize {
+        self.matched.len()
+    }
+
+    /// 文字クラスの数
+    ///
+    /// どの命令にもマッチしない文字や、alphabet にない文字は含めない
+    pub fn class_len(&self) -> usize {
+        self.class_len
+    }
+
+    /// 文字が属する文字クラスの番号。どの文字クラスにも属さない場合は None
+    fn class_of(&self, c: char) -> Option<usize> {
+        self.classes
+            .binary_search_by(|(lo, hi, _)| {
+                if *hi < c {
+                    std::cmp::Ordering::Less
+                } else if c < *lo {
+                    std::cmp::Ordering::Greater
+                } else {
+                    std::cmp::Ordering::Equal
+                }
+            })
+            .ok()
+            .map(|i| self.classes[i].2)
+    }
+}
+
+/// {n,m} の繰り返しを、カウンタを用いない式に展開
+///
+/// a{2,4} は aaa?a? と、a{2,} は aaa* と同じ式にする。
+/// 展開後の節の数を size に加え、MAX_EXPANDED を超える場合はエラー
+fn expand(ast: &AST, size: &mut usize) -> Result<AST, RegexError> {
+    let too_large = || RegexError::DfaUnsupported("large repetition");
+    *size = size
+        .checked_add(1)
+        .filter(|n| *n <= MAX_EXPANDED)
+        .ok_or_else(too_large)?;
+
+    let ex = |e: &AST, size: &mut usize| expand(e, size).map(Box::new);
+    let ast = match ast {
+        AST::Repeat(e, min, max) => {
+            let e = expand(e, size)?;
+            let copies = max.unwrap_or(*min).max(*min);
+            let mut len: usize = 0;
+            visit::walk(&e, &mut |_| len += 1);
+            *size = len
+                .checked_mul(copies)
+                .and_then(|n| size.checked_add(n))
+                .filter(|n| *n <= MAX_EXPANDED)
+                .ok_or_else(too_large)?;
+
+            let mut seq = vec![e.clone(); *min];
+            match max {
+                Some(max) => {
+                    seq.extend((*min..*max).map(|_| AST::Question(Box::new(e.clone()))));
+                }
+                None => seq.push(AST::Star(Box::new(e))),
+            }
+            AST::Seq(seq)
+        }
+        AST::Plus(e) => AST::Plus(ex(e, size)?),
+        AST::Star(e) => AST::Star(ex(e, size)?),
+        AST::Question(e) => AST::Question(ex(e, size)?),
+        AST::Or(e1, e2) => AST::Or(ex(e1, size)?, ex(e2, size)?),
+        AST::Seq(v) => AST::Seq(
+            v.iter()
+                .map(|e| expand(e, size))
+                .collect::<Result<_, _>>()?,
+        ),
+        AST::Capture(e, index, name) => AST::Capture(ex(e, size)?, *index, name.clone()),
+        AST::Conditional { group, yes, no } => AST::Conditional {
+            group: *group,
+            yes: ex(yes, size)?,
+            no: ex(no, size)?,
+        },
+        e => e.clone(),
+    };
+    Ok(ast)
+}
+
+/// DFA に変換できない命令を含む場合は、その構文を表すエラーを返す
+fn check(insts: &[Instruction], has_alphabet: bool) -> Result<(), RegexError> {
+    for inst in insts {
+        let name = match inst {
+            Instruction::Predicate(_, _) if !has_alphabet => "\\p{...} without alphabet",
+            Instruction::AnchorLineStart => "(?m)^",
+            Instruction::AnchorLineEnd => "(?m)$",
+            Instruction::WordBoundary(..) => "word boundary",
+            Instruction::CheckGroup(_, _) => "conditional",
+            Instruction::LookBehind(..) => "lookbehind",
+            Instruction::RepeatInit(_)
+            | Instruction::RepeatCheck(..)
+            | Instruction::RepeatInc(_, _)
+            | Instruction::RepeatCheckRel(..) => "counter",
+            Instruction::JumpRel(_) | Instruction::SplitRel(_, _) => "relative address",
+            _ => continue,
+        };
+        return Err(RegexError::DfaUnsupported(name));
+    }
+    Ok(())
+}
+
+/// DFA の状態を表す、昇順に並び重複のない pc の集合
+///
+/// スレッドを追加した順序によらず、同じ pc の集合は同じ値となる。
+/// 部分集合構成法で同じ状態を1つにまとめるための、メモのキーとする
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct StateKey(Vec<usize>);
+
+impl StateKey {
+    /// pc の列を昇順に並べ、重複を除いて生成
+    fn new(mut pcs: Vec<usize>) -> Self {
+        pcs.sort_unstable();
+        pcs.dedup();
+        StateKey(pcs)
+    }
+
+    /// 昇順に並ぶ pc の列
+    fn pcs(&self) -> &[usize] {
+        &self.0
+    }
+}
+
+/// 部分集合構成法で DFA を生成するための状態
+struct Builder<'a> {
+    insts: &'a [Instruction],
+    classes: Classes,               // 昇順に並ぶ文字の範囲と、その文字クラスの番号
+    members: Vec<Vec<usize>>,       // 文字クラスごとの、その文字を消費できる命令の pc の昇順の列
+    states: Vec<StateKey>,          // 状態ごとの pc の集合
+    memo: HashMap<StateKey, usize>, // pc の集合から状態の番号への対応
+    restart: StateKey,              // 各位置で新たに開始するスレッドの pc の集合
+    max_states: usize,              // 状態の数の上限
+}
+
+impl<'a> Builder<'a> {
+    fn new(insts: &'a [Instruction], alphabet: Option<&[char]>, max_states: usize) -> Self {
+        let (classes, members) = char_classes(insts, alphabet);
+        let mut builder = Builder {
+            insts,
+            classes,
+            members,
+            states: Vec::new(),
+            memo: HashMap::new(),
+            restart: StateKey(Vec::new()),
+            max_states,
+        };
+        builder.restart = builder.closure(vec![0], false, false);
+        builder
+    }
+
+    /// すべての状態と遷移を生成
+    ///
+    /// 初期状態は入力の先頭で ^ を通過するため、同じ pc の集合を持つ状態とは別に扱う
+    fn build(mut self) -> Result<Dfa, RegexError> {
+        let start = self.closure(vec![0], true, false);
+        self.states.push(start);
+        let restart = self.intern(self.restart.clone())?;
+
+        let class_len = self.members.len();
+        let mut trans = Vec::new();
+        let mut matched = Vec::new();
+        let mut accept_end = Vec::new();
+        let mut i = 0;
+        while i < self.states.len() {
+            let set = self.states[i].clone();
+            let is_matched = set.pcs().iter().any(|pc| self.is_match_inst(*pc));
+            for class in 0..class_len {
+                // match 命令に到達した状態からは、入力の残りによらずマッチする
+                let next = if is_matched {
+                    i
+                } else {
+                    let seeds = self.step(&set, class);
+                    let mut next = self.closure(seeds, false, false).0;
+                    next.extend_from_slice(self.restart.pcs());
+                    self.intern(StateKey::new(next))?
+                };
+                trans.push(next);
+            }
+
+            let ends = set
+                .pcs()
+                .iter()
+                .filter(|pc| matches!(self.insts[**pc], Instruction::AnchorEnd))
+                .map(|pc| pc + 1)
+                .collect();
+            let at_end = self.closure(ends, i == 0, true);
+            matched.push(is_matched);
+            accept_end.push(at_end.pcs().iter().any(|pc| self.is_match_inst(*pc)));
+            i += 1;
+        }
+
+        Ok(Dfa {
+            classes: self.classes,
+            class_len,
+            trans,
+            restart,
+            matched,
+            accept_end,
+        })
+    }
+
+    /// pc の集合に対応する状態の番号を返し、初めての集合の場合は状態を追加
+    fn intern(&mut self, set: StateKey) -> Result<usize, RegexError> {
+        if let Some(state) = self.memo.get(&set) {
+            return Ok(*state);
+        }
+        if self.states.len() >= self.max_states {
+            return Err(RegexError::DfaTooLarge {
+                states: self.states.len() + 1,
+            });
+        }
+        let state = self.states.len();
+        self.memo.insert(set.clone(), state);
+        self.states.push(set);
+        Ok(state)
+    }
+
+    /// pc の集合のうち、class の文字を消費できる命令の次の pc を返す
+    fn step(&self, set: &StateKey, class: usize) -> Vec<usize> {
+        let members = &self.members[class];
+        set.pcs()
+            .iter()
+            .filter(|pc| members.binary_search(pc).is_ok())
+            .map(|pc| pc + 1)
+            .collect()
+    }
+
+    /// seeds から文字を消費せずにたどれる、文字を消費する命令、match 命令、$ の pc の集合を返す
+    ///
+    /// ^ と \G は at_start が真の場合のみ、$ は at_end が真の場合のみ通過する。
+    /// $ を通過しない場合は、入力の末尾で改めてたどるために $ の pc を含める
+    fn closure(&self, seeds: Vec<usize>, at_start: bool, at_end: bool) -> StateKey {
+        let mut visited = vec![false; self.insts.len()];
+        let mut stack = seeds;
+        let mut set = Vec::new();
+        while let Some(pc) = stack.pop() {
+            if visited[pc] {
+                continue;
+            }
+            visited[pc] = true;
+
+            match &self.insts[pc] {
+                Instruction::Jump(addr) => stack.push(*addr),
+                Instruction::Split(addr1, addr2) => {
+                    stack.push(*addr2);
+                    stack.push(*addr1);
+                }
+                Instruction::Save(_) | Instruction::Nop => stack.push(pc + 1),
+                Instruction::AnchorStart | Instruction::AnchorContinue => {
+                    if at_start {
+                        stack.push(pc + 1);
+                    }
+                }
+                Instruction::AnchorEnd if at_end => stack.push(pc + 1),
+                _ => set.push(pc),
+            }
+        }
+        StateKey::new(set)
+    }
+
+    /// match 命令であるかを判定
+    fn is_match_inst(&self, pc: usize) -> bool {
+        matches!(self.insts[pc], Instruction::Match | Instruction::MatchId(_))
+    }
+}
+
+/// 文字を消費する命令を、マッチする文字の集合で文字クラスに分割
+///
+/// 昇順に並ぶ文字の範囲とその文字クラスの番号の列と、文字クラスごとの
+/// その文字を消費できる命令の pc の列を返す。どの命令にもマッチしない文字は含めない。
+/// alphabet を指定した場合は、その文字のみを対象とする
+fn char_classes(insts: &[Instruction], alphabet: Option<&[char]>) -> (Classes, Vec<Vec<usize>>) {
+    let consumers: Vec<usize> = (0..insts.len())
+        .filter(|pc| is_consumer(&insts[*pc]))
+        .collect();
+    let signature = |c: char| -> Vec<usize> {
+        consumers
+            .iter()
+            .copied()
+            .filter(|pc| evaluator::is_char_matched(&insts[*pc], c))
+            .collect()
+    };
+
+    // 文字クラスの代表の文字と、その文字を含む範囲
+    let ranges: Vec<(char, char)> = match alphabet {
+        Some(alphabet) => {
+            let mut chars = alphabet.to_vec();
+            chars.sort_unstable();
+            chars.dedup();
+            chars.into_iter().map(|c| (c, c)).collect()
+        }
+        None => {
+            // 各命令がマッチする範囲の端で区切り、区間内の文字はすべて同じ命令にマッチさせる
+            let mut bounds = vec![0, char::MAX as u32 + 1];
+            for pc in &consumers {
+                for (lo, hi) in inst_ranges(&insts[*pc]) {
+                    bounds.push(lo as u32);
+                    bounds.push(hi as u32 + 1);
+                }
+            }
+            bounds.sort_unstable();
+            bounds.dedup();
+            bounds
+                .windows(2)
+                .filter_map(|w| {
+                    // サロゲートのみからなる区間は文字を含まない
+                    let lo = (w[0]..w[1]).find_map(char::from_u32)?;
+                    let hi = char::from_u32(w[1] - 1).unwrap_or('\u{D7FF}');
+                    Some((lo, hi))
+                })
+                .collect()
+        }
+    };
+
+    let mut ids: HashMap<Vec<usize>, usize> = HashMap::new();
+    let mut members = Vec::new();
+    let mut classes: Classes = Vec::new();
+    for (lo, hi) in ranges {
+        let sig = signature(lo);
+        if sig.is_empty() {
+            continue;
+        }
+        let class = *ids.entry(sig.clone()).or_insert_with(|| {
+            members.push(sig);
+            members.len() - 1
+        });
+        // 隣接する同じ文字クラスの範囲は1つにまとめる
+        match classes.last_mut() {
+            Some((_, last_hi, last))
+                if *last == class && char::from_u32(*last_hi as u32 + 1) == Some(lo) =>
+            {
+                *last_hi = hi;
+            }
+            _ => classes.push((lo, hi, class)),
+        }
+    }
+    (classes, members)
+}
+
+/// 文字を消費する命令であるかを判定
+fn is_consumer(inst: &Instruction) -> bool {
+    matches!(
+        inst,
+        Instruction::Char(_)
+            | Instruction::Range(_, _)
+            | Instruction::AsciiClass(_)
+            | Instruction::Class(_)
+            | Instruction::AnyChar(_)
+            | Instruction::Predicate(_, _)
+    )
+}
+
+/// 文字を消費する命令がマッチする文字の範囲
+///
+/// 述語は範囲で表せないため空とし、alphabet を指定した場合のみ用いる
+fn inst_ranges(inst: &Instruction) -> Vec<(char, char)> {
+    match inst {
+        Instruction::Char(c) => vec![(*c, *c)],
+        Instruction::Range(lo, hi) => vec![(*lo, *hi)],
+        Instruction::AsciiClass(bits) => (0..128u8)
+            .filter(|c| bits[*c as usize / 64] & (1 << (c % 64)) != 0)
+            .map(|c| (c as char, c as char))
+            .collect(),
+        Instruction::Class(ranges) => ranges.clone(),
+        Instruction::AnyChar(true) => vec![('\0', char::MAX)],
+        Instruction::AnyChar(false) => vec![('\0', '\u{9}'), ('\u{B}', char::MAX)],
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{parser, Engine, Regex, RegexBuilder};
+
+    #[test]
+    fn alphabet() {
+        let re = RegexBuilder::new("GA[AT]TC|A{2,3}C")
+            .alphabet(&['A', 'C', 'G', 'T'])
+            .build()
+            .unwrap();
+        let dfa = re.compile_dfa().unwrap();
+        assert!(dfa.class_len() <= 4);
+        assert!(dfa.state_len() <= 16);
+
+        for line in [
+            "GAATTC", "TTGATTCA", "AAC", "CAAAC", "", "GATC", "ACAC", "GAXTC",
+        ] {
+            assert_eq!(dfa.is_match(line), re.is_match(line), "{line}");
+        }
+        // alphabet にない文字はどの文字にもマッチしない
+        let re = RegexBuilder::new("A.C")
+            .alphabet(&['A', 'C'])
+            .build()
+            .unwrap();
+        let dfa = re.compile_dfa().unwrap();
+        assert!(dfa.is_match("AAC"));
+        assert!(!dfa.is_match("AXC"));
+    }
+
+    #[test]
+    fn same_as_nfa() {
+        let patterns = [
+            "abc",
+            "a(b|c)*d",
+            "^ab",
+            "ab$",
+            "^$",
+            "a{2,4}b",
+            "[a-c]+x?",
+            "(?i)hello",
+            "a.c",
+            "(?s)a.c",
+            "\\d{3}-\\w+",
+            "a|",
+            "x*",
+            "a\\Kb",
+        ];
+        let lines = [
+            "", "abc", "xabd", "abcbcd", "ab", "cab", "aaab", "A\nC", "a\nc", "123-x", "hEllo",
+        ];
+        for pattern in patterns {
+            let re = Regex::new(pattern).unwrap();
+            let dfa = re.compile_dfa().unwrap();
+            for line in lines {
+                assert_eq!(dfa.is_match(line), re.is_match(line), "{pattern} {line:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn unsupported() {
+        for pattern in ["(?<=a)b", "(a)?(?(1)b|c)", "\\bab", "(?m)^a"] {
+            let re = Regex::new(pattern).unwrap();
+            assert!(
+                matches!(re.compile_dfa(), Err(RegexError::DfaUnsupported(_))),
+                "{pattern}"
+            );
+        }
+        let re = RegexBuilder::new("a+").max_match_len(2).build().unwrap();
+        assert!(matches!(
+            re.compile_dfa(),
+            Err(RegexError::DfaUnsupported("max_match_len"))
+        ));
+    }
+
+    #[test]
+    fn state_key() {
+        assert_eq!(
+            StateKey::new(vec![3, 1, 1, 2]),
+            StateKey::new(vec![2, 3, 1])
+        );
+        assert_eq!(StateKey::new(vec![2, 3, 1]).pcs(), [1, 2, 3]);
+
+        // スレッドの順序が異なる同じ pc の集合は、同じ状態となる
+        let ast = parser::parse("(?:a|b)c", parser::Flags::default())
+            .unwrap()
+            .ast;
+        let insts = codegen::get_code(&ast, true, false).unwrap();
+        let mut builder = Builder::new(&insts, None, DEFAULT_MAX_STATES);
+        let state = builder.intern(StateKey::new(vec![3, 1])).unwrap();
+        assert_eq!(builder.intern(StateKey::new(vec![1, 3, 3])).unwrap(), state);
+        assert_eq!(builder.states.len(), 1);
+        assert_eq!(
+            builder.closure(vec![3, 1], false, false),
+            builder.closure(vec![1, 3], false, false)
+        );
+
+        // (a|b)* の各位置のスレッドは、1つの状態にまとまる
+        let dfa = Regex::new("(a|b)*c").unwrap().compile_dfa().unwrap();
+        assert!(dfa.state_len() <= 3);
+        assert!(dfa.is_match("ababc"));
+    }
+
+    #[test]
+    fn too_large() {
+        // 末尾から n + 1 文字目が a であるかを判定する DFA は、2^(n+1) 個の状態を必要とする
+        let build = |n: usize, limit: usize| {
+            RegexBuilder::new(&format!("(a|b)*a(a|b){{{n}}}$"))
+                .max_dfa_states(limit)
+                .build()
+                .unwrap()
+        };
+        assert!(matches!(
+            build(8, 100).compile_dfa(),
+            Err(RegexError::DfaTooLarge { states: 101 })
+        ));
+        assert!(build(4, 100).compile_dfa().is_ok());
+        assert!(matches!(
+            build(14, DEFAULT_MAX_STATES).compile_dfa(),
+            Err(RegexError::DfaTooLarge { .. })
+        ));
+        assert_eq!(
+            RegexError::DfaTooLarge { states: 101 }.to_string(),
+            "RegexError: too many DFA states: states = 101"
+        );
+    }
+
+    #[test]
+    fn auto() {
+        let auto = |expr: &str, limit: usize| {
+            RegexBuilder::new(expr)
+                .engine(Engine::Auto)
+                .max_dfa_states(limit)
+                .build()
+                .unwrap()
+        };
+        // 状態の数が上限を超える場合や、変換できない構文を含む場合は、エラーとせずに幅優先探索を用いる
+        for (re, line) in [
+            (auto("(a|b)*a(a|b){8}$", 100), "abbbbbbbb"),
+            (auto("(a|b)*a(a|b){2}$", 100), "babb"),
+            (auto("(?<=x)y", 100), "xy"),
+            (auto("\\bfoo", 100), "a foo"),
+        ] {
+            assert_eq!(re.dfa.is_some(), line == "babb", "{}", re.as_pattern());
+            assert!(re.is_match(line), "{}", re.as_pattern());
+            assert!(!re.is_match("bbbb"), "{}", re.as_pattern());
+        }
+
+        // alphabet にない文字を含む入力では、命令列で評価する
+        let re = RegexBuilder::new("A.C")
+            .engine(Engine::Auto)
+            .alphabet(&['A', 'C'])
+            .build()
+            .unwrap();
+        assert!(re.dfa.is_some());
+        assert!(re.is_match("ACC"));
+        assert!(re.is_match("AXC"));
+        assert!(!re.is_match("AC"));
+    }
+}
@@ -0,0 +1,173 @@
+//! 命令列を Graphviz の DOT 形式に変換
+use super::Instruction;
+use std::fmt::Write;
+
+/// DOT のラベル中で特別な意味を持つ文字をエスケープ
+fn escape(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// ビット集合で表した ASCII 文字の集合を、a-z のような範囲の列として表示
+fn ascii_class_label(bits: &[u64; 2]) -> String {
+    let contains = |c: usize| c < 128 && bits[c / 64] & (1 << (c % 64)) != 0;
+    let mut label = String::new();
+    let mut c = 0;
+    while c < 128 {
+        if !contains(c) {
+            c += 1;
+            continue;
+        }
+        let lo = c;
+        while contains(c + 1) {
+            c += 1;
+        }
+        label.push(lo as u8 as char);
+        if c > lo {
+            label.push('-');
+            label.push(c as u8 as char);
+        }
+        c += 1;
+    }
+    label
+}
+
+/// 命令列を DOT 形式の有向グラフに変換
+///
+/// 命令ごとに1つのノードを作り、次に実行し得る命令へのエッジを張る。
+/// char 命令のエッジにはマッチする文字を、split 命令のエッジには優先順位をラベルとして付ける。
+pub fn to_dot(insts: &[Instruction]) -> String {
+    let mut dot = String::new();
+    // String への書き込みは失敗しないため、結果は無視する
+    let _ = writeln!(dot, "digraph regex {{");
+    let _ = writeln!(dot, "    rankdir=LR;");
+
+    for (pc, inst) in insts.iter().enumerate() {
+        let shape = match inst {
+            Instruction::Match | Instruction::MatchId(_) => "doublecircle",
+            _ => "box",
+        };
+        let label = escape(&format!("{pc:>04}: {inst}"));
+        let _ = writeln!(dot, "    {pc} [shape={shape}, label=\"{label}\"];");
+    }
+
+    for (pc, inst) in insts.iter().enumerate() {
+        // 相対アドレスの命令は、飛び先を絶対アドレスに変換して辺を出力する
+        let inst = if let Some(inst) = inst.to_absolute(pc) {
+            inst
+        } else {
+            continue;
+        };
+
+        match inst.as_ref() {
+            Instruction::Char(c) => {
+                let label = escape(&c.to_string());
+                let _ = writeln!(dot, "    {pc} -> {} [label=\"{label}\"];", pc + 1);
+            }
+            Instruction::Range(lo, hi) => {
+                let label = escape(&format!("{lo}-{hi}"));
+                let _ = writeln!(dot, "    {pc} -> {} [label=\"{label}\"];", pc + 1);
+            }
+            Instruction::AnyChar(_) => {
+                let _ = writeln!(dot, "    {pc} -> {} [label=\".\"];", pc + 1);
+            }
+            Instruction::Predicate(name, _) => {
+                let label = escape(&format!("\\p{{{name}}}"));
+                let _ = writeln!(dot, "    {pc} -> {} [label=\"{label}\"];", pc + 1);
+            }
+            Instruction::AsciiClass(bits) => {
+                let label = escape(&ascii_class_label(bits));
+                let _ = writeln!(dot, "    {pc} -> {} [label=\"{label}\"];", pc + 1);
+            }
+            Instruction::Class(ranges) => {
+                let label = format!("[{} ranges]", ranges.len());
+                let _ = writeln!(dot, "    {pc} -> {} [label=\"{label}\"];", pc + 1);
+            }
+            Instruction::Match
+            | Instruction::MatchId(_)
+            | Instruction::JumpRel(_)
+            | Instruction::SplitRel(_, _)
+            | Instruction::RepeatCheckRel(_, _, _, _) => (),
+            Instruction::Jump(addr) => {
+                let _ = writeln!(dot, "    {pc} -> {addr};");
+            }
+            Instruction::Split(addr1, addr2) => {
+                let _ = writeln!(dot, "    {pc} -> {addr1} [label=\"1\"];");
+                let _ = writeln!(dot, "    {pc} -> {addr2} [label=\"2\"];");
+            }
+            Instruction::AnchorStart => {
+                let _ = writeln!(dot, "    {pc} -> {} [label=\"^\"];", pc + 1);
+            }
+            Instruction::AnchorEnd => {
+                let _ = writeln!(dot, "    {pc} -> {} [label=\"$\"];", pc + 1);
+            }
+            Instruction::AnchorLineStart => {
+                let _ = writeln!(dot, "    {pc} -> {} [label=\"(?m)^\"];", pc + 1);
+            }
+            Instruction::AnchorLineEnd => {
+                let _ = writeln!(dot, "    {pc} -> {} [label=\"(?m)$\"];", pc + 1);
+            }
+            Instruction::WordBoundary(negate, _) => {
+                let label = escape(if *negate { "\\B" } else { "\\b" });
+                let _ = writeln!(dot, "    {pc} -> {} [label=\"{label}\"];", pc + 1);
+            }
+            Instruction::AnchorContinue => {
+                let label = escape("\\G");
+                let _ = writeln!(dot, "    {pc} -> {} [label=\"{label}\"];", pc + 1);
+            }
+            Instruction::LookBehind(negate, _, _) => {
+                let label = if *negate { "(?<!)" } else { "(?<=)" };
+                let _ = writeln!(dot, "    {pc} -> {} [label=\"{label}\"];", pc + 1);
+            }
+            Instruction::CheckGroup(group, matched) => {
+                let label = if *matched { "" } else { "!" };
+                let _ = writeln!(dot, "    {pc} -> {} [label=\"{label}({group})\"];", pc + 1);
+            }
+            Instruction::RepeatInit(_)
+            | Instruction::RepeatInc(_, _)
+            | Instruction::Save(_)
+            | Instruction::Nop => {
+                let _ = writeln!(dot, "    {pc} -> {};", pc + 1);
+            }
+            Instruction::RepeatCheck(_, _, _, exit) => {
+                let _ = writeln!(dot, "    {pc} -> {} [label=\"loop\"];", pc + 1);
+                let _ = writeln!(dot, "    {pc} -> {exit} [label=\"exit\"];");
+            }
+        }
+    }
+
+    let _ = writeln!(dot, "}}");
+    dot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{codegen, parser};
+
+    #[test]
+    fn alternation() {
+        let ast = parser::parse("a|b", parser::Flags::default()).unwrap().ast;
+        let insts = codegen::get_code(&ast, true, false).unwrap();
+        let dot = to_dot(&insts);
+
+        assert!(dot.starts_with("digraph regex {\n"));
+        assert!(dot.contains("0 [shape=box, label=\"0000: split 0001, 0003\"];"));
+        assert!(dot.contains("4 [shape=doublecircle, label=\"0004: match\"];"));
+        assert!(dot.contains("0 -> 1 [label=\"1\"];"));
+        assert!(dot.contains("0 -> 3 [label=\"2\"];"));
+        assert!(dot.contains("1 -> 2 [label=\"a\"];"));
+        assert!(dot.contains("2 -> 4;"));
+        assert!(dot.contains("3 -> 4 [label=\"b\"];"));
+    }
+}
@@ -0,0 +1,1105 @@
+//! 命令列と入力文字列を受け取り、マッチングを行う
+#[cfg(feature = "unicode")]
+use super::unicode_tables;
+use super::Instruction;
+use crate::helper::safe_add;
+use std::{
+    collections::HashSet,
+    error::Error,
+    fmt::{self, Display},
+    mem::swap,
+    rc::Rc,
+};
+
+/// 評価時のエラーを表す型
+#[derive(Debug)]
+pub enum EvalError {
+    PCOverFlow,             // プログラムカウンタがオーバーフローした
+    SPOverFlow,             // 文字列ポインタがオーバーフローした
+    InvalidPC,              // 命令列の範囲外を指すプログラムカウンタ
+    InvalidContext,         // 評価器の内部状態が不正
+    NoLookBehind,           // 入力全体を保持しない評価で、後読みを行おうとした
+    StepLimitExceeded,      // 実行した命令の数が上限を超えた
+    UnsupportedConditional, // 深さ優先探索以外の評価で、条件分岐を評価しようとした
+    TooManyStates, // 幅優先探索の1つの位置で、カウンタを持つスレッドの状態の数が上限を超えた
+}
+
+impl Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EvalError: {:?}", self)
+    }
+}
+
+impl Error for EvalError {}
+
+/// マッチの終了位置と、キャプチャグループのスロットに保存した位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Matched {
+    pub end: usize,
+    pub id: usize,                 // 到達した match 命令のパターンの番号
+    pub slots: Vec<Option<usize>>, // save 命令を実行していないスロットは None
+}
+
+/// match 命令のパターンの番号を返す
+///
+/// match_id 命令以外の match 命令は、単一のパターンの命令列として 0 番とする
+fn match_id(inst: &Instruction) -> Option<usize> {
+    match inst {
+        Instruction::Match => Some(0),
+        Instruction::MatchId(id) => Some(*id),
+        _ => None,
+    }
+}
+
+/// repeat_check 命令の分岐先を優先度順に返す
+///
+/// カウンタが下限未満の場合はループを続け、上限に達した場合はループを脱出する。
+/// それ以外の場合は、ループを続ける方を優先して両方に分岐する。
+fn repeat_targets(
+    count: usize,
+    min: usize,
+    max: Option<usize>,
+    body: usize,
+    exit: usize,
+) -> (Option<usize>, Option<usize>) {
+    if count < min {
+        (Some(body), None)
+    } else if max.is_some_and(|max| count >= max) {
+        (None, Some(exit))
+    } else {
+        (Some(body), Some(exit))
+    }
+}
+
+/// カウンタの値を取得
+fn get_counter(counters: &[usize], reg: usize) -> Result<usize, EvalError> {
+    // repeat_init でカウンタを初期化せずに参照した場合はエラー
+    counters.get(reg).copied().ok_or(EvalError::InvalidContext)
+}
+
+/// カウンタを 0 に初期化
+fn init_counter(counters: &mut Vec<usize>, reg: usize) {
+    if counters.len() <= reg {
+        counters.resize(reg + 1, 0);
+    }
+    counters[reg] = 0;
+}
+
+/// キャプチャグループのスロットに位置を保存
+fn save_slot(slots: &mut Vec<Option<usize>>, slot: usize, sp: usize) {
+    if slots.len() <= slot {
+        slots.resize(slot + 1, None);
+    }
+    slots[slot] = Some(sp);
+}
+
+/// group 番目のキャプチャグループの開始位置と終了位置が、いずれも保存されているかを判定
+fn is_group_matched(slots: &[Option<usize>], group: usize) -> bool {
+    let slot = |n: usize| slots.get(n).copied().flatten();
+    group
+        .checked_mul(2)
+        .is_some_and(|start| slot(start).is_some() && slot(start + 1).is_some())
+}
+
+/// 1回の探索で実行できる命令の数の残り
+///
+/// 深さ優先探索では命令を1つ実行するごとに、幅優先探索ではスレッドを1文字進めるごとに1を消費する
+#[derive(Debug, Clone, Copy)]
+pub struct Budget(Option<usize>); // None の場合は無制限
+
+impl Budget {
+    /// 上限が limit の残りを生成。None の場合は無制限とする
+    pub fn new(limit: Option<usize>) -> Self {
+        Budget(limit)
+    }
+
+    /// 残りを1つ消費し、残っていなかった場合はエラー
+    fn step(&mut self) -> Result<(), EvalError> {
+        match &mut self.0 {
+            Some(0) => Err(EvalError::StepLimitExceeded),
+            Some(n) => {
+                *n -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// 評価する入力文字列
+#[derive(Debug, Clone, Copy)]
+pub struct Input<'a> {
+    pub chars: &'a [char],
+    pub boundaries: Option<&'a [bool]>, // 書記素クラスタ単位で評価する場合、各位置がクラスタの境界であるか
+    pub scan_start: usize,              // \G がマッチする、走査の開始位置
+    pub window_start: usize,            // ^ がマッチする、評価する範囲の開始位置
+    pub max_len: Option<usize>,         // 1回の評価で消費できる文字数の上限。None の場合は無制限
+}
+
+impl Input<'_> {
+    /// sp が書記素クラスタの境界であるかを判定
+    ///
+    /// 書記素クラスタ単位で評価しない場合は、常に真
+    pub fn is_boundary(&self, sp: usize) -> bool {
+        self.boundaries
+            .is_none_or(|b| b.get(sp).copied().unwrap_or(true))
+    }
+
+    /// start から評価を始めたスレッドが、sp の文字を消費できるかを判定
+    fn can_consume(&self, start: usize, sp: usize) -> bool {
+        self.max_len.is_none_or(|n| sp - start < n)
+    }
+}
+
+/// 文字を消費する命令が、入力の文字にマッチするかを判定
+pub fn is_char_matched(inst: &Instruction, c: char) -> bool {
+    match inst {
+        Instruction::Char(ch) => *ch == c,
+        Instruction::AnyChar(new_line) => *new_line || c != '\n',
+        Instruction::Predicate(_, pred) => pred(c),
+        Instruction::Range(lo, hi) => *lo <= c && c <= *hi,
+        Instruction::AsciiClass(bits) => {
+            let c = c as usize;
+            c < 128 && bits[c / 64] & (1 << (c % 64)) != 0
+        }
+        Instruction::Class(ranges) => in_ranges(ranges, c),
+        _ => false,
+    }
+}
+
+/// 昇順に並び、互いに重ならない範囲のいずれかに c が含まれるかを判定
+fn in_ranges(ranges: &[(char, char)], c: char) -> bool {
+    ranges
+        .binary_search_by(|(lo, hi)| {
+            if *hi < c {
+                std::cmp::Ordering::Less
+            } else if c < *lo {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+/// \w にマッチする単語を構成する文字であるかを判定
+///
+/// unicode が偽の場合や unicode フィーチャを無効にした場合は、ASCII の英数字と _ のみを対象とする
+#[cfg_attr(not(feature = "unicode"), allow(unused_variables))]
+fn is_word_char(c: char, unicode: bool) -> bool {
+    #[cfg(feature = "unicode")]
+    if unicode {
+        return in_ranges(unicode_tables::WORD, c);
+    }
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// 文字を消費する命令を sp の文字に対して実行し、次に実行する命令のアドレスを返す
+///
+/// マッチしない場合は None を返す。
+/// 書記素クラスタ単位で評価する場合、any 命令はクラスタの途中の文字を無条件に消費し、
+/// クラスタの末尾に達するまで同じ命令にとどまる。
+fn step_char(
+    inst: &Instruction,
+    pc: usize,
+    line: Input,
+    sp: usize,
+) -> Result<Option<usize>, EvalError> {
+    let c = if let Some(c) = line.chars.get(sp) {
+        *c
+    } else {
+        return Ok(None);
+    };
+
+    // クラスタの途中の文字は、改行であっても any 命令で消費する
+    let is_any = matches!(inst, Instruction::AnyChar(_));
+    let matched = (is_any && !line.is_boundary(sp)) || is_char_matched(inst, c);
+    if !matched {
+        return Ok(None);
+    }
+
+    if is_any && !line.is_boundary(sp + 1) {
+        return Ok(Some(pc));
+    }
+
+    let mut next = pc;
+    safe_add(&mut next, &1, || EvalError::PCOverFlow)?;
+    Ok(Some(next))
+}
+
+/// アンカーの判定に用いる、評価中の位置の前後の文字
+#[derive(Debug, Clone, Copy, Default)]
+struct Around {
+    prev: Option<char>, // 直前の文字。入力の先頭では None
+    next: Option<char>, // 直後の文字。入力の末尾では None
+}
+
+impl Around {
+    /// 入力全体の sp の位置の前後の文字
+    fn at(chars: &[char], sp: usize) -> Self {
+        Around {
+            prev: sp.checked_sub(1).and_then(|i| chars.get(i)).copied(),
+            next: chars.get(sp).copied(),
+        }
+    }
+}
+
+/// アンカーの条件を満たすかを判定
+///
+/// around は sp の前後の文字を、window_start は評価する範囲の開始位置を、
+/// scan_start は走査の開始位置を表す。
+/// 行の先頭と末尾は、入力の先頭と末尾に加えて改行の直後と直前とする。
+/// 単語境界は、前後の文字の一方のみが単語を構成する文字である位置で、入力の外は単語を構成しない
+fn is_anchor_satisfied(
+    inst: &Instruction,
+    sp: usize,
+    around: Around,
+    window_start: usize,
+    scan_start: usize,
+) -> bool {
+    match inst {
+        Instruction::AnchorStart => sp == window_start,
+        Instruction::AnchorEnd => around.next.is_none(),
+        Instruction::AnchorLineStart => sp == window_start || around.prev == Some('\n'),
+        Instruction::AnchorLineEnd => matches!(around.next, None | Some('\n')),
+        Instruction::AnchorContinue => sp == scan_start,
+        Instruction::WordBoundary(negate, unicode) => {
+            let is_word = |c: Option<char>| c.is_some_and(|c| is_word_char(c, *unicode));
+            (is_word(around.prev) != is_word(around.next)) != *negate
+        }
+        _ => false,
+    }
+}
+
+/// 後読みの条件を満たすかを判定
+///
+/// sp の len 文字前の位置から後読みする式の命令列を評価し、sp で終わるマッチがあれば真。
+/// 否定の後読みの場合は、そのようなマッチがなければ真
+fn is_look_satisfied(inst: &Instruction, line: Input, sp: usize) -> Result<bool, EvalError> {
+    match inst {
+        Instruction::LookBehind(negate, len, insts) => {
+            let matched = match sp.checked_sub(*len) {
+                // 後読みする式は、マッチの長さの上限に関わらず評価する
+                Some(start) => eval_all(
+                    insts,
+                    Input {
+                        max_len: None,
+                        ..line
+                    },
+                    start,
+                )?
+                .iter()
+                .any(|(end, _)| *end == sp),
+                None => false,
+            };
+            Ok(matched != *negate)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// 文字を消費せずに通過した分岐命令を、新しいものから順にたどる連結リストの要素
+///
+/// 状態は分岐ごとに複製するため、複製が参照カウントの増加のみで済むよう要素を共有する
+#[derive(Debug)]
+struct Branch {
+    pc: usize,                // 分岐命令のアドレス
+    counters: Vec<usize>,     // 通過した時のカウンタ
+    prev: Option<Rc<Branch>>, // 1つ前に通過した分岐命令
+}
+
+impl Drop for Branch {
+    /// 長いリストを再帰せずに解放する
+    fn drop(&mut self) {
+        let mut prev = self.prev.take();
+        while let Some(branch) = prev {
+            prev = match Rc::try_unwrap(branch) {
+                Ok(mut branch) => branch.prev.take(),
+                Err(_) => None, // 他の状態と共有している要素は残す
+            };
+        }
+    }
+}
+
+/// 深さ優先探索で分岐ごとに複製する状態
+#[derive(Debug, Clone, Default)]
+struct DepthState {
+    counters: Vec<usize>,         // {n,m} のループのカウンタ
+    slots: Vec<Option<usize>>,    // キャプチャグループのスロット
+    branches: Option<Rc<Branch>>, // 文字を消費せずに通過した分岐命令
+}
+
+impl DepthState {
+    /// 分岐命令の通過を記録
+    ///
+    /// 文字を消費せずに同じ状態で同じ分岐命令に戻った場合は、(a?)* のように
+    /// 空文字列にマッチする式のループとなり評価が終わらないため、偽を返す
+    fn enter_branch(&mut self, pc: usize) -> bool {
+        let mut branches = std::iter::successors(self.branches.as_deref(), |b| b.prev.as_deref());
+        if branches.any(|b| b.pc == pc && b.counters == self.counters) {
+            return false;
+        }
+        self.branches = Some(Rc::new(Branch {
+            pc,
+            counters: self.counters.clone(),
+            prev: self.branches.take(),
+        }));
+        true
+    }
+}
+
+/// 深さ優先探索で評価
+///
+/// マッチした場合はマッチの終了位置とスロットを返す。
+/// longest が真の場合は、すべての分岐を探索して最も長いマッチを返す。
+/// 命令を実行するたびに、budget を1つ消費し、その pc と sp を引数として trace を呼び出す。
+///
+/// 再帰呼び出しは行わず、分岐命令で優先度の低い方の分岐先をスタックに積み、
+/// 評価が失敗するたびにスタックから取り出して再開する
+fn eval_depth<T>(
+    inst: &[Instruction],
+    line: Input,
+    sp: usize,
+    longest: bool,
+    budget: &mut Budget,
+    trace: &mut T,
+) -> Result<Option<Matched>, EvalError>
+where
+    T: FnMut(usize, usize),
+{
+    let start = sp;
+    let mut stack = vec![(0, sp, DepthState::default())]; // バックトラック時に再開する pc, sp と状態
+    let mut matched: Option<Matched> = None;
+
+    'backtrack: while let Some((mut pc, mut sp, mut state)) = stack.pop() {
+        loop {
+            let next = if let Some(i) = inst.get(pc) {
+                i
+            } else {
+                return Err(EvalError::InvalidPC);
+            };
+            budget.step()?;
+            trace(pc, sp);
+
+            // 相対アドレスの命令は、絶対アドレスの命令に変換して実行する
+            let resolved = next.to_absolute(pc).ok_or(EvalError::InvalidPC)?;
+            let next = resolved.as_ref();
+
+            match next {
+                Instruction::Char(_)
+                | Instruction::Range(_, _)
+                | Instruction::AsciiClass(_)
+                | Instruction::Class(_)
+                | Instruction::Predicate(_, _)
+                | Instruction::AnyChar(_) => {
+                    if !line.can_consume(start, sp) {
+                        continue 'backtrack;
+                    }
+                    if let Some(next_pc) = step_char(next, pc, line, sp)? {
+                        pc = next_pc;
+                        safe_add(&mut sp, &1, || EvalError::SPOverFlow)?;
+                        state.branches = None;
+                    } else {
+                        continue 'backtrack;
+                    }
+                }
+                Instruction::Match | Instruction::MatchId(_) => {
+                    // 長さが同じ場合は、先に見つかった優先度の高い方のマッチを残す
+                    if matched.as_ref().is_none_or(|m| sp > m.end) {
+                        matched = Some(Matched {
+                            end: sp,
+                            id: match_id(next).unwrap_or(0),
+                            slots: state.slots,
+                        });
+                    }
+                    if !longest {
+                        return Ok(matched);
+                    }
+                    continue 'backtrack;
+                }
+                Instruction::Jump(addr) => {
+                    pc = *addr;
+                }
+                Instruction::Split(addr1, addr2) => {
+                    if !state.enter_branch(pc) {
+                        continue 'backtrack;
+                    }
+                    stack.push((*addr2, sp, state.clone()));
+                    pc = *addr1;
+                }
+                Instruction::RepeatInit(reg) => {
+                    init_counter(&mut state.counters, *reg);
+                    safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+                }
+                Instruction::RepeatCheck(reg, min, max, exit) => {
+                    if !state.enter_branch(pc) {
+                        continue 'backtrack;
+                    }
+                    let count = get_counter(&state.counters, *reg)?;
+                    let mut body = pc;
+                    safe_add(&mut body, &1, || EvalError::PCOverFlow)?;
+                    match repeat_targets(count, *min, *max, body, *exit) {
+                        (Some(addr), None) | (None, Some(addr)) => pc = addr,
+                        (Some(addr1), Some(addr2)) => {
+                            stack.push((addr2, sp, state.clone()));
+                            pc = addr1;
+                        }
+                        (None, None) => return Err(EvalError::InvalidContext),
+                    }
+                }
+                Instruction::RepeatInc(reg, limit) => {
+                    let count = get_counter(&state.counters, *reg)?;
+                    state.counters[*reg] = (count + 1).min(*limit);
+                    safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+                }
+                Instruction::AnchorStart
+                | Instruction::AnchorEnd
+                | Instruction::AnchorLineStart
+                | Instruction::AnchorLineEnd
+                | Instruction::WordBoundary(_, _)
+                | Instruction::AnchorContinue => {
+                    let around = Around::at(line.chars, sp);
+                    if is_anchor_satisfied(next, sp, around, line.window_start, line.scan_start) {
+                        safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+                    } else {
+                        continue 'backtrack;
+                    }
+                }
+                Instruction::Save(slot) => {
+                    // 状態は分岐ごとに複製するため、バックトラック時には元のスロットに戻る
+                    save_slot(&mut state.slots, *slot, sp);
+                    safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+                }
+                Instruction::Nop => {
+                    safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+                }
+                Instruction::CheckGroup(group, matched) => {
+                    if is_group_matched(&state.slots, *group) == *matched {
+                        safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+                    } else {
+                        continue 'backtrack;
+                    }
+                }
+                Instruction::LookBehind(_, _, _) => {
+                    if is_look_satisfied(next, line, sp)? {
+                        safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+                    } else {
+                        continue 'backtrack;
+                    }
+                }
+                Instruction::JumpRel(_)
+                | Instruction::SplitRel(_, _)
+                | Instruction::RepeatCheckRel(_, _, _, _) => return Err(EvalError::InvalidContext),
+            }
+        }
+    }
+
+    Ok(matched)
+}
+
+/// 幅優先探索で実行するスレッド
+///
+/// スレッドの同一性は pc と counters で判定し、slots は比較しない
+#[derive(Debug, Clone, Default)]
+struct Thread {
+    pc: usize,                 // プログラムカウンタ
+    counters: Vec<usize>,      // {n,m} のループのカウンタ
+    slots: Vec<Option<usize>>, // キャプチャグループのスロット
+}
+
+/// 幅優先探索の1つの位置で記録できる、カウンタを持つスレッドの状態の数の上限
+///
+/// ((a?){100}){100} のように {n,m} を入れ子にすると、状態の数はカウンタの取り得る値の積となるため、
+/// 上限を超える場合はメモリを使い果たす前に EvalError::TooManyStates とする
+const MAX_COUNTER_STATES: usize = 1_000_000;
+
+/// 幅優先探索の各位置で、追加したスレッドの状態を記録する集合
+///
+/// カウンタを持たないスレッドは pc のみで判定できるため、ハッシュを計算せずに
+/// pc ごとに記録した世代の番号で判定する
+#[derive(Debug)]
+struct Visited {
+    generations: Vec<usize>, // pc ごとの、最後にスレッドを追加した世代
+    generation: usize,       // 現在の位置の世代。0 は未追加を表す
+    with_counters: HashSet<(usize, Vec<usize>)>, // カウンタを持つスレッドの pc とカウンタ
+}
+
+impl Visited {
+    fn new(len: usize) -> Self {
+        Visited {
+            generations: vec![0; len],
+            generation: 1,
+            with_counters: HashSet::new(),
+        }
+    }
+
+    /// スレッドの状態を記録し、既に記録していた場合は偽を返す
+    ///
+    /// カウンタを持つスレッドの状態の数が MAX_COUNTER_STATES を超える場合はエラー
+    fn insert(&mut self, thread: &Thread) -> Result<bool, EvalError> {
+        if !thread.counters.is_empty() {
+            let key = (thread.pc, thread.counters.clone());
+            if self.with_counters.len() >= MAX_COUNTER_STATES && !self.with_counters.contains(&key)
+            {
+                return Err(EvalError::TooManyStates);
+            }
+            return Ok(self.with_counters.insert(key));
+        }
+        let inserted = match self.generations.get_mut(thread.pc) {
+            Some(g) if *g == self.generation => false,
+            Some(g) => {
+                *g = self.generation;
+                true
+            }
+            None => true, // 範囲外の pc は、呼び出し元でエラーとする
+        };
+        Ok(inserted)
+    }
+
+    /// 次の位置のために、記録をすべて消去
+    fn clear(&mut self) {
+        self.generation += 1;
+        if !self.with_counters.is_empty() {
+            self.with_counters.clear();
+        }
+    }
+}
+
+/// スレッドを追加
+///
+/// 文字を消費しない命令をたどり、文字を消費する命令か Match 命令に到達した
+/// スレッドを優先度順に threads へ追加する。
+/// ((a?){100}){100} のような深い繰り返しでもネイティブのスタックを使わないよう、
+/// たどるスレッドは明示的なスタックに積み、優先度の高い方を後に積む
+fn add_thread(
+    inst: &[Instruction],
+    line: Option<Input>,
+    sp: usize,
+    around: Around,
+    threads: &mut Vec<Thread>,
+    visited: &mut Visited,
+    thread: Thread,
+) -> Result<(), EvalError> {
+    let mut stack = vec![thread];
+
+    while let Some(mut thread) = stack.pop() {
+        // 同じ状態のスレッドは、先に追加した優先度の高い方のみを残す
+        if !visited.insert(&thread)? {
+            continue;
+        }
+
+        let next = if let Some(i) = inst.get(thread.pc) {
+            i
+        } else {
+            return Err(EvalError::InvalidPC);
+        };
+
+        // 相対アドレスの命令は、絶対アドレスの命令に変換して実行する
+        let resolved = next.to_absolute(thread.pc).ok_or(EvalError::InvalidPC)?;
+        let next = resolved.as_ref();
+
+        match next {
+            Instruction::Jump(addr) => {
+                thread.pc = *addr;
+                stack.push(thread);
+            }
+            Instruction::Split(addr1, addr2) => {
+                let mut first = thread.clone();
+                first.pc = *addr1;
+                thread.pc = *addr2;
+                stack.push(thread);
+                stack.push(first);
+            }
+            Instruction::RepeatInit(reg) => {
+                init_counter(&mut thread.counters, *reg);
+                safe_add(&mut thread.pc, &1, || EvalError::PCOverFlow)?;
+                stack.push(thread);
+            }
+            Instruction::RepeatCheck(reg, min, max, exit) => {
+                let count = get_counter(&thread.counters, *reg)?;
+                let mut body = thread.pc;
+                safe_add(&mut body, &1, || EvalError::PCOverFlow)?;
+                let (body, exit) = repeat_targets(count, *min, *max, body, *exit);
+                if let Some(addr) = exit {
+                    // ループを脱出したカウンタは不要なため 0 に戻し、同じ状態のスレッドをまとめる
+                    let mut t = thread.clone();
+                    t.counters[*reg] = 0;
+                    t.pc = addr;
+                    stack.push(t);
+                }
+                if let Some(addr) = body {
+                    thread.pc = addr;
+                    stack.push(thread);
+                }
+            }
+            Instruction::RepeatInc(reg, limit) => {
+                let count = get_counter(&thread.counters, *reg)?;
+                thread.counters[*reg] = (count + 1).min(*limit);
+                safe_add(&mut thread.pc, &1, || EvalError::PCOverFlow)?;
+                stack.push(thread);
+            }
+            Instruction::AnchorStart
+            | Instruction::AnchorEnd
+            | Instruction::AnchorLineStart
+            | Instruction::AnchorLineEnd
+            | Instruction::WordBoundary(_, _)
+            | Instruction::AnchorContinue => {
+                // 条件を満たさない場合、スレッドはここで終了する
+                // 入力全体を保持しない評価では、入力の先頭を範囲と走査の開始位置とする
+                let window_start = line.map_or(0, |line| line.window_start);
+                let scan_start = line.map_or(0, |line| line.scan_start);
+                if is_anchor_satisfied(next, sp, around, window_start, scan_start) {
+                    safe_add(&mut thread.pc, &1, || EvalError::PCOverFlow)?;
+                    stack.push(thread);
+                }
+            }
+            Instruction::Save(slot) => {
+                save_slot(&mut thread.slots, *slot, sp);
+                safe_add(&mut thread.pc, &1, || EvalError::PCOverFlow)?;
+                stack.push(thread);
+            }
+            Instruction::Nop => {
+                safe_add(&mut thread.pc, &1, || EvalError::PCOverFlow)?;
+                stack.push(thread);
+            }
+            Instruction::LookBehind(_, _, _) => {
+                // 条件を満たさない場合、スレッドはここで終了する
+                let line = line.ok_or(EvalError::NoLookBehind)?;
+                if is_look_satisfied(next, line, sp)? {
+                    safe_add(&mut thread.pc, &1, || EvalError::PCOverFlow)?;
+                    stack.push(thread);
+                }
+            }
+            // 同じ pc のスレッドはスロットによらず1つにまとめるため、
+            // スロットに依存する条件分岐は正しく評価できない
+            Instruction::CheckGroup(_, _) => return Err(EvalError::UnsupportedConditional),
+            Instruction::JumpRel(_)
+            | Instruction::SplitRel(_, _)
+            | Instruction::RepeatCheckRel(_, _, _, _) => return Err(EvalError::InvalidContext),
+            Instruction::Char(_)
+            | Instruction::Range(_, _)
+            | Instruction::AsciiClass(_)
+            | Instruction::Class(_)
+            | Instruction::Predicate(_, _)
+            | Instruction::AnyChar(_)
+            | Instruction::Match
+            | Instruction::MatchId(_) => threads.push(thread),
+        }
+    }
+
+    Ok(())
+}
+
+/// 幅優先探索で評価
+///
+/// すべてのスレッドを1文字ずつ同時に進め、スレッドが Match に到達するたびに
+/// その位置、パターンの番号、スロットを引数として on_match を呼び出す。
+/// on_match が偽を返した場合は、そのスレッドより優先度の低いスレッドを破棄する。
+fn run_width<F>(
+    inst: &[Instruction],
+    line: Input,
+    mut sp: usize,
+    budget: &mut Budget,
+    mut on_match: F,
+) -> Result<(), EvalError>
+where
+    F: FnMut(usize, usize, &[Option<usize>]) -> bool,
+{
+    let start = sp;
+    let mut clist = Vec::new(); // 現在の位置で実行中のスレッド
+    let mut nlist = Vec::new(); // 次の位置で実行するスレッド
+    let mut visited = Visited::new(inst.len());
+
+    let thread = Thread::default();
+    add_thread(
+        inst,
+        Some(line),
+        sp,
+        Around::at(line.chars, sp),
+        &mut clist,
+        &mut visited,
+        thread,
+    )?;
+
+    while !clist.is_empty() {
+        visited.clear();
+
+        for thread in clist.drain(..) {
+            budget.step()?;
+            match &inst[thread.pc] {
+                i @ (Instruction::Char(_)
+                | Instruction::Range(_, _)
+                | Instruction::AsciiClass(_)
+                | Instruction::Class(_)
+                | Instruction::Predicate(_, _)
+                | Instruction::AnyChar(_)) => {
+                    // 上限の文字数を消費したスレッドは、それ以上進めない
+                    if !line.can_consume(start, sp) {
+                        continue;
+                    }
+                    if let Some(next_pc) = step_char(i, thread.pc, line, sp)? {
+                        let mut next = thread;
+                        next.pc = next_pc;
+                        add_thread(
+                            inst,
+                            Some(line),
+                            sp + 1,
+                            Around::at(line.chars, sp + 1),
+                            &mut nlist,
+                            &mut visited,
+                            next,
+                        )?;
+                    }
+                }
+                i @ (Instruction::Match | Instruction::MatchId(_)) => {
+                    if !on_match(sp, match_id(i).unwrap_or(0), &thread.slots) {
+                        // これより優先度の低いスレッドは破棄する
+                        break;
+                    }
+                }
+                _ => return Err(EvalError::InvalidContext),
+            }
+        }
+
+        swap(&mut clist, &mut nlist);
+        nlist.clear();
+        safe_add(&mut sp, &1, || EvalError::SPOverFlow)?;
+    }
+
+    Ok(())
+}
+
+/// 文字のイテレータから1文字ずつ読み込みながら幅優先探索で評価
+///
+/// 各位置で新たなスレッドを開始して1回の走査で、いずれかの位置から始まるマッチを探す。
+/// スレッドが match 命令に到達するたびにパターンの番号を引数として on_match を呼び出し、
+/// on_match が偽を返した場合は評価を終了する。
+/// 入力全体を保持しない場合は line を None とし、後読みを含む命令列はエラーとなる。
+fn run_stream<I, F>(
+    inst: &[Instruction],
+    chars: I,
+    line: Option<Input>,
+    mut on_match: F,
+) -> Result<(), EvalError>
+where
+    I: Iterator<Item = char>,
+    F: FnMut(usize) -> bool,
+{
+    let mut chars = chars.peekable();
+    let mut clist = Vec::new(); // 現在の位置で実行中のスレッド
+    let mut nlist = Vec::new(); // 次の位置で実行するスレッド
+    let mut visited = Visited::new(inst.len());
+    let mut sp = 0;
+    let mut around = Around {
+        prev: None,
+        next: chars.peek().copied(),
+    };
+
+    loop {
+        // 現在の位置から始まるスレッドを、最も低い優先度で追加
+        let thread = Thread::default();
+        add_thread(inst, line, sp, around, &mut clist, &mut visited, thread)?;
+
+        let c = chars.next();
+        let next_around = Around {
+            prev: c,
+            next: chars.peek().copied(),
+        };
+        visited.clear();
+
+        for thread in clist.drain(..) {
+            match &inst[thread.pc] {
+                i @ (Instruction::Char(_)
+                | Instruction::Range(_, _)
+                | Instruction::AsciiClass(_)
+                | Instruction::Class(_)
+                | Instruction::Predicate(_, _)
+                | Instruction::AnyChar(_)) => {
+                    if c.is_some_and(|c| is_char_matched(i, c)) {
+                        let mut next = thread;
+                        safe_add(&mut next.pc, &1, || EvalError::PCOverFlow)?;
+                        add_thread(
+                            inst,
+                            line,
+                            sp + 1,
+                            next_around,
+                            &mut nlist,
+                            &mut visited,
+                            next,
+                        )?;
+                    }
+                }
+                i @ (Instruction::Match | Instruction::MatchId(_)) => {
+                    if !on_match(match_id(i).unwrap_or(0)) {
+                        return Ok(());
+                    }
+                }
+                _ => return Err(EvalError::InvalidContext),
+            }
+        }
+
+        if c.is_none() {
+            return Ok(());
+        }
+
+        swap(&mut clist, &mut nlist);
+        safe_add(&mut sp, &1, || EvalError::SPOverFlow)?;
+        around = next_around;
+    }
+}
+
+/// 文字のイテレータから1文字ずつ読み込みながら幅優先探索で評価し、
+/// いずれかの位置から始まるマッチがあるかを判定
+#[cfg(feature = "std")]
+pub fn eval_stream<I>(inst: &[Instruction], chars: I) -> Result<bool, EvalError>
+where
+    I: Iterator<Item = char>,
+{
+    let mut matched = false;
+    run_stream(inst, chars, None, |_| {
+        matched = true;
+        false
+    })?;
+    Ok(matched)
+}
+
+/// 入力文字列を先頭から1回走査して幅優先探索で評価し、
+/// いずれかの位置でマッチしたパターンの番号を昇順に返す
+pub fn eval_set(inst: &[Instruction], line: Input) -> Result<Vec<usize>, EvalError> {
+    let mut ids = Vec::new();
+    run_stream(inst, line.chars.iter().copied(), Some(line), |id| {
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+        true
+    })?;
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+/// 入力文字列の sp 番目の文字から幅優先探索で評価し、
+/// sp から始まるマッチを持つパターンの番号と、そのパターンの最も長いマッチの終了位置を返す
+///
+/// パターンの番号の昇順に並べる
+pub fn eval_set_at(
+    inst: &[Instruction],
+    line: Input,
+    sp: usize,
+) -> Result<Vec<(usize, usize)>, EvalError> {
+    let mut ends: Vec<(usize, usize)> = Vec::new();
+    run_width(inst, line, sp, &mut Budget::new(None), |end, id, _| {
+        // スレッドは位置の昇順にマッチするため、後のマッチほど長い
+        if let Some(e) = ends.iter_mut().find(|(i, _)| *i == id) {
+            e.1 = end;
+        } else {
+            ends.push((id, end));
+        }
+        true
+    })?;
+    ends.sort_unstable();
+    Ok(ends)
+}
+
+/// 幅優先探索で評価
+///
+/// longest が偽の場合は、最も優先度の高いスレッドのマッチを返す。
+/// longest が真の場合は、最も長いマッチのうち最も優先度の高いものを返す。
+fn eval_width(
+    inst: &[Instruction],
+    line: Input,
+    sp: usize,
+    longest: bool,
+    budget: &mut Budget,
+) -> Result<Option<Matched>, EvalError> {
+    let mut matched: Option<Matched> = None;
+    run_width(inst, line, sp, budget, |end, id, slots| {
+        if matched.as_ref().is_none_or(|m| end > m.end) {
+            matched = Some(Matched {
+                end,
+                id,
+                slots: slots.to_vec(),
+            });
+        }
+        longest
+    })?;
+    Ok(matched)
+}
+
+/// 入力文字列の sp 番目の文字から幅優先探索で評価し、
+/// 到達し得るすべてのマッチの終了位置とパターンの番号を、終了位置の昇順に返す
+pub fn eval_all(
+    inst: &[Instruction],
+    line: Input,
+    sp: usize,
+) -> Result<Vec<(usize, usize)>, EvalError> {
+    let mut ends: Vec<(usize, usize)> = Vec::new();
+    run_width(inst, line, sp, &mut Budget::new(None), |end, id, _| {
+        // 同じ位置で複数のスレッドがマッチした場合は、最も優先度の高いものにまとめる
+        if ends.last().is_none_or(|(e, _)| *e != end) {
+            ends.push((end, id));
+        }
+        true
+    })?;
+    Ok(ends)
+}
+
+/// 入力文字列の sp 番目の文字から深さ優先探索で評価し、実行した命令を記録する
+///
+/// 命令を実行するたびに、その pc と sp を引数として trace を呼び出す。
+/// 記録しない通常の評価は eval で行う
+pub fn eval_traced<T>(
+    inst: &[Instruction],
+    line: Input,
+    sp: usize,
+    longest: bool,
+    budget: &mut Budget,
+    trace: &mut T,
+) -> Result<Option<Matched>, EvalError>
+where
+    T: FnMut(usize, usize),
+{
+    eval_depth(inst, line, sp, longest, budget, trace)
+}
+
+/// 入力文字列の sp 番目の文字からマッチングを行う
+///
+/// マッチした場合はマッチの終了位置とスロットを返す。
+/// 実行できる命令の数の残りを budget から消費し、残りがなくなった場合はエラー
+pub fn eval(
+    inst: &[Instruction],
+    line: Input,
+    sp: usize,
+    is_depth: bool,
+    longest: bool,
+    budget: &mut Budget,
+) -> Result<Option<Matched>, EvalError> {
+    if is_depth {
+        eval_depth(inst, line, sp, longest, budget, &mut |_, _| ())
+    } else {
+        eval_width(inst, line, sp, longest, budget)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{codegen, parser};
+
+    fn code(expr: &str) -> Vec<Instruction> {
+        let ast = parser::parse(expr, parser::Flags::default()).unwrap().ast;
+        codegen::get_code(&ast, true, false).unwrap()
+    }
+
+    /// 入力の先頭から評価し、マッチした場合は終了位置とスロットを返す
+    fn run(inst: &[Instruction], line: &str, is_depth: bool) -> Option<Matched> {
+        let chars: Vec<char> = line.chars().collect();
+        let input = Input {
+            chars: &chars,
+            boundaries: None,
+            scan_start: 0,
+            window_start: 0,
+            max_len: None,
+        };
+        eval(inst, input, 0, is_depth, false, &mut Budget(None)).unwrap()
+    }
+
+    #[test]
+    fn depth_same_as_width() {
+        let cases = [
+            ("a(b|c)*d", "abcbd"),
+            ("(a*)(a*)", "aaa"),
+            ("(a|ab)(c|bcd)", "abcd"),
+            ("(a+)(a*)b", "aaab"),
+            ("a{2,3}", "aaaa"),
+            ("(x)?y", "y"),
+            ("((a)|b)+", "abab"),
+            ("$", ""),
+            ("a", "b"),
+        ];
+        for (expr, line) in cases {
+            let inst = code(expr);
+            assert_eq!(run(&inst, line, true), run(&inst, line, false), "{expr}");
+        }
+    }
+
+    #[test]
+    fn deep_nesting() {
+        // ネイティブのスタックを使わないため、深い入れ子や長い繰り返しでもオーバーフローしない
+        let depth = parser::DEFAULT_MAX_NEST - 1;
+        let expr = format!("{}a{}", "(".repeat(depth), ")".repeat(depth));
+        let m = run(&code(&expr), "a", true).unwrap();
+        assert_eq!(m.end, 1);
+        assert_eq!(m.slots.len(), (depth + 1) * 2);
+
+        let line = "ab".repeat(50_000);
+        let m = run(&code("(?:a|b)*$"), &line, true).unwrap();
+        assert_eq!(m.end, 100_000);
+    }
+
+    #[test]
+    fn visited() {
+        let thread = |pc, counters: Vec<usize>| Thread {
+            pc,
+            counters,
+            slots: Vec::new(),
+        };
+        let mut visited = Visited::new(4);
+        // カウンタを持たないスレッドは世代の配列で、持つスレッドは HashSet で判定する
+        let insert = |visited: &mut Visited, t: Thread| visited.insert(&t).unwrap();
+        for _ in 0..3 {
+            assert!(insert(&mut visited, thread(1, vec![])));
+            assert!(!insert(&mut visited, thread(1, vec![])));
+            assert!(insert(&mut visited, thread(2, vec![])));
+            assert!(insert(&mut visited, thread(1, vec![0])));
+            assert!(!insert(&mut visited, thread(1, vec![0])));
+            assert!(insert(&mut visited, thread(1, vec![1])));
+            visited.clear();
+        }
+        assert!(insert(&mut visited, thread(9, vec![])));
+    }
+
+    #[test]
+    fn visited_same_as_depth() {
+        // {n,m} はカウンタを持つスレッドとして、それ以外は pc のみで記録する
+        let cases = [
+            ("(a|b){2,3}c", "ababc"),
+            ("x{3}", "xxxx"),
+            ("(a{1,2}b){2}", "aabab"),
+            ("(ab){0,2}$", "ababab"),
+            ("(a|b)*c", "abac"),
+            ("(a*)*b", "aab"),
+        ];
+        for (expr, line) in cases {
+            let inst = code(expr);
+            let has_counter = inst.iter().any(|i| matches!(i, Instruction::RepeatInit(_)));
+            assert_eq!(has_counter, expr.contains('{'), "{expr}");
+            for start in 0..=line.len() {
+                let chars: Vec<char> = line.chars().collect();
+                let input = Input {
+                    chars: &chars,
+                    boundaries: None,
+                    scan_start: 0,
+                    window_start: 0,
+                    max_len: None,
+                };
+                let depth = eval(&inst, input, start, true, false, &mut Budget(None)).unwrap();
+                let width = eval(&inst, input, start, false, false, &mut Budget(None)).unwrap();
+                assert_eq!(depth, width, "{expr} {start}");
+            }
+        }
+    }
+
+    #[test]
+    fn drop_long_branches() {
+        // 再帰して解放すると、ネイティブのスタックがオーバーフローする長さ
+        let mut branches = None;
+        for pc in 0..1_000_000 {
+            branches = Some(Rc::new(Branch {
+                pc,
+                counters: Vec::new(),
+                prev: branches,
+            }));
+        }
+        drop(branches);
+    }
+}
@@ -0,0 +1,148 @@
+//! 文字列の選択のみからなるパターンを、命令列を用いずにトライ木で探索
+use super::parser::AST;
+
+/// トライ木の節
+#[derive(Debug, Clone, Default)]
+struct Node {
+    next: Vec<(char, usize)>, // 文字の昇順に並んだ、子の節への遷移
+    branch: Option<usize>,    // この節で終わる選択肢のうち、最も優先度の高いものの番号
+}
+
+/// foo|bar|baz のような、文字列の選択を表すトライ木
+///
+/// 選択肢はパターン中に現れる順に 0 から番号を付け、番号が小さいほど優先度が高い
+#[derive(Debug, Clone)]
+pub struct Literals {
+    nodes: Vec<Node>, // 0 番目が根の節
+}
+
+/// 式を | で区切った選択肢の文字列を、パターン中に現れる順に branches に追加
+///
+/// 文字の連続でない選択肢を含む場合や、空の選択肢を含む場合は偽を返す
+fn collect_branches(ast: &AST, branches: &mut Vec<Vec<char>>) -> bool {
+    match ast {
+        AST::Or(e1, e2) => collect_branches(e1, branches) && collect_branches(e2, branches),
+        AST::Char(c) => {
+            branches.push(vec![*c]);
+            true
+        }
+        AST::Seq(v) if !v.is_empty() => {
+            let mut branch = Vec::new();
+            for e in v {
+                if let AST::Char(c) = e {
+                    branch.push(*c);
+                } else {
+                    return false;
+                }
+            }
+            branches.push(branch);
+            true
+        }
+        _ => false,
+    }
+}
+
+impl Literals {
+    /// 式が2つ以上の文字列の選択である場合に、トライ木を生成
+    ///
+    /// キャプチャグループ、文字クラス、アンカーなどを含む場合は None を返す
+    pub fn from_ast(ast: &AST) -> Option<Literals> {
+        if !matches!(ast, AST::Or(_, _)) {
+            return None;
+        }
+        let mut branches = Vec::new();
+        if !collect_branches(ast, &mut branches) {
+            return None;
+        }
+
+        let mut nodes = vec![Node::default()];
+        for (i, branch) in branches.iter().enumerate() {
+            let mut node = 0;
+            for c in branch {
+                node = match nodes[node].next.binary_search_by_key(c, |(c, _)| *c) {
+                    Ok(j) => nodes[node].next[j].1,
+                    Err(j) => {
+                        let child = nodes.len();
+                        nodes[node].next.insert(j, (*c, child));
+                        nodes.push(Node::default());
+                        child
+                    }
+                };
+            }
+            // 同じ文字列の選択肢が複数ある場合は、先に現れた方を残す
+            nodes[node].branch.get_or_insert(i);
+        }
+
+        Some(Literals { nodes })
+    }
+
+    /// chars の start 文字目から始まるマッチの終了位置を返す
+    ///
+    /// longest が偽の場合は最も優先度の高い選択肢のマッチを、
+    /// 真の場合は最も長いマッチを返す
+    fn match_at(&self, chars: &[char], start: usize, longest: bool) -> Option<usize> {
+        let mut best: Option<(usize, usize)> = None; // 選択肢の番号と終了位置
+        let mut node = 0;
+        let mut sp = start;
+        loop {
+            if let Some(branch) = self.nodes[node].branch {
+                if longest || best.is_none_or(|(b, _)| branch < b) {
+                    best = Some((branch, sp));
+                }
+            }
+            let c = if let Some(c) = chars.get(sp) {
+                c
+            } else {
+                break;
+            };
+            match self.nodes[node].next.binary_search_by_key(c, |(c, _)| *c) {
+                Ok(j) => node = self.nodes[node].next[j].1,
+                Err(_) => break,
+            }
+            sp += 1;
+        }
+        best.map(|(_, end)| end)
+    }
+
+    /// chars の from 文字目以降から始まる、最も左にあるマッチの開始位置と終了位置を返す
+    pub fn find_at(&self, chars: &[char], from: usize, longest: bool) -> Option<(usize, usize)> {
+        (from..chars.len())
+            .find_map(|start| self.match_at(chars, start, longest).map(|end| (start, end)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::parser::{self, Flags};
+
+    fn literals(expr: &str) -> Option<Literals> {
+        Literals::from_ast(&parser::parse(expr, Flags::default()).unwrap().ast)
+    }
+
+    #[test]
+    fn from_ast() {
+        assert!(literals("foo|bar|baz").is_some());
+        assert!(literals("a|bc").is_some());
+        assert!(literals("foo").is_none());
+        assert!(literals("foo|b(a)r").is_none());
+        assert!(literals("foo|ba[rz]").is_none());
+        assert!(literals("foo|").is_none());
+        assert!(literals("^foo|bar").is_none());
+    }
+
+    #[test]
+    fn find_at() {
+        let chars: Vec<char> = "xbazfoobar".chars().collect();
+        let lit = literals("foo|bar|baz").unwrap();
+        assert_eq!(lit.find_at(&chars, 0, false), Some((1, 4)));
+        assert_eq!(lit.find_at(&chars, 2, false), Some((4, 7)));
+        assert_eq!(lit.find_at(&chars, 8, false), None);
+
+        // 同じ位置から始まる選択肢は、最左最初では先の選択肢を、最左最長では長い方を選ぶ
+        let chars: Vec<char> = "abc".chars().collect();
+        let lit = literals("ab|abc").unwrap();
+        assert_eq!(lit.find_at(&chars, 0, false), Some((0, 2)));
+        assert_eq!(lit.find_at(&chars, 0, true), Some((0, 3)));
+    }
+}
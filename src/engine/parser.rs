@@ -1,32 +1,380 @@
 //! 正規表現をパースし、抽象構文木に変換
+#[cfg(feature = "unicode")]
+use super::unicode_tables;
+use super::visit;
 use std::{
     error::Error,
     fmt::{self, Display},
+    iter::Peekable,
     mem::take, // take はある変数からの所有権の取得と、その変数の初期化を同時に行う
 };
 
 /// 抽象構文木を表現するための型
-/// ```
+/// ```text
 /// AST::Seq(vec![AST::Char('a'), AST::Char('b'), AST::Char('c')])
 /// ```
-#[derive(Debug)]
+///
+/// AST::Predicate の関数は関数ポインタのアドレスで比較するため、
+/// 同じ関数でもコード生成単位が異なると等しくならない場合がある
+#[allow(
+    clippy::upper_case_acronyms,
+    unpredictable_function_pointer_comparisons
+)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AST {
     Char(char),
+    AnyChar(bool),                // . 任意の1文字。偽の場合は改行を除く (s フラグで真)
+    Class(Vec<(char, char)>),     // 文字クラス。昇順に並び、互いに重ならない文字の範囲の列
+    Predicate(String, Predicate), // \p{name} 名前と、文字がマッチするかを判定する関数
     Plus(Box<AST>),
     Star(Box<AST>),
     Question(Box<AST>),
+    Repeat(Box<AST>, usize, Option<usize>), // {n,m} による繰り返し。上限が None の場合は無制限
     Or(Box<AST>, Box<AST>),
-    Seq(Vec<AST>), // 正規表現の列を表現する (sequence)
+    Seq(Vec<AST>),                            // 正規表現の列を表現する (sequence)
+    Capture(Box<AST>, usize, Option<String>), // キャプチャグループ。グループ番号と、名前付きグループの場合は名前
+    Look {
+        ahead: bool,  // 先読みであるか。偽の場合は後読み
+        negate: bool, // 否定であるか
+        inner: Box<AST>,
+    },
+    Empty,                    // 空の正規表現 (a| の右辺など)
+    AnchorStart,              // ^ 入力の先頭
+    AnchorEnd,                // $ 入力の末尾
+    AnchorLineStart,          // m フラグの ^ 入力または行の先頭
+    AnchorLineEnd,            // m フラグの $ 入力または行の末尾
+    WordBoundary(bool, bool), // \b 単語境界、\B 単語境界以外。否定であるかと、Unicode の \w で判定するか
+    AnchorContinue,           // \G 走査の開始位置。find_iter では直前のマッチの終了位置
+    ResetStart,               // \K 報告するマッチの開始位置を現在の位置に設定
+    Conditional {
+        group: usize,  // 条件とするキャプチャグループの番号
+        yes: Box<AST>, // グループがマッチした場合の式
+        no: Box<AST>,  // グループがマッチしていない場合の式
+    },
+}
+
+impl AST {
+    /// 1文字にマッチする式であるかを判定
+    pub fn is_literal(&self) -> bool {
+        matches!(
+            self,
+            AST::Char(_) | AST::AnyChar(_) | AST::Class(_) | AST::Predicate(_, _)
+        )
+    }
+
+    /// 限量子であるかを判定
+    pub fn is_quantifier(&self) -> bool {
+        matches!(
+            self,
+            AST::Plus(_) | AST::Star(_) | AST::Question(_) | AST::Repeat(_, _, _)
+        )
+    }
+
+    /// アンカーや後読みのような、文字を消費せずに位置の条件を検査する表明であるかを判定
+    pub fn is_assertion(&self) -> bool {
+        matches!(
+            self,
+            AST::AnchorStart
+                | AST::AnchorEnd
+                | AST::AnchorLineStart
+                | AST::AnchorLineEnd
+                | AST::WordBoundary(_, _)
+                | AST::AnchorContinue
+                | AST::ResetStart
+                | AST::Look { .. }
+        )
+    }
+
+    /// 空文字列にマッチし得るかを判定
+    pub fn is_nullable(&self) -> bool {
+        match self {
+            AST::Plus(e) => e.is_nullable(),
+            AST::Repeat(e, min, _) => *min == 0 || e.is_nullable(),
+            AST::Star(_) | AST::Question(_) | AST::Empty => true,
+            AST::Or(e1, e2) => e1.is_nullable() || e2.is_nullable(),
+            AST::Seq(v) => v.iter().all(|e| e.is_nullable()),
+            AST::Capture(e, _, _) => e.is_nullable(),
+            AST::Conditional { yes, no, .. } => yes.is_nullable() || no.is_nullable(),
+            // 残りは1文字の式と表明で、表明は文字を消費しない
+            _ => !self.is_literal(),
+        }
+    }
+
+    /// 常に同じ文字数にマッチする場合は、その文字数を返す
+    ///
+    /// 繰り返し回数が決まっていない限量子を含む場合や、
+    /// a|bc のように Or の両辺の長さが異なる場合は None を返す
+    pub fn fixed_len(&self) -> Option<usize> {
+        match self {
+            AST::Char(_) | AST::AnyChar(_) | AST::Class(_) | AST::Predicate(_, _) => Some(1),
+            AST::Empty | AST::AnchorStart | AST::AnchorEnd | AST::AnchorContinue => Some(0),
+            AST::AnchorLineStart | AST::AnchorLineEnd | AST::WordBoundary(_, _) => Some(0),
+            AST::ResetStart => Some(0),
+            AST::Look { .. } => Some(0),
+            AST::Plus(e) | AST::Star(e) | AST::Question(e) => match e.fixed_len() {
+                Some(0) => Some(0),
+                _ => None,
+            },
+            AST::Repeat(e, min, Some(max)) if min == max => e.fixed_len()?.checked_mul(*min),
+            AST::Repeat(e, _, _) => match e.fixed_len() {
+                Some(0) => Some(0),
+                _ => None,
+            },
+            AST::Or(e1, e2) => {
+                let len = e1.fixed_len()?;
+                (e2.fixed_len()? == len).then_some(len)
+            }
+            AST::Seq(v) => v
+                .iter()
+                .try_fold(0usize, |sum, e| sum.checked_add(e.fixed_len()?)),
+            AST::Capture(e, _, _) => e.fixed_len(),
+            AST::Conditional { yes, no, .. } => {
+                let len = yes.fixed_len()?;
+                (no.fixed_len()? == len).then_some(len)
+            }
+        }
+    }
+
+    /// 逆順の入力にマッチする式を返す
+    ///
+    /// 連接の順序を反転し、^ と $ を入れ替え、先読みと後読みを入れ替える。
+    /// 例えば ^ab$ は、ba にマッチする ^ba$ となる
+    pub fn reversed(&self) -> AST {
+        let rev = |e: &AST| Box::new(e.reversed());
+        match self {
+            AST::Plus(e) => AST::Plus(rev(e)),
+            AST::Star(e) => AST::Star(rev(e)),
+            AST::Question(e) => AST::Question(rev(e)),
+            AST::Repeat(e, min, max) => AST::Repeat(rev(e), *min, *max),
+            AST::Or(e1, e2) => AST::Or(rev(e1), rev(e2)),
+            AST::Seq(v) => AST::Seq(v.iter().rev().map(|e| e.reversed()).collect()),
+            AST::Capture(e, index, name) => AST::Capture(rev(e), *index, name.clone()),
+            AST::Look {
+                ahead,
+                negate,
+                inner,
+            } => AST::Look {
+                ahead: !ahead,
+                negate: *negate,
+                inner: rev(inner),
+            },
+            // グループがマッチしたかは入力の向きによって変わるため、逆順の命令列には用いない
+            AST::Conditional { group, yes, no } => AST::Conditional {
+                group: *group,
+                yes: rev(yes),
+                no: rev(no),
+            },
+            AST::AnchorStart => AST::AnchorEnd,
+            AST::AnchorEnd => AST::AnchorStart,
+            AST::AnchorLineStart => AST::AnchorLineEnd,
+            AST::AnchorLineEnd => AST::AnchorLineStart,
+            AST::Char(_)
+            | AST::AnyChar(_)
+            | AST::Class(_)
+            | AST::Predicate(_, _)
+            | AST::Empty
+            | AST::AnchorContinue
+            | AST::WordBoundary(_, _)
+            | AST::ResetStart => self.clone(),
+        }
+    }
+
+    /// 式が Or の場合は、直接連なる Or を含めた選択肢の数を返す
+    ///
+    /// fold_or は a|b|c|d を AST::Or(AST::Or(a, b), AST::Or(c, d)) とするため、両側の Or をたどる。
+    /// Or でない場合は 1 を返す
+    pub fn or_width(&self) -> usize {
+        match self {
+            AST::Or(e1, e2) => e1.or_width() + e2.or_width(),
+            _ => 1,
+        }
+    }
+
+    /// マッチが必ず先頭に含む文字列を prefix に追加
+    ///
+    /// 式全体が文字列のみからなり、続く式の文字列も prefix に追加できる場合は真を返す。
+    /// アンカーと空の式は文字を消費しないため読み飛ばし、先読みと後読みはそこで打ち切る。
+    /// \K ではマッチの開始位置が変わるため、それまでに追加した文字列を取り除く
+    pub fn literal_prefix(&self, prefix: &mut String) -> bool {
+        match self {
+            AST::Char(c) => {
+                prefix.push(*c);
+                true
+            }
+            AST::Empty => true,
+            AST::ResetStart => {
+                prefix.clear();
+                true
+            }
+            AST::Look { .. } => false,
+            e if e.is_assertion() => true,
+            AST::Seq(v) => v.iter().all(|e| e.literal_prefix(prefix)),
+            AST::Capture(e, _, _) => e.literal_prefix(prefix),
+            AST::Plus(e) => {
+                // 少なくとも1回はマッチするため、1回目の文字列は必ず含まれる
+                e.literal_prefix(prefix);
+                false
+            }
+            AST::Repeat(e, min, _) if *min > 0 => {
+                e.literal_prefix(prefix);
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// 入力の先頭にのみマッチする、^ から始まる式であるかを判定
+    ///
+    /// ^a|^b のように、Or の両辺が ^ から始まる場合も真となる
+    pub fn is_anchored_start(&self) -> bool {
+        match self {
+            AST::AnchorStart => true,
+            AST::Seq(v) => v.first().is_some_and(|e| e.is_anchored_start()),
+            AST::Capture(e, _, _) => e.is_anchored_start(),
+            AST::Or(e1, e2) => e1.is_anchored_start() && e2.is_anchored_start(),
+            _ => false,
+        }
+    }
+
+    /// 入力の末尾にのみマッチする、$ で終わる式であるかを判定
+    ///
+    /// a$|b$ のように、Or の両辺が $ で終わる場合も真となる
+    pub fn is_anchored_end(&self) -> bool {
+        match self {
+            AST::AnchorEnd => true,
+            AST::Seq(v) => v.last().is_some_and(|e| e.is_anchored_end()),
+            AST::Capture(e, _, _) => e.is_anchored_end(),
+            AST::Or(e1, e2) => e1.is_anchored_end() && e2.is_anchored_end(),
+            _ => false,
+        }
+    }
+
+    /// キャプチャグループの名前をグループ番号順に返す
+    ///
+    /// group_count はキャプチャグループの数で、戻り値の長さは group_count + 1 となる。
+    /// 0 番目はマッチ全体を表すため常に None で、名前のないグループも None となる
+    pub fn capture_names(&self, group_count: usize) -> Vec<Option<String>> {
+        let mut names = vec![None; group_count + 1];
+        visit::walk(self, &mut |e| {
+            if let AST::Capture(_, index, name) = e {
+                if let Some(slot) = names.get_mut(*index) {
+                    slot.clone_from(name);
+                }
+            }
+        });
+        names
+    }
+
+    /// 限量子と等価な {n,m} を、より単純な限量子に置き換える
+    ///
+    /// a{1,1} は a、a{0,1} は a?、a{0,} は a*、a{1,} は a+、a{0,0} は空の式とする。
+    /// ただし a{0,0} がキャプチャグループを含む場合は、グループ番号と名前を残すため置き換えない
+    pub fn simplify_repeat(self) -> AST {
+        let simplify = |e: Box<AST>| Box::new(e.simplify_repeat());
+        match self {
+            AST::Repeat(e, 1, Some(1)) => e.simplify_repeat(),
+            AST::Repeat(e, 0, Some(1)) => AST::Question(simplify(e)),
+            AST::Repeat(e, 0, None) => AST::Star(simplify(e)),
+            AST::Repeat(e, 1, None) => AST::Plus(simplify(e)),
+            AST::Repeat(e, 0, Some(0)) if !e.has_capture() => AST::Empty,
+            AST::Repeat(e, min, max) => AST::Repeat(simplify(e), min, max),
+            AST::Plus(e) => AST::Plus(simplify(e)),
+            AST::Star(e) => AST::Star(simplify(e)),
+            AST::Question(e) => AST::Question(simplify(e)),
+            AST::Or(e1, e2) => AST::Or(simplify(e1), simplify(e2)),
+            AST::Seq(v) => AST::Seq(v.into_iter().map(|e| e.simplify_repeat()).collect()),
+            AST::Capture(e, index, name) => AST::Capture(simplify(e), index, name),
+            AST::Look {
+                ahead,
+                negate,
+                inner,
+            } => AST::Look {
+                ahead,
+                negate,
+                inner: simplify(inner),
+            },
+            AST::Conditional { group, yes, no } => AST::Conditional {
+                group,
+                yes: simplify(yes),
+                no: simplify(no),
+            },
+            e => e,
+        }
+    }
+
+    /// キャプチャグループを含むかを判定
+    fn has_capture(&self) -> bool {
+        let mut found = false;
+        visit::walk(self, &mut |e| found |= matches!(e, AST::Capture(..)));
+        found
+    }
+
+    /// \K を含むかを判定
+    pub fn has_reset_start(&self) -> bool {
+        let mut found = false;
+        visit::walk(self, &mut |e| found |= matches!(e, AST::ResetStart));
+        found
+    }
+
+    /// (?(n)yes|no) の条件分岐を含むかを判定
+    pub fn has_conditional(&self) -> bool {
+        let mut found = false;
+        visit::walk(self, &mut |e| found |= matches!(e, AST::Conditional { .. }));
+        found
+    }
+
+    /// すべてのキャプチャグループの番号を offset だけずらす
+    pub fn shift_captures(&mut self, offset: usize) {
+        match self {
+            AST::Plus(e) | AST::Star(e) | AST::Question(e) | AST::Repeat(e, _, _) => {
+                e.shift_captures(offset)
+            }
+            AST::Or(e1, e2) => {
+                e1.shift_captures(offset);
+                e2.shift_captures(offset);
+            }
+            AST::Seq(v) => v.iter_mut().for_each(|e| e.shift_captures(offset)),
+            AST::Look { inner, .. } => inner.shift_captures(offset),
+            AST::Capture(e, index, _) => {
+                *index += offset;
+                e.shift_captures(offset);
+            }
+            AST::Conditional { group, yes, no } => {
+                *group += offset;
+                yes.shift_captures(offset);
+                no.shift_captures(offset);
+            }
+            _ => (),
+        }
+    }
 }
 
 /// パースエラーを表すための型
 #[derive(Debug)]
 pub enum ParseError {
-    InvalidEscape(usize, char), // 誤ったエスケープシーケンス
-    invalidRightParen(usize),   // 開き括弧なし
-    NoPrev(usize),              // +, |, *, ? の前に式がない
-    NoRightParen,               // 閉じ括弧なし
-    Empty,                      // 空のパターン
+    InvalidEscape(usize, char),       // 誤ったエスケープシーケンス
+    InvalidRightParen(usize),         // 開き括弧なし
+    NoPrev(usize),                    // +, |, *, ? の前に式がない
+    NoRightParen,                     // 閉じ括弧なし
+    Empty,                            // 空のパターン
+    InvalidFlag(usize, char),         // 誤ったインラインフラグ
+    InvalidRepeat(usize),             // 誤った {n,m} の指定
+    NoRightBracket(usize),            // 文字クラスの閉じ括弧なし
+    InvalidClassRange(usize),         // 文字クラスの範囲の始点が終点より大きい
+    EmptyClass(usize),                // どの文字にもマッチしない文字クラス
+    NestedQuantifier(usize),          // a** のように限量子の直後に限量子がある
+    InvalidGroupName(usize),          // 誤ったグループ名
+    DuplicateGroupName(usize),        // 既に使われているグループ名
+    VariableLookBehind(usize),        // 後読みの中の式が固定長でない
+    RepeatTooLarge(usize, usize),     // {n,m} の繰り返し回数が上限を超える
+    UnknownPredicate(usize),          // \p{name} の名前の述語が登録されていない
+    FeatureDisabled(usize, Features), // 無効にした構文が使われた
+    TooManyGroups(usize),             // キャプチャグループの数が上限を超える
+    TrailingBackslash(usize),         // パターンの末尾の \ の後に文字がない。その \ の位置
+    InvalidCodepoint(usize, u32),     // \x や \u の値が文字として有効なコードポイントでない
+    InvalidConditional(usize),        // 誤った (?(n)yes|no) の条件分岐。開き括弧の位置
+    PatternTooLong(usize),            // パターンの文字数が上限を超える。パターンの文字数
+    NestingTooDeep(usize),            // グループの入れ子が上限を超える。上限を超えた開き括弧の位置
 }
 
 /// パースエラーを表示するために、Display トレイトを実装
@@ -36,7 +384,7 @@ impl Display for ParseError {
             ParseError::InvalidEscape(pos, c) => {
                 write!(f, "ParseError: invalid espace: pos = {pos}, char = '{c}'")
             }
-            ParseError::invalidRightParen(pos) => {
+            ParseError::InvalidRightParen(pos) => {
                 write!(f, "ParseError: invalid right parenthesis: pos = {pos}")
             }
             ParseError::NoPrev(pos) => {
@@ -45,19 +393,456 @@ impl Display for ParseError {
             ParseError::NoRightParen => {
                 write!(f, "ParseError: no right parenthesis")
             }
-            ParseError::Empty(pos, c) => write!(f, "ParseError: empty expression"),
+            ParseError::Empty => write!(f, "ParseError: empty expression"),
+            ParseError::InvalidFlag(pos, c) => {
+                write!(f, "ParseError: invalid flag: pos = {pos}, char = '{c}'")
+            }
+            ParseError::InvalidRepeat(pos) => {
+                write!(f, "ParseError: invalid repetition: pos = {pos}")
+            }
+            ParseError::NoRightBracket(pos) => {
+                write!(f, "ParseError: no right bracket: pos = {pos}")
+            }
+            ParseError::InvalidClassRange(pos) => {
+                write!(f, "ParseError: invalid class range: pos = {pos}")
+            }
+            ParseError::EmptyClass(pos) => {
+                write!(f, "ParseError: empty class: pos = {pos}")
+            }
+            ParseError::NestedQuantifier(pos) => {
+                write!(f, "ParseError: nested quantifier: pos = {pos}")
+            }
+            ParseError::InvalidGroupName(pos) => {
+                write!(f, "ParseError: invalid group name: pos = {pos}")
+            }
+            ParseError::DuplicateGroupName(pos) => {
+                write!(f, "ParseError: duplicate group name: pos = {pos}")
+            }
+            ParseError::VariableLookBehind(pos) => {
+                write!(f, "ParseError: variable-length lookbehind: pos = {pos}")
+            }
+            ParseError::FeatureDisabled(pos, feature) => {
+                write!(f, "ParseError: {feature} is disabled: pos = {pos}")
+            }
+            ParseError::UnknownPredicate(pos) => {
+                write!(f, "ParseError: unknown predicate: pos = {pos}")
+            }
+            ParseError::TooManyGroups(pos) => {
+                write!(f, "ParseError: too many capture groups: pos = {pos}")
+            }
+            ParseError::TrailingBackslash(pos) => {
+                write!(f, "ParseError: trailing backslash: pos = {pos}")
+            }
+            ParseError::InvalidCodepoint(pos, value) => {
+                write!(
+                    f,
+                    "ParseError: invalid codepoint: pos = {pos}, value = U+{value:04X}"
+                )
+            }
+            ParseError::InvalidConditional(pos) => {
+                write!(f, "ParseError: invalid conditional: pos = {pos}")
+            }
+            ParseError::PatternTooLong(len) => {
+                write!(f, "ParseError: pattern too long: len = {len}")
+            }
+            ParseError::NestingTooDeep(pos) => {
+                write!(f, "ParseError: nesting too deep: pos = {pos}")
+            }
+            ParseError::RepeatTooLarge(pos, count) => {
+                write!(
+                    f,
+                    "ParseError: too large repetition: pos = {pos}, count = {count}"
+                )
+            }
         }
     }
 }
 
 impl Error for ParseError {}
 
+impl ParseError {
+    /// エラーの種類を表す、表示用の文字列に依存しないコードを返す
+    pub fn code(&self) -> ParseErrorCode {
+        match self {
+            ParseError::InvalidEscape(..) => ParseErrorCode::InvalidEscape,
+            ParseError::InvalidRightParen(..) => ParseErrorCode::InvalidRightParen,
+            ParseError::NoPrev(..) => ParseErrorCode::NoPrev,
+            ParseError::NoRightParen => ParseErrorCode::NoRightParen,
+            ParseError::Empty => ParseErrorCode::Empty,
+            ParseError::InvalidFlag(..) => ParseErrorCode::InvalidFlag,
+            ParseError::InvalidRepeat(..) => ParseErrorCode::InvalidRepeat,
+            ParseError::NoRightBracket(..) => ParseErrorCode::NoRightBracket,
+            ParseError::InvalidClassRange(..) => ParseErrorCode::InvalidClassRange,
+            ParseError::EmptyClass(..) => ParseErrorCode::EmptyClass,
+            ParseError::NestedQuantifier(..) => ParseErrorCode::NestedQuantifier,
+            ParseError::InvalidGroupName(..) => ParseErrorCode::InvalidGroupName,
+            ParseError::DuplicateGroupName(..) => ParseErrorCode::DuplicateGroupName,
+            ParseError::VariableLookBehind(..) => ParseErrorCode::VariableLookBehind,
+            ParseError::RepeatTooLarge(..) => ParseErrorCode::RepeatTooLarge,
+            ParseError::UnknownPredicate(..) => ParseErrorCode::UnknownPredicate,
+            ParseError::FeatureDisabled(..) => ParseErrorCode::FeatureDisabled,
+            ParseError::TooManyGroups(..) => ParseErrorCode::TooManyGroups,
+            ParseError::InvalidCodepoint(..) => ParseErrorCode::InvalidCodepoint,
+            ParseError::InvalidConditional(..) => ParseErrorCode::InvalidConditional,
+            ParseError::PatternTooLong(..) => ParseErrorCode::PatternTooLong,
+            ParseError::NestingTooDeep(..) => ParseErrorCode::NestingTooDeep,
+            ParseError::TrailingBackslash(..) => ParseErrorCode::TrailingBackslash,
+        }
+    }
+}
+
+/// パースエラーの種類を表すコード
+///
+/// ParseError の各値と同じ名前を持つ。
+/// Display の文字列と異なり、各値の数値と as_str の文字列は変更しない。
+/// 新たなエラーには、既存の値と重ならない数値を割り当てる
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ParseErrorCode {
+    InvalidEscape = 1,
+    InvalidRightParen = 2,
+    NoPrev = 3,
+    NoRightParen = 4,
+    Empty = 5,
+    InvalidFlag = 6,
+    InvalidRepeat = 7,
+    NoRightBracket = 8,
+    InvalidClassRange = 9,
+    EmptyClass = 10,
+    NestedQuantifier = 11,
+    InvalidGroupName = 12,
+    DuplicateGroupName = 13,
+    VariableLookBehind = 14,
+    RepeatTooLarge = 15,
+    UnknownPredicate = 16,
+    FeatureDisabled = 17,
+    TooManyGroups = 18,
+    InvalidCodepoint = 19,
+    InvalidConditional = 20,
+    PatternTooLong = 21,
+    NestingTooDeep = 22,
+    TrailingBackslash = 23,
+}
+
+impl ParseErrorCode {
+    /// コードを表す snake_case の文字列を返す
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ParseErrorCode::InvalidEscape => "invalid_escape",
+            ParseErrorCode::InvalidRightParen => "invalid_right_paren",
+            ParseErrorCode::NoPrev => "no_prev",
+            ParseErrorCode::NoRightParen => "no_right_paren",
+            ParseErrorCode::Empty => "empty",
+            ParseErrorCode::InvalidFlag => "invalid_flag",
+            ParseErrorCode::InvalidRepeat => "invalid_repeat",
+            ParseErrorCode::NoRightBracket => "no_right_bracket",
+            ParseErrorCode::InvalidClassRange => "invalid_class_range",
+            ParseErrorCode::EmptyClass => "empty_class",
+            ParseErrorCode::NestedQuantifier => "nested_quantifier",
+            ParseErrorCode::InvalidGroupName => "invalid_group_name",
+            ParseErrorCode::DuplicateGroupName => "duplicate_group_name",
+            ParseErrorCode::VariableLookBehind => "variable_look_behind",
+            ParseErrorCode::RepeatTooLarge => "repeat_too_large",
+            ParseErrorCode::UnknownPredicate => "unknown_predicate",
+            ParseErrorCode::FeatureDisabled => "feature_disabled",
+            ParseErrorCode::TooManyGroups => "too_many_groups",
+            ParseErrorCode::InvalidCodepoint => "invalid_codepoint",
+            ParseErrorCode::InvalidConditional => "invalid_conditional",
+            ParseErrorCode::PatternTooLong => "pattern_too_long",
+            ParseErrorCode::NestingTooDeep => "nesting_too_deep",
+            ParseErrorCode::TrailingBackslash => "trailing_backslash",
+        }
+    }
+}
+
+/// エラーではないが、誤りや冗長である可能性が高いパターン中の構文
+///
+/// 各値は、その構文のパターン中の位置を持つ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lint {
+    NestedQuantifier(usize),    // a** のように限量子の直後に限量子がある
+    DuplicateBranch(usize),     // a|a のように、以前と同じ | の選択肢がある
+    AlwaysMatchingClass(usize), // [\s\S] のように任意の1文字にマッチする文字クラス
+    EmptyGroup(usize),          // () のように中身が空のグループ
+}
+
+impl Lint {
+    /// パターン中の位置を返す
+    pub fn pos(&self) -> usize {
+        match self {
+            Lint::NestedQuantifier(pos)
+            | Lint::DuplicateBranch(pos)
+            | Lint::AlwaysMatchingClass(pos)
+            | Lint::EmptyGroup(pos) => *pos,
+        }
+    }
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lint::NestedQuantifier(pos) => write!(f, "Lint: nested quantifier: pos = {pos}"),
+            Lint::DuplicateBranch(pos) => write!(f, "Lint: duplicate branch: pos = {pos}"),
+            Lint::AlwaysMatchingClass(pos) => {
+                write!(f, "Lint: class matches any character: pos = {pos}")
+            }
+            Lint::EmptyGroup(pos) => write!(f, "Lint: empty group: pos = {pos}"),
+        }
+    }
+}
+
+/// パース時に切り替え可能なフラグ
+///
+/// RegexBuilder で初期値を指定でき、(?x) のようなインラインフラグで
+/// 現在のグループの終わりまで変更できる
+#[derive(Debug, Clone, Copy)]
+pub struct Flags<'a> {
+    pub ignore_whitespace: bool, // x: 空白と # から行末までのコメントを無視
+    pub ignore_case: bool,       // i: 大文字と小文字を区別しない
+    pub multi_line: bool,        // m: ^ と $ を行の先頭と末尾にもマッチさせる
+    pub dot_matches_new_line: bool, // s: . を改行にもマッチさせる
+    pub unicode: bool, // \d, \w, \s を Unicode の文字を含む文字クラスとする (インラインフラグなし)
+    pub allow_nested_quantifier: bool, // a** のような限量子の直後の限量子を許す (インラインフラグなし)
+    pub allow_missing_min: bool, // {,m} のように下限を省略した繰り返しを許す (インラインフラグなし)
+    pub max_repeat: usize,       // {n,m} で指定できる繰り返し回数の上限 (インラインフラグなし)
+    pub max_captures: usize,     // キャプチャグループの数の上限 (インラインフラグなし)
+    pub max_len: usize,          // パターンの文字数の上限 (インラインフラグなし)
+    pub max_nest: usize,         // グループの入れ子の深さの上限 (インラインフラグなし)
+    pub predicates: &'a [(String, Predicate)], // \p{name} で参照できる述語 (インラインフラグなし)
+    pub features: Features,      // 使用できる構文 (インラインフラグなし)
+}
+
+/// パターン中で使用できる構文の集合
+///
+/// 各構文を1ビットで表し、| で組み合わせる。
+/// 例えば Features::all().without(Features::ALTERNATION) は | 以外のすべての構文を許す
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Features(u32);
+
+impl Features {
+    pub const GROUP: Features = Features(1 << 0); // (...) によるグループ
+    pub const ALTERNATION: Features = Features(1 << 1); // | による選択
+    pub const QUANTIFIER: Features = Features(1 << 2); // +, *, ?, {n,m} による繰り返し
+    pub const ESCAPE: Features = Features(1 << 3); // \ によるエスケープ
+    pub const CLASS: Features = Features(1 << 4); // [...] による文字クラス
+    pub const ANCHOR: Features = Features(1 << 5); // ^ と $ によるアンカー
+
+    /// すべての構文を許す
+    pub const fn all() -> Features {
+        Features((1 << 6) - 1)
+    }
+
+    /// どの構文も許さない。通常の文字と . のみを使用できる
+    pub const fn empty() -> Features {
+        Features(0)
+    }
+
+    /// other のすべての構文を含むかを判定
+    pub const fn contains(self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// other の構文を除いた集合を返す
+    pub const fn without(self, other: Features) -> Features {
+        Features(self.0 & !other.0)
+    }
+
+    /// パターン中の特殊文字 c が属する構文を返す。特殊文字でない場合は None
+    fn of(c: char) -> Option<Features> {
+        match c {
+            '(' | ')' => Some(Features::GROUP),
+            '|' => Some(Features::ALTERNATION),
+            '+' | '*' | '?' | '{' => Some(Features::QUANTIFIER),
+            '\\' => Some(Features::ESCAPE),
+            '[' => Some(Features::CLASS),
+            '^' | '$' => Some(Features::ANCHOR),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Features::all()
+    }
+}
+
+impl std::ops::BitOr for Features {
+    type Output = Features;
+
+    fn bitor(self, rhs: Features) -> Features {
+        Features(self.0 | rhs.0)
+    }
+}
+
+/// エラーメッセージのために、含まれる構文の名前を | で区切って表示
+impl Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [
+            (Features::GROUP, "group"),
+            (Features::ALTERNATION, "alternation"),
+            (Features::QUANTIFIER, "quantifier"),
+            (Features::ESCAPE, "escape"),
+            (Features::CLASS, "class"),
+            (Features::ANCHOR, "anchor"),
+        ];
+        let names: Vec<&str> = names
+            .iter()
+            .filter(|(feature, _)| self.contains(*feature))
+            .map(|(_, name)| *name)
+            .collect();
+        write!(f, "{}", names.join(" | "))
+    }
+}
+
+/// 文字がマッチするかを判定する述語
+///
+/// 関数ポインタのため、述語を含む Regex も Clone, Send, Sync となる
+pub type Predicate = fn(char) -> bool;
+
+/// {n,m} の繰り返し回数の上限の既定値
+pub const DEFAULT_MAX_REPEAT: usize = 1000;
+
+/// キャプチャグループの数の上限の既定値
+pub const DEFAULT_MAX_CAPTURES: usize = 1000;
+
+/// パターンの文字数の上限の既定値
+pub const DEFAULT_MAX_LEN: usize = 1_000_000;
+
+/// グループの入れ子の深さの上限の既定値
+pub const DEFAULT_MAX_NEST: usize = 250;
+
+impl Default for Flags<'_> {
+    fn default() -> Self {
+        Flags {
+            ignore_whitespace: false,
+            ignore_case: false,
+            multi_line: false,
+            dot_matches_new_line: false,
+            unicode: cfg!(feature = "unicode"),
+            allow_nested_quantifier: false,
+            allow_missing_min: false,
+            max_repeat: DEFAULT_MAX_REPEAT,
+            max_captures: DEFAULT_MAX_CAPTURES,
+            max_len: DEFAULT_MAX_LEN,
+            max_nest: DEFAULT_MAX_NEST,
+            predicates: &[],
+            features: Features::all(),
+        }
+    }
+}
+
+/// インラインフラグの1文字を解釈し、on が真の場合はフラグを設定、偽の場合は解除
+///
+/// i, m, s, x を受け付ける。m と s はそれ以降にパースする ^, $, . の AST を切り替える
+fn parse_flag(flags: &mut Flags, pos: usize, c: char, on: bool) -> Result<(), ParseError> {
+    match c {
+        'x' => flags.ignore_whitespace = on,
+        'i' => flags.ignore_case = on,
+        'm' => flags.multi_line = on,
+        's' => flags.dot_matches_new_line = on,
+        _ => return Err(ParseError::InvalidFlag(pos, c)),
+    }
+    Ok(())
+}
+
+/// (?ix-x) のようなインラインフラグの並びを、閉じ括弧まで読み込んでフラグを変更
+///
+/// (? は読み込み済みとし、first はその直後の文字とその位置を表す。
+/// - より前のフラグは設定し、後のフラグは解除する。
+/// - が複数ある場合や、(?i-) のように - の後にフラグがない場合はエラー
+fn parse_flags<I>(
+    chars: &mut Peekable<I>,
+    flags: &mut Flags,
+    first: (usize, char),
+) -> Result<(), ParseError>
+where
+    I: Iterator<Item = (usize, char)>,
+{
+    let mut on = true;
+    let mut next = first;
+    loop {
+        match next {
+            (j, '-') => {
+                if !on || matches!(chars.peek(), Some((_, ')'))) {
+                    return Err(ParseError::InvalidFlag(j, '-'));
+                }
+                on = false;
+            }
+            (j, f) => parse_flag(flags, j, f, on)?,
+        }
+
+        match chars.next() {
+            Some((_, ')')) => return Ok(()),
+            Some(c) => next = c,
+            None => return Err(ParseError::NoRightParen),
+        }
+    }
+}
+
+/// 大文字と小文字を区別しない場合に加える、c の大文字と小文字
+///
+/// 小文字化または大文字化した結果が1文字となるものだけを返す
+fn case_variants(c: char) -> impl Iterator<Item = char> {
+    // 1文字となる場合のみ、その文字を返す
+    fn single(mut it: impl Iterator<Item = char>) -> Option<char> {
+        let c = it.next();
+        if it.next().is_none() {
+            c
+        } else {
+            None
+        }
+    }
+
+    let lower = single(c.to_lowercase());
+    let upper = single(c.to_uppercase());
+    lower.into_iter().chain(upper).filter(move |v| *v != c)
+}
+
+/// 大文字と小文字を区別しない場合の1文字の式
+///
+/// 大文字や小文字がある場合は、それらを含む文字クラスとする
+fn fold_case_char(c: char) -> AST {
+    let mut ranges = vec![(c, c)];
+    ranges.extend(case_variants(c).map(|v| (v, v)));
+    if ranges.len() == 1 {
+        AST::Char(c)
+    } else {
+        AST::Class(normalize_ranges(ranges))
+    }
+}
+
+/// 文字の範囲の列に、含まれる文字の大文字と小文字を加える
+fn fold_case_ranges(ranges: Vec<(char, char)>) -> Vec<(char, char)> {
+    // U+1E943 より後には、大文字と小文字のある文字はない
+    const LAST_CASED: char = '\u{1E943}';
+
+    let mut result = ranges.clone();
+    for (lo, hi) in ranges {
+        for c in lo..=hi.min(LAST_CASED) {
+            result.extend(case_variants(c).map(|v| (v, v)));
+        }
+    }
+    normalize_ranges(result)
+}
+
 /// 特殊文字のエスケープ
+///
+/// ^ と $ はパターン中のどの位置でもアンカーとして扱うため、
+/// 文字として扱う場合は \^ と \$ のようにエスケープする。
+/// \d, \w, \s などは、対応する文字クラスに変換する
+///
 /// pos: 現在の文字の位置
 /// c: エスケープする特殊文字
-fn parse_escape(pos: usize, c: char) -> Result<AST, ParseError> {
+/// unicode: \d, \w, \s を Unicode の文字を含む文字クラスとするか
+fn parse_escape(pos: usize, c: char, unicode: bool) -> Result<AST, ParseError> {
+    if let Some(ranges) = perl_class(c, unicode) {
+        return Ok(AST::Class(ranges));
+    }
+
     match c {
-        '\\' | '(' | ')' | '|' | '+' | '*' | '?' => Ok(AST::Char(c)),
+        '\\' | '(' | ')' | '|' | '+' | '*' | '?' | '{' | '}' | '[' | ']' | '^' | '$' | '.' => {
+            Ok(AST::Char(c))
+        }
         _ => {
             let err = ParseError::InvalidEscape(pos, c);
             Err(err)
@@ -65,16 +850,310 @@ fn parse_escape(pos: usize, c: char) -> Result<AST, ParseError> {
     }
 }
 
+/// \d, \w, \s とその否定 \D, \W, \S が表す文字の範囲を、昇順に並べて返す
+///
+/// unicode が偽の場合は、ASCII の数字、英数字と _、空白文字のみを対象とする。
+/// unicode フィーチャを有効にして unicode が真の場合は、Unicode の 10 進数字、
+/// 英字や結合文字などの単語を構成する文字、空白文字を対象とする
+#[cfg_attr(not(feature = "unicode"), allow(unused_variables))]
+fn perl_class(c: char, unicode: bool) -> Option<Vec<(char, char)>> {
+    let ranges = match c.to_ascii_lowercase() {
+        #[cfg(feature = "unicode")]
+        'd' if unicode => unicode_tables::DIGIT.to_vec(),
+        #[cfg(feature = "unicode")]
+        'w' if unicode => unicode_tables::WORD.to_vec(),
+        #[cfg(feature = "unicode")]
+        's' if unicode => unicode_tables::SPACE.to_vec(),
+        'd' => vec![('0', '9')],
+        'w' => vec![('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')],
+        's' => vec![('\t', '\r'), (' ', ' ')], // \t, \n, \v, \f, \r と空白
+        _ => return None,
+    };
+
+    if c.is_ascii_uppercase() {
+        Some(complement_ranges(&ranges))
+    } else {
+        Some(ranges)
+    }
+}
+
+/// 文字クラスの要素
+enum ClassMember {
+    Char(char),             // 1文字、または範囲の端
+    Set(Vec<(char, char)>), // \d のような文字の範囲の集合
+}
+
+/// 文字クラス中の特殊文字のエスケープ
+///
+/// 文字クラス中の \b は、他の正規表現エンジンと同様にバックスペース (U+0008) とする。
+/// 文字クラスの外の \b は単語境界の表明で、文字クラス中の \B はエラーとなる
+fn parse_class_escape(pos: usize, c: char, unicode: bool) -> Result<ClassMember, ParseError> {
+    match c {
+        '-' => Ok(ClassMember::Char(c)),
+        'b' => Ok(ClassMember::Char('\u{8}')),
+        _ => match parse_escape(pos, c, unicode)? {
+            AST::Char(c) => Ok(ClassMember::Char(c)),
+            AST::Class(ranges) => Ok(ClassMember::Set(ranges)),
+            _ => Err(ParseError::InvalidEscape(pos, c)),
+        },
+    }
+}
+
+/// \p{name} の名前を読み込み、登録された述語を AST::Predicate として返す
+///
+/// \p は読み込み済みとし、pos はその p の位置を表す。
+/// 名前の述語が登録されていない場合はエラー
+fn parse_predicate<I>(
+    chars: &mut Peekable<I>,
+    pos: usize,
+    predicates: &[(String, Predicate)],
+) -> Result<AST, ParseError>
+where
+    I: Iterator<Item = (usize, char)>,
+{
+    if chars.next_if(|(_, c)| *c == '{').is_none() {
+        return Err(ParseError::InvalidEscape(pos, 'p'));
+    }
+
+    let mut name = String::new();
+    loop {
+        match chars.next() {
+            Some((_, '}')) => break,
+            Some((_, c)) => name.push(c),
+            None => return Err(ParseError::InvalidEscape(pos, 'p')),
+        }
+    }
+
+    if let Some((_, f)) = predicates.iter().find(|(n, _)| *n == name) {
+        Ok(AST::Predicate(name, *f))
+    } else {
+        Err(ParseError::UnknownPredicate(pos))
+    }
+}
+
+/// \0 に続く8進数のエスケープシーケンスを、対応する文字に変換
+///
+/// \0 は読み込み済みとし、pos はその 0 の位置を表す。
+/// 続く最大3桁の8進数を読み込み、\0101 は A、\0 のみの場合は NUL 文字となる。
+/// \1 から \9 は後方参照と紛らわしいため、8進数としては扱わない
+fn parse_octal<I>(chars: &mut Peekable<I>, pos: usize) -> Result<char, ParseError>
+where
+    I: Iterator<Item = (usize, char)>,
+{
+    let mut n = 0;
+    for _ in 0..3 {
+        if let Some((_, c)) = chars.next_if(|(_, c)| matches!(c, '0'..='7')) {
+            n = n * 8 + c.to_digit(8).unwrap(); // 8進数の数字であることは確認済み
+        } else {
+            break;
+        }
+    }
+    char::from_u32(n).ok_or(ParseError::InvalidEscape(pos, '0'))
+}
+
+/// \c に続く英字を、対応する制御文字に変換
+///
+/// \c は読み込み済みとし、pos はその c の位置を表す。
+/// 英字の下位5ビットを値とするため、\cI と \ci はいずれもタブ (\x09) となる。
+/// 英字が続かない場合は ParseError::InvalidEscape とする
+fn parse_control<I>(chars: &mut Peekable<I>, pos: usize) -> Result<char, ParseError>
+where
+    I: Iterator<Item = (usize, char)>,
+{
+    if let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_alphabetic()) {
+        Ok(char::from(c as u8 & 0x1F))
+    } else {
+        Err(ParseError::InvalidEscape(pos, 'c'))
+    }
+}
+
+/// \x と \u に続く16進数のエスケープシーケンスを、対応する文字に変換
+///
+/// \x または \u は読み込み済みとし、pos はその x または u の位置を、c はその文字を表す。
+/// \x{...} と \u{...} は波括弧の中の1桁以上の16進数を、\xHH は2桁、\uHHHH は4桁の16進数を読み込む。
+/// 桁が足りない場合や、u32 に収まらない場合は ParseError::InvalidEscape とし、
+/// サロゲートの範囲 (D800 から DFFF) や 10FFFF を超える値は ParseError::InvalidCodepoint とする
+fn parse_hex<I>(chars: &mut Peekable<I>, pos: usize, c: char) -> Result<char, ParseError>
+where
+    I: Iterator<Item = (usize, char)>,
+{
+    let err = || ParseError::InvalidEscape(pos, c);
+    let braced = chars.next_if(|(_, c)| *c == '{').is_some();
+    let max_digits = match (braced, c) {
+        (true, _) => usize::MAX,
+        (false, 'x') => 2,
+        (false, _) => 4,
+    };
+
+    let mut n: u32 = 0;
+    let mut digits = 0;
+    while digits < max_digits {
+        if let Some((_, d)) = chars.next_if(|(_, d)| d.is_ascii_hexdigit()) {
+            let d = d.to_digit(16).unwrap(); // 16進数の数字であることは確認済み
+            n = n
+                .checked_mul(16)
+                .and_then(|n| n.checked_add(d))
+                .ok_or_else(err)?;
+            digits += 1;
+        } else {
+            break;
+        }
+    }
+
+    let closed = !braced || chars.next_if(|(_, c)| *c == '}').is_some();
+    if digits == 0 || !closed || (!braced && digits < max_digits) {
+        return Err(err());
+    }
+    char::from_u32(n).ok_or(ParseError::InvalidCodepoint(pos, n))
+}
+
+/// 次のコードポイントの文字を返す。サロゲートの範囲は飛ばす
+fn next_char(c: char) -> Option<char> {
+    match c {
+        '\u{D7FF}' => Some('\u{E000}'),
+        _ => char::from_u32(c as u32 + 1),
+    }
+}
+
+/// 前のコードポイントの文字を返す。サロゲートの範囲は飛ばす
+fn prev_char(c: char) -> Option<char> {
+    match c {
+        '\u{E000}' => Some('\u{D7FF}'),
+        _ => (c as u32).checked_sub(1).and_then(char::from_u32),
+    }
+}
+
+/// 文字の範囲の列を昇順に並べ、重なる範囲と隣接する範囲を結合
+fn normalize_ranges(mut ranges: Vec<(char, char)>) -> Vec<(char, char)> {
+    ranges.sort();
+    let mut result: Vec<(char, char)> = Vec::new();
+    for (lo, hi) in ranges {
+        if let Some((_, last_hi)) = result.last_mut() {
+            if next_char(*last_hi).is_none_or(|c| lo <= c) {
+                *last_hi = (*last_hi).max(hi);
+                continue;
+            }
+        }
+        result.push((lo, hi));
+    }
+    result
+}
+
+/// 正規化した文字の範囲の列に含まれない文字の範囲の列を返す
+fn complement_ranges(ranges: &[(char, char)]) -> Vec<(char, char)> {
+    let mut result = Vec::new();
+    let mut next = Some('\0'); // まだどの範囲にも含まれていない最小の文字
+    for &(lo, hi) in ranges {
+        if let Some(n) = next {
+            if n < lo {
+                // lo は n より大きいため、前の文字が存在する
+                result.push((n, prev_char(lo).unwrap()));
+            }
+        }
+        next = next_char(hi);
+    }
+    if let Some(n) = next {
+        result.push((n, char::MAX));
+    }
+    result
+}
+
+/// 文字クラス [...] を AST に変換
+///
+/// 先頭の [ は読み込み済みとし、pos はその位置を表す。
+/// [^...] は否定を表し、含まれない文字の範囲に変換する。
+/// [ または [^ の直後の ] と、範囲の終点がない - は通常の文字として扱う。
+/// そのため [] は空の文字クラスではなく、] を含む閉じていない文字クラスとなる。
+/// 閉じ括弧がない場合は、開き括弧の位置 pos を持つ ParseError::NoRightBracket とする。
+/// 大文字と小文字を区別しない場合は、含まれる文字の大文字と小文字も含める。
+///
+/// 例: [a-z_] は AST::Class([('_', '_'), ('a', 'z')]) となる
+fn parse_class<I>(chars: &mut Peekable<I>, pos: usize, flags: Flags) -> Result<AST, ParseError>
+where
+    I: Iterator<Item = (usize, char)>,
+{
+    // 範囲の端となる1文字、または \d のような文字の範囲の集合を読み込む
+    let next_member = |chars: &mut Peekable<I>| -> Result<(usize, ClassMember), ParseError> {
+        match chars.next() {
+            Some((i, '\\')) => match chars.next() {
+                Some((j, '0')) => Ok((i, ClassMember::Char(parse_octal(chars, j)?))),
+                Some((j, c @ ('x' | 'u'))) => Ok((i, ClassMember::Char(parse_hex(chars, j, c)?))),
+                Some((j, 'c')) => Ok((i, ClassMember::Char(parse_control(chars, j)?))),
+                Some((j, c)) => Ok((i, parse_class_escape(j, c, flags.unicode)?)),
+                None => Err(ParseError::NoRightBracket(pos)),
+            },
+            Some((i, c)) => Ok((i, ClassMember::Char(c))),
+            None => Err(ParseError::NoRightBracket(pos)),
+        }
+    };
+
+    let negated = chars.next_if(|(_, c)| *c == '^').is_some();
+    let mut ranges = Vec::new();
+    let mut first = true;
+
+    loop {
+        if !first && chars.next_if(|(_, c)| *c == ']').is_some() {
+            break;
+        }
+        first = false;
+
+        let (i, lo) = match next_member(chars)? {
+            (_, ClassMember::Set(set)) => {
+                ranges.extend(set);
+                continue;
+            }
+            (i, ClassMember::Char(c)) => (i, c),
+        };
+        if chars.next_if(|(_, c)| *c == '-').is_none() {
+            ranges.push((lo, lo));
+            continue;
+        }
+
+        // a-] のように終点がない場合は、- を通常の文字として扱う
+        if matches!(chars.peek(), Some((_, ']')) | None) {
+            ranges.push((lo, lo));
+            ranges.push(('-', '-'));
+            continue;
+        }
+
+        // [a-\d] のように、範囲の終点が文字の集合の場合はエラー
+        let hi = match next_member(chars)? {
+            (_, ClassMember::Char(c)) if lo <= c => c,
+            _ => return Err(ParseError::InvalidClassRange(i)),
+        };
+        ranges.push((lo, hi));
+    }
+
+    // 大文字と小文字を区別しない場合は、否定する前に大文字と小文字を加える
+    let mut ranges = if flags.ignore_case {
+        fold_case_ranges(ranges)
+    } else {
+        normalize_ranges(ranges)
+    };
+    if negated {
+        ranges = complement_ranges(&ranges);
+    }
+
+    // [^\0-\u{10FFFF}] のように、どの文字にもマッチしない場合はエラー
+    if ranges.is_empty() {
+        return Err(ParseError::EmptyClass(pos));
+    }
+
+    Ok(AST::Class(ranges))
+}
+
 /// parse_plus_star_question 関数で利用するための列挙型
+#[allow(clippy::upper_case_acronyms)]
 enum PSQ {
     Plus,
     Star,
     Question,
+    Repeat(usize, Option<usize>), // {n,m} の下限と上限
 }
 
-/// +, *, ? を AST に変換
+/// +, *, ?, {n,m} を AST に変換
 ///
-/// 後置記法で、+, *, ? の前にパターンがない場合はエラー
+/// 後置記法で、+, *, ?, {n,m} の前にパターンがない場合はエラー
 ///
 /// 例 : *ab, abc|+ などはエラー
 fn parse_plus_star_question(
@@ -88,122 +1167,968 @@ fn parse_plus_star_question(
             PSQ::Plus => AST::Plus(Box::new(prev)),
             PSQ::Star => AST::Star(Box::new(prev)),
             PSQ::Question => AST::Question(Box::new(prev)),
+            PSQ::Repeat(min, max) => AST::Repeat(Box::new(prev), min, max),
         };
         seq.push(ast);
-        Ok(());
+        Ok(())
     } else {
         // 限量子前に限量するパターンが現れないような用い方の時
         Err(ParseError::NoPrev(pos))
     }
 }
 
+/// 10 進数の数値を読み込む
+///
+/// 数字が1つもない場合は None を返す。
+/// 数値が usize に収まらない場合は、pos の位置の {n,m} が誤っているものとしてエラー
+fn parse_number<I>(chars: &mut Peekable<I>, pos: usize) -> Result<Option<usize>, ParseError>
+where
+    I: Iterator<Item = (usize, char)>,
+{
+    let mut num = None;
+    while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+        let d = c.to_digit(10).unwrap() as usize; // 数字であることは確認済み
+        let n = num.unwrap_or(0usize);
+        let n = n.checked_mul(10).and_then(|n| n.checked_add(d));
+        num = Some(n.ok_or(ParseError::InvalidRepeat(pos))?);
+    }
+    Ok(num)
+}
+
+/// {n}, {n,}, {n,m} を解釈し、繰り返し回数の下限と上限を持つ PSQ::Repeat を返す
+///
+/// 先頭の { は読み込み済みとし、pos はその位置を表す。
+/// {n,} のように上限がない場合、上限は None とする。
+/// flags.allow_missing_min が真の場合は、{,m} を下限を 0 とした {0,m} として扱う。
+/// 下限と上限の両方を省略した {,} はエラー。
+/// 繰り返し回数 (上限がない場合は下限) が flags.max_repeat を超える場合はエラー。
+fn parse_repeat<I>(chars: &mut Peekable<I>, pos: usize, flags: Flags) -> Result<PSQ, ParseError>
+where
+    I: Iterator<Item = (usize, char)>,
+{
+    let min = parse_number(chars, pos)?;
+    let (min, max) = if chars.next_if(|(_, c)| *c == ',').is_some() {
+        let max = parse_number(chars, pos)?; // {n,} の場合は上限なし
+        match min {
+            Some(min) => (min, max),
+            None if flags.allow_missing_min && max.is_some() => (0, max),
+            // {,} は上限も省略しているためエラー
+            None => return Err(ParseError::InvalidRepeat(pos)),
+        }
+    } else {
+        let min = min.ok_or(ParseError::InvalidRepeat(pos))?;
+        (min, Some(min)) // {n} の場合は下限と上限が等しい
+    };
+
+    if chars.next_if(|(_, c)| *c == '}').is_none() {
+        return Err(ParseError::InvalidRepeat(pos));
+    }
+
+    // {3,1} のように下限が上限を超える場合はエラー
+    if let Some(max) = max {
+        if min > max {
+            return Err(ParseError::InvalidRepeat(pos));
+        }
+    }
+
+    // 繰り返し回数に比例してカウンタの取り得る値が増え、評価に時間がかかるためエラー
+    let count = max.unwrap_or(min);
+    if count > flags.max_repeat {
+        return Err(ParseError::RepeatTooLarge(pos, count));
+    }
+
+    Ok(PSQ::Repeat(min, max))
+}
+
+/// (?P<name> または (?<name> のグループ名を、閉じ山括弧まで読み込む
+///
+/// グループ名は英数字と _ からなり、数字で始まらない空でない文字列とする
+///
+/// pos: グループ名の先頭の位置
+fn parse_group_name<I>(chars: &mut I, pos: usize) -> Result<String, ParseError>
+where
+    I: Iterator<Item = (usize, char)>,
+{
+    let mut name = String::new();
+    for (_, c) in chars {
+        match c {
+            '>' if !name.is_empty() => return Ok(name),
+            '_' => name.push(c),
+            c if c.is_alphanumeric() && !(name.is_empty() && c.is_ascii_digit()) => name.push(c),
+            _ => return Err(ParseError::InvalidGroupName(pos)),
+        }
+    }
+    Err(ParseError::InvalidGroupName(pos))
+}
+
+/// 括弧の種類
+enum Group {
+    Capture(usize, Option<String>), // キャプチャグループの番号と名前
+    NonCapture,                     // (?:...)
+    LookBehind(bool, usize),        // (?<=...) と (?<!...)。否定であるかと、開き括弧の位置
+    Conditional(usize, usize),      // (?(n)yes|no)。条件とするグループの番号と、開き括弧の位置
+}
+
+/// (?(n)yes|no) の条件とするグループの番号を、閉じ括弧まで読み込む
+///
+/// (?( は読み込み済みとし、pos は開き括弧の位置を表す。
+/// 番号が 1 以上の10進数でない場合や、閉じ括弧がない場合はエラー
+fn parse_condition<I>(chars: &mut Peekable<I>, pos: usize) -> Result<usize, ParseError>
+where
+    I: Iterator<Item = (usize, char)>,
+{
+    let err = || ParseError::InvalidConditional(pos);
+    let mut group: usize = 0;
+    let mut digits = 0;
+    while let Some((_, d)) = chars.next_if(|(_, d)| d.is_ascii_digit()) {
+        let d = d.to_digit(10).unwrap() as usize; // 10進数の数字であることは確認済み
+        group = group
+            .checked_mul(10)
+            .and_then(|n| n.checked_add(d))
+            .ok_or_else(err)?;
+        digits += 1;
+    }
+
+    if digits == 0 || group == 0 || chars.next_if(|(_, c)| *c == ')').is_none() {
+        return Err(err());
+    }
+    Ok(group)
+}
+
+/// キャプチャグループに番号を付ける
+///
+/// 名前付きグループの場合は、グループ名とその位置を name に渡す。
+/// 同じ名前のグループが既にある場合や、グループの数が limit を超える場合はエラー
+///
+/// pos: 開き括弧の位置
+fn new_capture(
+    name: Option<(String, usize)>,
+    pos: usize,
+    limit: usize,
+    names: &mut Vec<String>,
+    num_groups: &mut usize,
+) -> Result<Group, ParseError> {
+    // グループごとに開始位置と終了位置を保存するため、多すぎるとスロットが肥大化する
+    if *num_groups >= limit {
+        return Err(ParseError::TooManyGroups(pos));
+    }
+
+    let name = match name {
+        Some((name, pos)) => {
+            if names.contains(&name) {
+                return Err(ParseError::DuplicateGroupName(pos));
+            }
+            names.push(name.clone());
+            Some(name)
+        }
+        None => None,
+    };
+
+    *num_groups += 1;
+    Ok(Group::Capture(*num_groups, name))
+}
+
 /// Or で結合された複数の式を AST に変換
 ///
-/// 例: abc|def|ghi は、 AST::Or("abc", AST::Or("def" , "ghi")) という AST となる
+/// 選択肢を前半と後半に分けて再帰的に結合し、選択肢の数 n に対して深さ log n の木とする。
+/// 各 Or は左側を優先するため、選択肢の優先順位はパターン中に現れる順のまま保たれる。
+/// 例: abc|def|ghi は AST::Or("abc", AST::Or("def", "ghi")) に、
+/// a|b|c|d は AST::Or(AST::Or(a, b), AST::Or(c, d)) となる。
+/// | は最も優先順位が低く、アンカーは各選択肢の Seq に含まれるため、
+/// ^a|b$ は AST::Or(Seq(^, a), Seq(b, $)) となり、^(a|b)$ とは異なる
 fn fold_or(mut seq_or: Vec<AST>) -> Option<AST> {
     if seq_or.len() > 1 {
-        let mut ast = seq_or.pop().unwrap();
-        seq_or.reverse(); // AST::Or 先頭の式をASTのルートするため、並びを反転させる
-        for s in seq_or {
-            ast = AST::Or(Box::new(s), Box::new(ast));
-        }
-        Some(ast)
+        let rest = seq_or.split_off(seq_or.len() / 2);
+        let e1 = fold_or(seq_or)?;
+        let e2 = fold_or(rest)?;
+        Some(AST::Or(Box::new(e1), Box::new(e2)))
     } else {
         seq_or.pop() // seq_or 中の唯一の要素を返す
     }
 }
 
+/// パターン中の通常の文字を AST に変換
+///
+/// 大文字と小文字を区別しない場合は、大文字と小文字を含む文字クラスとする
+fn literal(c: char, flags: Flags) -> AST {
+    if flags.ignore_case {
+        fold_case_char(c)
+    } else {
+        AST::Char(c)
+    }
+}
+
+/// 式の列を AST に変換
+///
+/// 1つの式のみからなる場合は、AST::Seq で囲まずにその式を返す
+fn new_seq(mut seq: Vec<AST>) -> AST {
+    if seq.len() == 1 {
+        seq.pop().unwrap()
+    } else {
+        AST::Seq(seq)
+    }
+}
+
+/// | の選択肢を seq_or に追加
+///
+/// 以前と同じ選択肢の場合は、pos を選択肢の先頭の位置として lints に追加する
+fn push_branch(seq_or: &mut Vec<AST>, branch: AST, pos: usize, lints: &mut Vec<Lint>) {
+    if seq_or.contains(&branch) {
+        lints.push(Lint::DuplicateBranch(pos));
+    }
+    seq_or.push(branch);
+}
+
+/// パースの結果
+#[derive(Debug)]
+pub struct Parsed {
+    pub ast: AST,
+    pub group_count: usize, // キャプチャグループの数 (マッチ全体を表す 0 番目は含まない)
+    pub lints: Vec<Lint>,   // パース中に見つかった、エラーではない問題
+}
+
 /// 正規表現を正規表現を抽象構文木に変換
 /// 引数として受け取った正規表現文字列から1文字ずつ文字を取り出し、それに該当する AST を生成する
-pub fn parse(expr: &str) -> Result<AST, ParseError> {
+///
+/// パターンの文字数が flags.max_len を超える場合は、AST を生成する前にエラーとする。
+/// Seq と Or のコンテキストの大きさは文字数で、スタックの深さは flags.max_nest で制限される
+pub fn parse(expr: &str, mut flags: Flags) -> Result<Parsed, ParseError> {
+    let len = expr.chars().count();
+    if len > flags.max_len {
+        return Err(ParseError::PatternTooLong(len));
+    }
+
     // 内部状態を表現するための型
     // 関数内で型を定義することで、この関数内でのみ用いる
     // Char: 文字列処理中
     // Escape: エスケープシーケンス処理中
+    // Quote: \Q から \E までの引用中
     enum ParseState {
         Char,
         Escape,
+        Quote,
     }
 
     let mut seq = Vec::new(); // 現在の Seq のコンテキスト
     let mut seq_or = Vec::new(); // 現在の Or のコンテキスト
-    let mut stack = Vec::new(); // コンテキストのスタック、コンテキストの保存と復元を行う
+    let mut stack = Vec::new(); // コンテキストのスタック、コンテキストとフラグの保存と復元を行う
     let mut state = ParseState::Char; // 現在の状態
+    let mut names = Vec::new(); // これまでに現れたグループ名
+    let mut num_groups = 0; // これまでに現れたキャプチャグループの数
+    let mut branch_pos = 0; // 現在の | の選択肢の先頭の位置
+    let mut lints = Vec::new(); // これまでに見つかった、エラーではない問題
+    let mut conditions = Vec::new(); // 条件分岐が参照するグループの番号と、その開き括弧の位置
+    let mut quantified = false; // 直前の要素が限量子であるか
 
     // chars で各文字のイテレータを取得
     // enumerate で繰り返し番号とイテレータのペアが返る
     // 番号はエラー時に、エラーが起きた場所を把握するために使う
-    for (i, c) in expr.chars().enumerate() {
+    // peekable で次の文字を先読みできるようにする
+    let mut chars = expr.chars().enumerate().peekable();
+    while let Some((i, c)) = chars.next() {
         match &state {
             ParseState::Char => {
+                if flags.ignore_whitespace {
+                    // 空白は読み飛ばし、# から行末まではコメントとして読み飛ばす
+                    if c.is_whitespace() {
+                        continue;
+                    } else if c == '#' {
+                        chars.find(|(_, c)| *c == '\n');
+                        continue;
+                    }
+                }
+
+                // 無効にした構文の特殊文字はエラー
+                if let Some(feature) = Features::of(c) {
+                    if !flags.features.contains(feature) {
+                        return Err(ParseError::FeatureDisabled(i, feature));
+                    }
+                }
+
+                // a** のような限量子の連続は、多くの場合誤りのためエラー
+                // 許す場合は、直前の限量子を含む式全体を更に限量する
+                let is_quantifier = matches!(c, '+' | '*' | '?' | '{');
+                // (?:a+)? のように、括弧で囲った限量子は字句上連続しないため許す
+                if is_quantifier && quantified {
+                    debug_assert!(seq.last().is_some_and(AST::is_quantifier));
+                    if !flags.allow_nested_quantifier {
+                        return Err(ParseError::NestedQuantifier(i));
+                    }
+                    lints.push(Lint::NestedQuantifier(i));
+                }
+                quantified = is_quantifier;
+
                 match c {
                     '+' => parse_plus_star_question(&mut seq, PSQ::Plus, i)?,
                     '*' => parse_plus_star_question(&mut seq, PSQ::Star, i)?,
                     '?' => parse_plus_star_question(&mut seq, PSQ::Question, i)?,
+                    '{' => {
+                        let repeat = parse_repeat(&mut chars, i, flags)?;
+                        parse_plus_star_question(&mut seq, repeat, i)?;
+                    }
                     '(' => {
+                        // 通常の括弧はキャプチャグループとし、開き括弧の順に番号を付ける
+                        // (? で始まる場合は、続く文字でグループの種類を判定
+                        let group = if chars.next_if(|(_, c)| *c == '?').is_some() {
+                            match chars.next() {
+                                Some((_, ':')) => Group::NonCapture, // (?:...) はキャプチャしないグループ
+                                Some((_, '(')) => {
+                                    // (?(n)yes|no) は n 番目のグループがマッチしたかによる条件分岐
+                                    let group = parse_condition(&mut chars, i)?;
+                                    conditions.push((group, i));
+                                    Group::Conditional(group, i)
+                                }
+                                Some((_, '<')) => {
+                                    // (?<=...) と (?<!...) は後読み、それ以外は名前付きグループ
+                                    if let Some((_, c)) =
+                                        chars.next_if(|(_, c)| *c == '=' || *c == '!')
+                                    {
+                                        Group::LookBehind(c == '!', i)
+                                    } else {
+                                        let name = parse_group_name(&mut chars, i + 3)?;
+                                        new_capture(
+                                            Some((name, i + 3)),
+                                            i,
+                                            flags.max_captures,
+                                            &mut names,
+                                            &mut num_groups,
+                                        )?
+                                    }
+                                }
+                                Some((j, 'P')) => {
+                                    if chars.next_if(|(_, c)| *c == '<').is_none() {
+                                        return Err(ParseError::InvalidGroupName(j + 1));
+                                    }
+                                    let name = parse_group_name(&mut chars, i + 4)?;
+                                    new_capture(
+                                        Some((name, i + 4)),
+                                        i,
+                                        flags.max_captures,
+                                        &mut names,
+                                        &mut num_groups,
+                                    )?
+                                }
+                                Some((_, '#')) => {
+                                    // (?#...) はコメントとして閉じ括弧まで読み飛ばす
+                                    if !chars.any(|(_, c)| c == ')') {
+                                        return Err(ParseError::NoRightParen);
+                                    }
+                                    continue;
+                                }
+                                Some((j, f)) if f.is_ascii_alphabetic() || f == '-' => {
+                                    // (?x) や (?i-x) のようなインラインフラグは、現在のグループの終わりまで有効
+                                    parse_flags(&mut chars, &mut flags, (j, f))?;
+                                    continue;
+                                }
+                                // それ以外は ? の前に式がないものとしてエラー
+                                _ => return Err(ParseError::NoPrev(i + 1)),
+                            }
+                        } else {
+                            new_capture(None, i, flags.max_captures, &mut names, &mut num_groups)?
+                        };
+
                         // 現在のコンテキストをスタックに保存し、
                         // 現在のコンテキストを空の状態にする
+                        if stack.len() >= flags.max_nest {
+                            return Err(ParseError::NestingTooDeep(i));
+                        }
                         let prev = take(&mut seq);
                         let prev_or = take(&mut seq_or);
-                        stack.push(prev, prev_or);
+                        stack.push((prev, prev_or, flags, group, i, branch_pos));
+                        branch_pos = chars.peek().map_or(i, |(j, _)| *j);
                     }
                     ')' => {
                         // 現在のコンテキストをスタックからポップ
-                        if let Some((mut prev, prev_or)) = stack.pop() {
+                        if let Some((mut prev, prev_or, prev_flags, group, open, prev_branch)) =
+                            stack.pop()
+                        {
                             // "()" のように式が空の場合は push しない
+                            // "(a|)" のように | の右辺が空の場合は Empty を push する
                             if !seq.is_empty() {
-                                seq_or.push(AST::Seq(seq))
+                                push_branch(&mut seq_or, new_seq(seq), branch_pos, &mut lints);
+                            } else if !seq_or.is_empty() {
+                                push_branch(&mut seq_or, AST::Empty, branch_pos, &mut lints);
+                            } else {
+                                lints.push(Lint::EmptyGroup(open));
                             }
 
-                            // Or を生成
-                            if let Some(ast) = fold_or(seq_or) {
-                                prev.push(ast);
+                            // Or を生成し、グループの種類に応じて囲む
+                            // "()" のように式が空のキャプチャグループは、空文字列をキャプチャする
+                            let branches = seq_or.len();
+                            let ast = fold_or(seq_or);
+                            match group {
+                                Group::Capture(index, name) => {
+                                    let ast = ast.unwrap_or(AST::Empty);
+                                    prev.push(AST::Capture(Box::new(ast), index, name));
+                                }
+                                // 空の (?:) も限量できるよう、Empty として残す
+                                Group::NonCapture => prev.push(ast.unwrap_or(AST::Empty)),
+                                Group::LookBehind(negate, pos) => {
+                                    // 後読みする文字数をコード生成時に決めるため、固定長に限る
+                                    let inner = ast.unwrap_or(AST::Empty);
+                                    if inner.fixed_len().is_none() {
+                                        return Err(ParseError::VariableLookBehind(pos));
+                                    }
+                                    prev.push(AST::Look {
+                                        ahead: false,
+                                        negate,
+                                        inner: Box::new(inner),
+                                    });
+                                }
+                                Group::Conditional(group, pos) => {
+                                    // | の左辺をマッチした場合の、右辺をマッチしていない場合の式とする
+                                    let (yes, no) = match ast {
+                                        _ if branches > 2 => {
+                                            return Err(ParseError::InvalidConditional(pos))
+                                        }
+                                        Some(AST::Or(yes, no)) if branches == 2 => (yes, no),
+                                        ast => (
+                                            Box::new(ast.unwrap_or(AST::Empty)),
+                                            Box::new(AST::Empty),
+                                        ),
+                                    };
+                                    prev.push(AST::Conditional { group, yes, no });
+                                }
                             }
 
                             // 以前のコンテキストを、現在のコンテキストにする
                             seq = prev;
                             seq_or = prev_or;
+                            flags = prev_flags;
+                            branch_pos = prev_branch;
                         } else {
                             // "abc)" のように、開き括弧がないのに閉じ括弧がある場合はエラー
-                            return Err(Box::new(ParseError::invalidRightParen(i)));
+                            return Err(ParseError::InvalidRightParen(i));
                         }
                     }
                     '|' => {
                         if seq.is_empty() {
                             // "||", "(|abc)" などと、式が空の場合はエラー
-                            return Err(Box::new(ParseError::NoPrev(i)));
+                            return Err(ParseError::NoPrev(i));
                         } else {
                             let prev = take(&mut seq);
-                            seq_or.push(AST::Char(c));
+                            push_branch(&mut seq_or, new_seq(prev), branch_pos, &mut lints);
+                            branch_pos = i + 1;
                         }
                     }
                     '\\' => state = ParseState::Escape,
-                    _ => seq.push(AST::Char(c)),
+                    '[' => {
+                        let class = parse_class(&mut chars, i, flags)?;
+                        if matches!(&class, AST::Class(ranges) if ranges[..] == [('\0', char::MAX)])
+                        {
+                            lints.push(Lint::AlwaysMatchingClass(i));
+                        }
+                        seq.push(class);
+                    }
+                    '.' => seq.push(AST::AnyChar(flags.dot_matches_new_line)),
+                    '^' if flags.multi_line => seq.push(AST::AnchorLineStart),
+                    '$' if flags.multi_line => seq.push(AST::AnchorLineEnd),
+                    '^' => seq.push(AST::AnchorStart),
+                    '$' => seq.push(AST::AnchorEnd),
+                    // 文字クラスの外の ] は、POSIX と同様に通常の文字として扱う
+                    _ => seq.push(literal(c, flags)),
                 }
             }
             ParseState::Escape => {
                 // エスケープシーケンス
-                let ast = parse_escape(i, c)?;
+                // 空白を無視する場合も、エスケープした空白と # は通常の文字として扱う
+                if c == 'Q' {
+                    state = ParseState::Quote;
+                    continue;
+                }
+                let ast = if flags.ignore_whitespace && (c.is_whitespace() || c == '#') {
+                    literal(c, flags)
+                } else if c == '0' {
+                    literal(parse_octal(&mut chars, i)?, flags)
+                } else if c == 'x' || c == 'u' {
+                    literal(parse_hex(&mut chars, i, c)?, flags)
+                } else if c == 'c' {
+                    literal(parse_control(&mut chars, i)?, flags)
+                } else if c == 'G' {
+                    AST::AnchorContinue
+                } else if c == 'b' || c == 'B' {
+                    AST::WordBoundary(c == 'B', flags.unicode)
+                } else if c == 'K' {
+                    AST::ResetStart
+                } else if c == 'p' {
+                    parse_predicate(&mut chars, i, flags.predicates)?
+                } else {
+                    match parse_escape(i, c, flags.unicode)? {
+                        AST::Char(c) => literal(c, flags),
+                        ast => ast,
+                    }
+                };
                 seq.push(ast);
                 state = ParseState::Char;
             }
+            ParseState::Quote => {
+                // \E までの文字は、特殊文字や \ も含めて通常の文字として扱う
+                // \E がない場合は、パターンの末尾までを引用とする
+                if c == '\\' && chars.next_if(|(_, c)| *c == 'E').is_some() {
+                    state = ParseState::Char;
+                } else {
+                    seq.push(literal(c, flags));
+                }
+            }
         }
     }
 
+    // a\ のように、エスケープする文字がないまま終わった場合はエラー
+    // \Q...\ の末尾の \ は引用中の通常の文字として扱う
+    if let ParseState::Escape = state {
+        return Err(ParseError::TrailingBackslash(expr.chars().count() - 1));
+    }
+
     // 閉じ括弧が足りない場合はエラー
     if !stack.is_empty() {
-        return Err(Box::new(ParseError::NoRightParen));
+        return Err(ParseError::NoRightParen);
+    }
+
+    // 条件分岐はパターン中に存在するグループのみを参照できる
+    if let Some((_, pos)) = conditions.iter().find(|(group, _)| *group > num_groups) {
+        return Err(ParseError::InvalidConditional(*pos));
     }
 
     // "()" のように、式が空の場合は push しない
+    // "a|" のように | の右辺が空の場合は Empty を push する
     if !seq.is_empty() {
-        seq_or.push(AST::Seq(seq));
+        push_branch(&mut seq_or, new_seq(seq), branch_pos, &mut lints);
+    } else if !seq_or.is_empty() {
+        push_branch(&mut seq_or, AST::Empty, branch_pos, &mut lints);
     }
 
-    // Or を生成し、成功した場合はそれを返す
+    // Or を生成し、成功した場合は限量子を正規化して返す
     if let Some(ast) = fold_or(seq_or) {
-        Ok(ast)
+        Ok(Parsed {
+            ast: ast.simplify_repeat(),
+            group_count: num_groups,
+            lints,
+        })
     } else {
-        Err(Box::new(ParseError::Empty))
+        Err(ParseError::Empty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 既定のフラグでパースした抽象構文木
+    fn ast(expr: &str) -> AST {
+        parse(expr, Flags::default()).unwrap().ast
+    }
+
+    #[test]
+    fn comment_group() {
+        assert_eq!(ast("a(?#comment)b"), ast("ab"));
+        assert_eq!(ast("a(?#)b"), ast("ab"));
+        assert!(matches!(
+            parse("a(?#comment", Flags::default()),
+            Err(ParseError::NoRightParen)
+        ));
+    }
+
+    #[test]
+    fn escaped_anchor() {
+        let seq = AST::Seq;
+        assert_eq!(
+            ast(r"a\$b"),
+            seq(vec![AST::Char('a'), AST::Char('$'), AST::Char('b')])
+        );
+        assert_eq!(ast(r"\^"), AST::Char('^'));
+        assert_eq!(ast("a$"), seq(vec![AST::Char('a'), AST::AnchorEnd]));
+    }
+
+    #[test]
+    fn nested_quantifier() {
+        assert!(matches!(
+            parse("a**", Flags::default()),
+            Err(ParseError::NestedQuantifier(2))
+        ));
+        // 括弧で囲った限量子は更に限量できる
+        let star = |ast| AST::Star(Box::new(ast));
+        assert_eq!(
+            ast("(a*)*"),
+            star(AST::Capture(Box::new(star(AST::Char('a'))), 1, None))
+        );
+        assert_eq!(
+            ast("(?:a+)?"),
+            AST::Question(Box::new(AST::Plus(Box::new(AST::Char('a')))))
+        );
+
+        let flags = Flags {
+            allow_nested_quantifier: true,
+            ..Flags::default()
+        };
+        assert_eq!(parse("a**", flags).unwrap().ast, star(star(AST::Char('a'))));
+    }
+
+    #[test]
+    fn quantified_non_capture_group() {
+        let question = |ast| AST::Question(Box::new(ast));
+        let plus = AST::Plus(Box::new(AST::Char('a')));
+        assert_eq!(
+            ast("(?:a+)?b"),
+            AST::Seq(vec![question(plus), AST::Char('b')])
+        );
+        assert!(parse("(?:a*)*", Flags::default()).is_ok());
+        assert!(parse("(?:a?){1000}", Flags::default()).is_ok());
+        // 空のグループも限量できる
+        assert_eq!(
+            ast("(?:){3}"),
+            AST::Repeat(Box::new(AST::Empty), 3, Some(3))
+        );
+        assert!(matches!(
+            parse("(?:a**)", Flags::default()),
+            Err(ParseError::NestedQuantifier(5))
+        ));
+    }
+
+    #[test]
+    fn quote() {
+        assert_eq!(ast(r"\Qa+b\E"), ast(r"a\+b"));
+        // \E がない場合は末尾まで
+        assert_eq!(ast(r"\Q.\"), ast(r"\.\\"));
+    }
+
+    #[test]
+    fn max_repeat() {
+        assert!(matches!(
+            parse("a{100000}", Flags::default()),
+            Err(ParseError::RepeatTooLarge(_, 100000))
+        ));
+        assert!(parse("a{100}", Flags::default()).is_ok());
+        assert!(parse("a{2,1000}", Flags::default()).is_ok());
+
+        let flags = Flags {
+            max_repeat: 10,
+            ..Flags::default()
+        };
+        assert!(parse("a{11}", flags).is_err());
+    }
+
+    #[test]
+    fn group_count() {
+        let count = |expr| parse(expr, Flags::default()).unwrap().group_count;
+        assert_eq!(count("(a)((b)c)"), 3);
+        assert_eq!(count("(?:a)(?<=a)"), 0);
+        assert_eq!(count("(?P<x>a)|(b)"), 2);
+    }
+
+    #[test]
+    fn octal() {
+        assert_eq!(ast(r"\0101"), ast("A"));
+        assert_eq!(ast(r"\0"), ast("\0"));
+        // 4桁目以降は通常の文字
+        assert_eq!(ast(r"\01011"), ast("A1"));
+        assert_eq!(ast(r"[\0101]"), ast("[A]"));
+    }
+
+    #[test]
+    fn feature_disabled() {
+        let flags = Flags {
+            features: Features::all().without(Features::ALTERNATION),
+            ..Flags::default()
+        };
+        assert!(matches!(
+            parse("a|b", flags),
+            Err(ParseError::FeatureDisabled(1, Features::ALTERNATION))
+        ));
+        assert!(parse("a(b)+", flags).is_ok());
+    }
+
+    #[test]
+    fn alternation() {
+        let seq = |s: &str| AST::Seq(s.chars().map(AST::Char).collect());
+        let or = |a, b| AST::Or(Box::new(a), Box::new(b));
+
+        assert_eq!(ast("ab|cd"), or(seq("ab"), seq("cd")));
+        assert_eq!(
+            ast("a|b|c"),
+            or(AST::Char('a'), or(AST::Char('b'), AST::Char('c')))
+        );
+        assert_eq!(ast("ab|c|de"), or(seq("ab"), or(AST::Char('c'), seq("de"))));
+    }
+
+    #[test]
+    fn inline_flags() {
+        let seq = AST::Seq;
+        assert_eq!(
+            ast("(?m)^a$"),
+            seq(vec![
+                AST::AnchorLineStart,
+                AST::Char('a'),
+                AST::AnchorLineEnd
+            ])
+        );
+        assert_eq!(
+            ast("(?m)^(?-m)^"),
+            seq(vec![AST::AnchorLineStart, AST::AnchorStart])
+        );
+        assert_eq!(
+            ast(".(?s).(?-s)."),
+            seq(vec![
+                AST::AnyChar(false),
+                AST::AnyChar(true),
+                AST::AnyChar(false)
+            ])
+        );
+        assert_eq!(
+            ast("(?ms)(?im-s).$"),
+            seq(vec![AST::AnyChar(false), AST::AnchorLineEnd])
+        );
+        assert!(matches!(
+            parse("(?q)", Flags::default()),
+            Err(ParseError::InvalidFlag(2, 'q'))
+        ));
+        assert!(matches!(
+            parse("(?i-m-s)", Flags::default()),
+            Err(ParseError::InvalidFlag(5, '-'))
+        ));
+    }
+
+    #[test]
+    fn literal_prefix() {
+        let prefix = |expr| {
+            let mut prefix = String::new();
+            let complete = ast(expr).literal_prefix(&mut prefix);
+            (prefix, complete)
+        };
+        assert_eq!(prefix("foo.*"), ("foo".to_string(), false));
+        assert_eq!(prefix("^bar"), ("bar".to_string(), true));
+        assert_eq!(prefix("a*b"), (String::new(), false));
+    }
+
+    #[test]
+    fn backspace_and_word_boundary() {
+        assert_eq!(ast(r"[\b]"), AST::Class(vec![('\u{8}', '\u{8}')]));
+        let unicode = Flags::default().unicode;
+        assert_eq!(
+            ast(r"\ba\B"),
+            AST::Seq(vec![
+                AST::WordBoundary(false, unicode),
+                AST::Char('a'),
+                AST::WordBoundary(true, unicode)
+            ])
+        );
+        assert!(matches!(
+            parse(r"[\B]", Flags::default()),
+            Err(ParseError::InvalidEscape(2, 'B'))
+        ));
+    }
+
+    #[test]
+    fn simplify_repeat() {
+        let a = || Box::new(AST::Char('a'));
+        let simplify = |min, max| AST::Repeat(a(), min, max).simplify_repeat();
+        assert_eq!(simplify(1, Some(1)), AST::Char('a'));
+        assert_eq!(simplify(0, Some(1)), AST::Question(a()));
+        assert_eq!(simplify(0, None), AST::Star(a()));
+        assert_eq!(simplify(1, None), AST::Plus(a()));
+        assert_eq!(simplify(0, Some(0)), AST::Empty);
+        assert_eq!(simplify(2, Some(3)), AST::Repeat(a(), 2, Some(3)));
+
+        // グループ番号を残すため、キャプチャグループを含む a{0,0} は置き換えない
+        let group = AST::Repeat(Box::new(AST::Capture(a(), 1, None)), 0, Some(0));
+        assert_eq!(group.clone().simplify_repeat(), group);
+    }
+
+    #[test]
+    fn bracket() {
+        assert!(matches!(
+            parse("x[abc", Flags::default()),
+            Err(ParseError::NoRightBracket(1))
+        ));
+        // 文字クラスの外の ] は通常の文字
+        assert_eq!(ast("a]b"), ast("a\\]b"));
+        // [ の直後の ] は文字クラスに含まれるため、[] は閉じていない文字クラスとなる
+        assert!(matches!(
+            parse("[]", Flags::default()),
+            Err(ParseError::NoRightBracket(0))
+        ));
+        assert_eq!(ast("[]a]"), AST::Class(vec![(']', ']'), ('a', 'a')]));
+        assert!(matches!(
+            parse("[^\\0-\u{10FFFF}]", Flags::default()),
+            Err(ParseError::EmptyClass(0))
+        ));
+    }
+
+    #[test]
+    fn single_element_seq() {
+        assert_eq!(ast("a"), AST::Char('a'));
+        assert_eq!(ast("(?:a)"), AST::Char('a'));
+        assert_eq!(
+            ast("a|b"),
+            AST::Or(Box::new(AST::Char('a')), Box::new(AST::Char('b')))
+        );
+        assert_eq!(ast("(?:a)+"), AST::Plus(Box::new(AST::Char('a'))));
+        assert_eq!(ast("ab"), AST::Seq(vec![AST::Char('a'), AST::Char('b')]));
+    }
+
+    #[test]
+    fn classify() {
+        assert!(ast("a").is_literal());
+        assert!(ast("[a-z]").is_literal());
+        assert!(ast(".").is_literal());
+        assert!(!ast("ab").is_literal());
+
+        for expr in ["a+", "a*", "a?", "a{2,3}"] {
+            assert!(ast(expr).is_quantifier(), "{expr}");
+        }
+        assert!(!ast("(a)").is_quantifier());
+
+        for expr in ["^", "$", "\\b", "\\G", "\\K", "(?<=a)"] {
+            assert!(ast(expr).is_assertion(), "{expr}");
+        }
+        assert!(!ast("a").is_assertion());
+
+        for expr in ["a*", "a?", "a{0,2}", "(a|)", "^$", "(a*)+"] {
+            assert!(ast(expr).is_nullable(), "{expr}");
+        }
+        for expr in ["a", "a+", "a{1,2}", "ab*", "[ab]"] {
+            assert!(!ast(expr).is_nullable(), "{expr}");
+        }
+    }
+
+    #[test]
+    fn alternation_precedence() {
+        let or = |e1, e2| AST::Or(Box::new(e1), Box::new(e2));
+        assert_eq!(
+            ast("^a|b$"),
+            or(
+                AST::Seq(vec![AST::AnchorStart, AST::Char('a')]),
+                AST::Seq(vec![AST::Char('b'), AST::AnchorEnd])
+            )
+        );
+        assert_eq!(
+            ast("^(a|b)$"),
+            AST::Seq(vec![
+                AST::AnchorStart,
+                AST::Capture(Box::new(or(AST::Char('a'), AST::Char('b'))), 1, None),
+                AST::AnchorEnd,
+            ])
+        );
+        assert_eq!(ast("a|b"), or(AST::Char('a'), AST::Char('b')));
+    }
+
+    #[test]
+    fn trailing_backslash() {
+        for (expr, pos) in [
+            ("a\\", 1),
+            ("\\", 0),
+            ("(a\\", 2),
+            ("é\\", 1),
+            ("a\\\\\\", 3),
+        ] {
+            let err = parse(expr, Flags::default()).unwrap_err();
+            assert!(
+                matches!(err, ParseError::TrailingBackslash(p) if p == pos),
+                "{expr}"
+            );
+            assert_eq!(err.code(), ParseErrorCode::TrailingBackslash);
+            assert_eq!(err.code() as u32, 23);
+            assert_eq!(err.code().as_str(), "trailing_backslash");
+        }
+        assert_eq!(
+            ParseError::TrailingBackslash(1).to_string(),
+            "ParseError: trailing backslash: pos = 1"
+        );
+        // エスケープした \ や、引用中の \ は通常の文字
+        assert_eq!(
+            ast("a\\\\"),
+            AST::Seq(vec![AST::Char('a'), AST::Char('\\')])
+        );
+        assert_eq!(
+            ast("\\Q.\\"),
+            AST::Seq(vec![AST::Char('.'), AST::Char('\\')])
+        );
+    }
+
+    #[test]
+    fn invalid_codepoint() {
+        let err = |expr| parse(expr, Flags::default()).unwrap_err();
+        assert!(matches!(
+            err("\\u{D800}"),
+            ParseError::InvalidCodepoint(_, 0xD800)
+        ));
+        assert!(matches!(
+            err("a\\u{110000}"),
+            ParseError::InvalidCodepoint(_, 0x11_0000)
+        ));
+        assert!(matches!(
+            err("[\\u{DFFF}]"),
+            ParseError::InvalidCodepoint(_, 0xDFFF)
+        ));
+        assert_eq!(ast("\\u{10FFFF}"), AST::Char('\u{10FFFF}'));
+        assert_eq!(ast("\\x41"), AST::Char('A'));
+    }
+
+    #[test]
+    fn control_escape() {
+        assert_eq!(ast("\\cI"), AST::Char('\t'));
+        assert_eq!(ast("\\ca"), AST::Char('\u{1}'));
+        assert_eq!(ast("[\\cJ]"), AST::Class(vec![('\n', '\n')]));
+        assert!(parse("\\c", Flags::default()).is_err());
+        assert!(parse("\\c1", Flags::default()).is_err());
+    }
+
+    #[test]
+    fn balanced_or() {
+        fn depth(ast: &AST) -> usize {
+            match ast {
+                AST::Or(e1, e2) => 1 + depth(e1).max(depth(e2)),
+                _ => 0,
+            }
+        }
+        let letters: Vec<String> = ('a'..='z').map(String::from).collect();
+        assert_eq!(depth(&ast(&letters.join("|"))), 5);
+        let words: Vec<String> = (0..1000).map(|i| format!("w{i}")).collect();
+        assert_eq!(depth(&ast(&words.join("|"))), 10);
+
+        let or = |e1, e2| AST::Or(Box::new(e1), Box::new(e2));
+        let c = AST::Char;
+        assert_eq!(ast("a|b|c|d"), or(or(c('a'), c('b')), or(c('c'), c('d'))));
+        assert_eq!(ast("a|b|c"), or(c('a'), or(c('b'), c('c'))));
+    }
+
+    #[test]
+    fn error_codes() {
+        let errors = [
+            (ParseError::InvalidEscape(0, 'q'), "invalid_escape"),
+            (ParseError::InvalidRightParen(0), "invalid_right_paren"),
+            (ParseError::NoPrev(0), "no_prev"),
+            (ParseError::NoRightParen, "no_right_paren"),
+            (ParseError::Empty, "empty"),
+            (ParseError::InvalidFlag(0, 'q'), "invalid_flag"),
+            (ParseError::InvalidRepeat(0), "invalid_repeat"),
+            (ParseError::NoRightBracket(0), "no_right_bracket"),
+            (ParseError::InvalidClassRange(0), "invalid_class_range"),
+            (ParseError::EmptyClass(0), "empty_class"),
+            (ParseError::NestedQuantifier(0), "nested_quantifier"),
+            (ParseError::InvalidGroupName(0), "invalid_group_name"),
+            (ParseError::DuplicateGroupName(0), "duplicate_group_name"),
+            (ParseError::VariableLookBehind(0), "variable_look_behind"),
+            (ParseError::RepeatTooLarge(0, 0), "repeat_too_large"),
+            (ParseError::UnknownPredicate(0), "unknown_predicate"),
+            (
+                ParseError::FeatureDisabled(0, Features::GROUP),
+                "feature_disabled",
+            ),
+            (ParseError::TooManyGroups(0), "too_many_groups"),
+            (ParseError::InvalidCodepoint(0, 0xD800), "invalid_codepoint"),
+            (ParseError::InvalidConditional(0), "invalid_conditional"),
+            (ParseError::PatternTooLong(0), "pattern_too_long"),
+            (ParseError::NestingTooDeep(0), "nesting_too_deep"),
+            (ParseError::TrailingBackslash(0), "trailing_backslash"),
+        ];
+        // 数値は 1 から順に割り当てられ、値を持つエラーでもコードは変わらない
+        for (i, (err, name)) in errors.iter().enumerate() {
+            assert_eq!(err.code() as usize, i + 1, "{err}");
+            assert_eq!(err.code().as_str(), *name, "{err}");
+        }
+        assert_eq!(
+            ParseError::InvalidEscape(3, 'z').code(),
+            ParseError::InvalidEscape(0, 'q').code()
+        );
     }
 }
@@ -0,0 +1,184 @@
+//! コード生成した命令列と、そのメタデータ
+use super::{
+    codegen::{self, CodeGenError},
+    evaluator,
+    parser::{self, AST},
+    CaptureLocations, Instruction, RegexError,
+};
+
+/// コンパイル済みの命令列
+///
+/// Regex から入力中のマッチを探索する処理を除いたもので、命令列とキャプチャグループの名前、
+/// 入力の先頭と末尾に固定されているかを保持する。
+/// 評価は入力の指定した位置から始まるマッチのみを対象とする。
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub(crate) insts: Vec<Instruction>,    // コード生成した命令列
+    pub(crate) names: Vec<Option<String>>, // グループ番号順のキャプチャグループの名前
+    anchored_start: bool,                  // ^ から始まる式であるか
+    anchored_end: bool,                    // $ で終わる式であるか
+}
+
+impl Program {
+    /// 既定のオプションで正規表現をパースし、コード生成
+    pub fn new(expr: &str) -> Result<Program, RegexError> {
+        let parsed = parser::parse(expr, parser::Flags::default())?;
+        Ok(Program::from_ast(
+            &parsed.ast,
+            parsed.group_count,
+            true,
+            false,
+        )?)
+    }
+
+    /// AST からコード生成
+    ///
+    /// group_count はマッチ全体を除いたキャプチャグループの数。
+    /// optimize と relative は codegen::get_code と同じ
+    pub(crate) fn from_ast(
+        ast: &AST,
+        group_count: usize,
+        optimize: bool,
+        relative: bool,
+    ) -> Result<Program, CodeGenError> {
+        Ok(Program {
+            insts: codegen::get_code(ast, optimize, relative)?,
+            names: ast.capture_names(group_count),
+            anchored_start: ast.is_anchored_start(),
+            anchored_end: ast.is_anchored_end(),
+        })
+    }
+
+    /// 命令列の長さを返す
+    pub fn len(&self) -> usize {
+        self.insts.len()
+    }
+
+    /// 命令列が空であるかを判定
+    ///
+    /// コード生成した命令列は必ず match 命令を含むため、常に偽
+    pub fn is_empty(&self) -> bool {
+        self.insts.is_empty()
+    }
+
+    /// マッチ全体を表す 0 番目を含めた、キャプチャグループの数を返す
+    pub fn captures_len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// キャプチャグループの名前をグループ番号順に返す
+    ///
+    /// 0 番目と名前のないグループは None となる
+    pub fn capture_names(&self) -> impl Iterator<Item = Option<&str>> {
+        self.names.iter().map(|name| name.as_deref())
+    }
+
+    /// 入力の先頭にのみマッチする、^ から始まる式であるかを判定
+    pub fn is_anchored_start(&self) -> bool {
+        self.anchored_start
+    }
+
+    /// 入力の末尾にのみマッチする、$ で終わる式であるかを判定
+    pub fn is_anchored_end(&self) -> bool {
+        self.anchored_end
+    }
+
+    /// この命令列の直後に other を続ける命令列を生成
+    ///
+    /// other のキャプチャグループは、この命令列のグループに続く番号に付け替える
+    pub fn concat(&self, other: &Program) -> Result<Program, CodeGenError> {
+        let slots = self.group_slots()?;
+        let mut names = self.names.clone();
+        names.extend(other.names.iter().skip(1).cloned());
+
+        Ok(Program {
+            insts: codegen::concat(&self.insts, &other.insts, slots)?,
+            names,
+            anchored_start: self.anchored_start,
+            anchored_end: other.anchored_end,
+        })
+    }
+
+    /// この命令列と other のいずれかにマッチする命令列を生成
+    ///
+    /// 両方にマッチする場合は、この命令列を優先する。
+    /// other のキャプチャグループは、この命令列のグループに続く番号に付け替える
+    pub fn union(&self, other: &Program) -> Result<Program, CodeGenError> {
+        let slots = self.group_slots()?;
+        let mut names = self.names.clone();
+        names.extend(other.names.iter().skip(1).cloned());
+
+        Ok(Program {
+            insts: codegen::union(&self.insts, &other.insts, slots)?,
+            names,
+            anchored_start: self.anchored_start && other.anchored_start,
+            anchored_end: self.anchored_end && other.anchored_end,
+        })
+    }
+
+    /// マッチ全体を除いたキャプチャグループが用いるスロットの数
+    fn group_slots(&self) -> Result<usize, CodeGenError> {
+        let groups = self.names.len().saturating_sub(1);
+        groups
+            .checked_mul(2)
+            .ok_or(CodeGenError::FailCapture(groups))
+    }
+
+    /// 入力文字列の start 文字目から始まるマッチを、幅優先探索で最左最初に評価
+    ///
+    /// 入力中のマッチの探索は行わず、start から始まるマッチのみを対象とする。
+    /// マッチした場合は、各キャプチャグループの文字単位の範囲を返す。
+    /// 実行する命令の数は制限しない。
+    /// \G は start にマッチする
+    pub fn run(&self, line: &str, start: usize) -> Option<CaptureLocations> {
+        let chars: Vec<char> = line.chars().collect();
+        if start > chars.len() {
+            return None;
+        }
+        let input = evaluator::Input {
+            chars: &chars,
+            boundaries: None,
+            scan_start: start,
+            window_start: 0,
+            max_len: None,
+        };
+
+        let mut budget = evaluator::Budget::new(None);
+        let m = evaluator::eval(&self.insts, input, start, false, false, &mut budget).ok()??;
+        let mut slots = vec![None; self.names.len() * 2];
+        for (dst, src) in slots.iter_mut().zip(&m.slots).skip(2) {
+            *dst = *src;
+        }
+        slots[0] = Some(super::reset_start(start, &m));
+        slots[1] = Some(m.end);
+        Some(CaptureLocations { slots })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run() {
+        let program = Program::new("^(?<y>[0-9]+)-([a-z]+)$").unwrap();
+        assert_eq!(program.captures_len(), 3);
+        assert_eq!(
+            program.capture_names().collect::<Vec<_>>(),
+            [None, Some("y"), None]
+        );
+        assert!(program.is_anchored_start());
+        assert!(program.is_anchored_end());
+
+        let locs = program.run("2024-abc", 0).unwrap();
+        assert_eq!(locs.get(0), Some((0, 8)));
+        assert_eq!(locs.get(1), Some((0, 4)));
+        assert_eq!(locs.get(2), Some((5, 8)));
+        assert!(program.run("x2024-abc", 0).is_none());
+
+        // 入力中の探索は行わず、start から始まるマッチのみを返す
+        let program = Program::new("b+").unwrap();
+        assert!(program.run("abb", 0).is_none());
+        assert_eq!(program.run("abb", 1).unwrap().get(0), Some((1, 3)));
+    }
+}
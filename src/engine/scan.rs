@@ -0,0 +1,101 @@
+//! マッチの開始位置の候補となる文字の探索
+//!
+//! simd フィーチャを有効にすると、x86_64 では SSE2 命令で 4 文字ずつまとめて比較する。
+//! SSE2 は x86_64 のすべての CPU で使用できるため、実行時の検出は行わない。
+//! それ以外のアーキテクチャや、フィーチャを無効にした場合は1文字ずつ比較する。
+//! どちらの場合も結果は同じ
+
+/// 1回のループでまとめて比較する文字の数。SSE2 のレジスタ 4 本分
+#[cfg(all(target_arch = "x86_64", any(feature = "simd", test)))]
+const LANES: usize = 16;
+
+/// chars の中で最初に c が現れる位置を返す
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+pub fn find_char(chars: &[char], c: char) -> Option<usize> {
+    find_char_sse2(chars, c)
+}
+
+/// chars の中で最初に c が現れる位置を返す
+#[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+pub fn find_char(chars: &[char], c: char) -> Option<usize> {
+    find_char_scalar(chars, c)
+}
+
+/// 1文字ずつ比較して、chars の中で最初に c が現れる位置を返す
+#[cfg(any(not(all(feature = "simd", target_arch = "x86_64")), test))]
+fn find_char_scalar(chars: &[char], c: char) -> Option<usize> {
+    chars.iter().position(|x| *x == c)
+}
+
+/// SSE2 命令で LANES 文字ずつ比較して、chars の中で最初に c が現れる位置を返す
+///
+/// char は 32 ビットのため、1本のレジスタで 4 文字を比較する。
+/// LANES 文字のいずれかが c に等しい場合のみ、その中の位置を1文字ずつ探す。
+/// LANES 文字に満たない末尾は1文字ずつ比較する
+#[cfg(all(target_arch = "x86_64", any(feature = "simd", test)))]
+fn find_char_sse2(chars: &[char], c: char) -> Option<usize> {
+    use core::arch::x86_64::{
+        __m128i, _mm_cmpeq_epi32, _mm_loadu_si128, _mm_movemask_epi8, _mm_or_si128, _mm_set1_epi32,
+    };
+
+    let mut chunks = chars.chunks_exact(LANES);
+    // SAFETY: SSE2 は x86_64 の必須の命令セットのため、常に実行できる
+    let needle = unsafe { _mm_set1_epi32(c as i32) };
+    for (i, chunk) in chunks.by_ref().enumerate() {
+        let p = chunk.as_ptr() as *const __m128i;
+        // SAFETY: chunk は LANES 個の char、つまり 128 ビットの値 4 つ分の長さを持つため、
+        // p から p.add(3) までの読み込みは chunk の範囲内となる。
+        // _mm_loadu_si128 は境界に揃っていないアドレスからも読み込める
+        let found = unsafe {
+            let eq0 = _mm_cmpeq_epi32(_mm_loadu_si128(p), needle);
+            let eq1 = _mm_cmpeq_epi32(_mm_loadu_si128(p.add(1)), needle);
+            let eq2 = _mm_cmpeq_epi32(_mm_loadu_si128(p.add(2)), needle);
+            let eq3 = _mm_cmpeq_epi32(_mm_loadu_si128(p.add(3)), needle);
+            let any = _mm_or_si128(_mm_or_si128(eq0, eq1), _mm_or_si128(eq2, eq3));
+            _mm_movemask_epi8(any) != 0
+        };
+        if found {
+            return chunk.iter().position(|x| *x == c).map(|j| i * LANES + j);
+        }
+    }
+
+    let rest = chunks.remainder();
+    let offset = chars.len() - rest.len();
+    rest.iter().position(|x| *x == c).map(|j| offset + j)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_as_scalar() {
+        let chars: Vec<char> = "abcdefghijklmnopqrstuvwxyzあいう"
+            .chars()
+            .cycle()
+            .take(100)
+            .collect();
+        for len in 0..chars.len() {
+            let chars = &chars[..len];
+            for c in ['a', 'p', 'q', 'z', 'い', '\u{10FFFF}'] {
+                #[cfg(target_arch = "x86_64")]
+                assert_eq!(
+                    find_char_sse2(chars, c),
+                    find_char_scalar(chars, c),
+                    "{len} {c}"
+                );
+                assert_eq!(find_char(chars, c), find_char_scalar(chars, c), "{len} {c}");
+            }
+        }
+
+        // LANES 文字の区切りの前後にある文字
+        for pos in [14, 15, 16, 17, 31, 32, 33, 47, 48] {
+            let mut chars = vec!['x'; 50];
+            chars[pos] = 'y';
+            chars[49] = 'y';
+            #[cfg(target_arch = "x86_64")]
+            assert_eq!(find_char_sse2(&chars, 'y'), Some(pos));
+            assert_eq!(find_char_scalar(&chars, 'y'), Some(pos));
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//! 複数の正規表現をまとめて評価し、マッチしたパターンを判定
+use super::{
+    codegen, evaluator,
+    parser::{self, ParseError, AST},
+    Instruction, RegexError,
+};
+use std::ops::Range;
+
+/// 複数の正規表現をまとめてコンパイルしたもの
+///
+/// すべてのパターンを1つの命令列に結合し、入力を1回走査するだけで、
+/// 入力のいずれかの位置にマッチするパターンをすべて求める
+#[derive(Debug, Clone)]
+pub struct RegexSet {
+    insts: Vec<Instruction>, // すべてのパターンを結合した命令列
+    len: usize,              // パターンの数
+}
+
+impl RegexSet {
+    /// 複数の正規表現をまとめてコンパイル
+    ///
+    /// いずれかのパターンのコンパイルに失敗した場合は、そのエラーを返す。
+    /// Regex::new と異なり空のパターンはエラーとせず、すべての位置で空文字列にマッチする
+    pub fn new(patterns: &[&str]) -> Result<RegexSet, RegexError> {
+        let mut programs = Vec::new();
+        for pattern in patterns {
+            let ast = match parser::parse(pattern, parser::Flags::default()) {
+                Ok(parsed) => parsed.ast,
+                Err(ParseError::Empty) => AST::Empty,
+                Err(e) => return Err(e.into()),
+            };
+            let insts = codegen::get_code(&ast, true, false)?;
+            programs.push(insts);
+        }
+
+        let insts = codegen::union_set(&programs)?;
+        Ok(RegexSet {
+            insts,
+            len: patterns.len(),
+        })
+    }
+
+    /// パターンの数を返す
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// パターンが1つもないかを判定
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// 入力文字列のいずれかの位置にマッチするパターンの番号を、昇順に返す
+    ///
+    /// 評価中にエラーが起きた場合は、どのパターンにもマッチしなかったものとして扱う
+    pub fn matches(&self, line: &str) -> Vec<usize> {
+        let chars: Vec<char> = line.chars().collect();
+        let input = evaluator::Input {
+            chars: &chars,
+            boundaries: None,
+            scan_start: 0,
+            window_start: 0,
+            max_len: None,
+        };
+        evaluator::eval_set(&self.insts, input).unwrap_or_default()
+    }
+
+    /// 入力文字列の start バイト目から始まるマッチを持つパターンの番号を、昇順に返す
+    ///
+    /// start より後から始まるマッチは含めない。マッチの終了位置はパターンごとに異なってもよい。
+    /// ^ は start ではなく入力の先頭にのみマッチし、\G は start にマッチする。
+    /// start が入力の範囲外の場合や文字の境界でない場合、評価中にエラーが起きた場合は空とする
+    pub fn matches_at(&self, line: &str, start: usize) -> Vec<usize> {
+        let from = if let Some(prefix) = line.get(..start) {
+            prefix.chars().count()
+        } else {
+            return Vec::new();
+        };
+        let chars: Vec<char> = line.chars().collect();
+        let input = evaluator::Input {
+            chars: &chars,
+            boundaries: None,
+            scan_start: from,
+            window_start: 0,
+            max_len: None,
+        };
+        match evaluator::eval_set_at(&self.insts, input, from) {
+            Ok(ends) => ends.into_iter().map(|(id, _)| id).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// 入力文字列を先頭から順に、最も長くマッチするパターンで区切るトークナイザを返す
+    ///
+    /// 詳しくは Lexer を参照
+    pub fn lexer<'s, 't>(&'s self, line: &'t str) -> Lexer<'s, 't> {
+        Lexer {
+            set: self,
+            line,
+            chars: line.chars().collect(),
+            pos: 0,
+            offset: 0,
+        }
+    }
+}
+
+/// RegexSet による最長一致のトークナイザ
+///
+/// 現在の位置から始まるマッチのうち最も長いものを1つのトークンとし、その終了位置から次のトークンを探す。
+/// 同じ長さのマッチが複数ある場合は、番号の小さいパターンを優先する。
+/// 各トークンはパターンの番号、マッチした文字列、バイト単位の範囲の組とする。
+/// 入力の末尾に達した場合や、どのパターンも1文字以上マッチしない場合はトークンを返さず、
+/// 残りの入力は rest で確認できる。
+/// 位置の扱いは RegexSet::matches_at と同じく、^ は入力の先頭に、\G は現在の位置にマッチする
+#[derive(Debug)]
+pub struct Lexer<'s, 't> {
+    set: &'s RegexSet,
+    line: &'t str,
+    chars: Vec<char>,
+    pos: usize,    // 次のトークンを探す文字単位の位置
+    offset: usize, // pos のバイト単位の位置
+}
+
+impl<'t> Lexer<'_, 't> {
+    /// まだトークンに区切っていない残りの入力を返す
+    ///
+    /// 入力全体をトークンに区切った場合は空文字列となる
+    pub fn rest(&self) -> &'t str {
+        &self.line[self.offset..]
+    }
+}
+
+impl<'t> Iterator for Lexer<'_, 't> {
+    type Item = (usize, &'t str, Range<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.chars.len() {
+            return None;
+        }
+        let input = evaluator::Input {
+            chars: &self.chars,
+            boundaries: None,
+            scan_start: self.pos,
+            window_start: 0,
+            max_len: None,
+        };
+        let ends = evaluator::eval_set_at(&self.set.insts, input, self.pos).ok()?;
+
+        // 最も長いマッチのうち、番号の最も小さいパターンを選ぶ
+        let mut best: Option<(usize, usize)> = None;
+        for (id, end) in ends {
+            if best.is_none_or(|(_, e)| end > e) {
+                best = Some((id, end));
+            }
+        }
+        let (id, end) = best.filter(|(_, end)| *end > self.pos)?;
+
+        let len: usize = self.chars[self.pos..end].iter().map(|c| c.len_utf8()).sum();
+        let span = self.offset..self.offset + len;
+        self.pos = end;
+        self.offset = span.end;
+        Some((id, &self.line[span.clone()], span))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches() {
+        let set = RegexSet::new(&["\\d+", "[a-z]+"]).unwrap();
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.matches("abc123"), vec![0, 1]);
+        assert_eq!(set.matches("123"), vec![0]);
+        assert_eq!(set.matches("abc"), vec![1]);
+        assert!(set.matches("ABC").is_empty());
+    }
+
+    #[test]
+    fn empty_pattern() {
+        let set = RegexSet::new(&["", "abc"]).unwrap();
+        assert_eq!(set.matches(""), vec![0]);
+        assert_eq!(set.matches("xyz"), vec![0]);
+        assert_eq!(set.matches("xabcx"), vec![0, 1]);
+        // 空のパターンはすべての位置にマッチする
+        let line = "xabc";
+        for start in 0..=line.len() {
+            let expected = if start == 1 { vec![0, 1] } else { vec![0] };
+            assert_eq!(set.matches_at(line, start), expected, "{start}");
+        }
+    }
+
+    #[test]
+    fn matches_at() {
+        let set = RegexSet::new(&["if", "[a-z]+"]).unwrap();
+        assert_eq!(set.matches_at("x if", 2), vec![0, 1]);
+        assert_eq!(set.matches_at("x if", 3), vec![1]);
+        // start より後から始まるマッチは含めない
+        assert!(set.matches_at("x if", 1).is_empty());
+        assert!(set.matches_at("x if", 9).is_empty());
+        // バイト単位で文字の境界でない位置は空
+        assert!(set.matches_at("éif", 1).is_empty());
+        assert_eq!(set.matches_at("éif", 2), vec![0, 1]);
+    }
+
+    #[test]
+    fn lexer() {
+        let set = RegexSet::new(&["if", "[a-z]+", " +"]).unwrap();
+        let tokens: Vec<_> = set.lexer("if x").collect();
+        assert_eq!(
+            tokens,
+            vec![(0, "if", 0..2), (2, " ", 2..3), (1, "x", 3..4)]
+        );
+
+        // 長いマッチを優先する
+        let tokens: Vec<_> = set.lexer("iffy if").collect();
+        assert_eq!(
+            tokens,
+            vec![(1, "iffy", 0..4), (2, " ", 4..5), (0, "if", 5..7)]
+        );
+
+        // どのパターンもマッチしない位置で止まる
+        let mut lexer = set.lexer("if 1x");
+        assert_eq!(lexer.next(), Some((0, "if", 0..2)));
+        assert_eq!(lexer.next(), Some((2, " ", 2..3)));
+        assert_eq!(lexer.next(), None);
+        assert_eq!(lexer.rest(), "1x");
+
+        let mut lexer = set.lexer("x");
+        assert_eq!(lexer.next(), Some((1, "x", 0..1)));
+        assert_eq!(lexer.next(), None);
+        assert_eq!(lexer.rest(), "");
+    }
+}
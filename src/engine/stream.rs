@@ -0,0 +1,117 @@
+//! Read から読み込んだバイト列を UTF-8 として1文字ずつデコード
+use std::io::{self, ErrorKind, Read};
+
+/// 読み込み時のバッファサイズ
+const BUF_SIZE: usize = 8192;
+
+/// Read から読み込んだバイト列を文字に変換するイテレータ
+///
+/// バッファの境界で分断された UTF-8 の文字は、次の読み込みで続きを補って変換する
+pub struct Utf8Chars<R> {
+    reader: R,
+    buf: Vec<u8>,     // 読み込み済みで、まだ変換していないバイト列
+    chars: Vec<char>, // 変換済みで、まだ返していない文字。逆順に格納
+    eof: bool,
+}
+
+impl<R: Read> Utf8Chars<R> {
+    pub fn new(reader: R) -> Self {
+        Utf8Chars {
+            reader,
+            buf: Vec::new(),
+            chars: Vec::new(),
+            eof: false,
+        }
+    }
+
+    /// 次のバッファを読み込み、変換可能な部分を文字に変換
+    fn fill(&mut self) -> io::Result<()> {
+        let mut chunk = [0; BUF_SIZE];
+        let n = match self.reader.read(&mut chunk) {
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        if n == 0 {
+            self.eof = true;
+            // 末尾に変換できないバイト列が残っている場合はエラー
+            if !self.buf.is_empty() {
+                return Err(io::Error::new(ErrorKind::InvalidData, "incomplete utf-8"));
+            }
+            return Ok(());
+        }
+        self.buf.extend_from_slice(&chunk[..n]);
+
+        // 末尾の途中で切れた文字は、次の読み込みまで buf に残す
+        let valid = match std::str::from_utf8(&self.buf) {
+            Ok(s) => s.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(e) => return Err(io::Error::new(ErrorKind::InvalidData, e)),
+        };
+        let rest = self.buf.split_off(valid);
+        // 先頭から valid バイトが UTF-8 として正しいことは確認済み
+        let s = String::from_utf8(std::mem::replace(&mut self.buf, rest)).unwrap();
+        self.chars = s.chars().rev().collect();
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for Utf8Chars<R> {
+    type Item = io::Result<char>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.chars.is_empty() {
+            if self.eof {
+                return None;
+            }
+            if let Err(e) = self.fill() {
+                self.eof = true;
+                return Some(Err(e));
+            }
+        }
+        self.chars.pop().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 1回の読み込みで、最大 n バイトずつ返す
+    struct Chunked<'a> {
+        data: &'a [u8],
+        n: usize,
+    }
+
+    impl Read for Chunked<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.n.min(self.data.len()).min(buf.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn split_utf8() {
+        let s = "aé日本🦀z";
+        for n in 1..=4 {
+            let reader = Chunked {
+                data: s.as_bytes(),
+                n,
+            };
+            let chars: String = Utf8Chars::new(reader).map(|c| c.unwrap()).collect();
+            assert_eq!(chars, s);
+        }
+
+        // 末尾で途切れた文字はエラー
+        let reader = Chunked {
+            data: &"日".as_bytes()[..2],
+            n: 1,
+        };
+        let result: io::Result<String> = Utf8Chars::new(reader).collect();
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
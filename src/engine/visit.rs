@@ -0,0 +1,57 @@
+//! 抽象構文木の走査
+use super::parser::AST;
+
+/// 式の直下の部分式を、パターン中に現れる順に返す
+pub fn children(ast: &AST) -> Vec<&AST> {
+    match ast {
+        AST::Plus(e) | AST::Star(e) | AST::Question(e) | AST::Repeat(e, _, _) => vec![e],
+        AST::Or(e1, e2) => vec![e1, e2],
+        AST::Seq(v) => v.iter().collect(),
+        AST::Capture(e, _, _) => vec![e],
+        AST::Look { inner, .. } => vec![inner],
+        AST::Conditional { yes, no, .. } => vec![yes, no],
+        AST::Char(_)
+        | AST::AnyChar(_)
+        | AST::Class(_)
+        | AST::Predicate(_, _)
+        | AST::Empty
+        | AST::AnchorStart
+        | AST::AnchorEnd
+        | AST::AnchorLineStart
+        | AST::AnchorLineEnd
+        | AST::WordBoundary(_, _)
+        | AST::AnchorContinue
+        | AST::ResetStart => Vec::new(),
+    }
+}
+
+/// 式とそのすべての部分式を、行きがけ順に f で訪問する
+///
+/// 部分式は親の式の後に、パターン中に現れる順に訪問する
+pub fn walk<F>(ast: &AST, f: &mut F)
+where
+    F: FnMut(&AST),
+{
+    f(ast);
+    for e in children(ast) {
+        walk(e, f);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::parser::{parse, Flags};
+
+    #[test]
+    fn count_chars() {
+        let ast = parse("a(bc|d)*e?[xy]", Flags::default()).unwrap().ast;
+        let mut count = 0;
+        walk(&ast, &mut |e| {
+            if let AST::Char(_) = e {
+                count += 1;
+            }
+        });
+        assert_eq!(count, 5);
+    }
+}
@@ -0,0 +1,10 @@
+//! 正規表現エンジン
+mod engine;
+mod helper;
+
+pub use engine::{
+    CaptureLocations, CaptureMatches, Captures, CodeGenError, Dfa, Engine, EvalError, Features,
+    Lexer, Lint, Match, Matches, NoExpand, ParseError, ParseErrorCode, Program, Regex,
+    RegexBuilder, RegexError, RegexSet, Replacer, Split, TraceEvent,
+};
+pub use helper::DynError;
@@ -1,3 +1,36 @@
-fn main() {
-    println!("Hello, world!");
+use rs_regex::{DynError, Regex};
+use std::{
+    env,
+    fs::File,
+    io::{BufRead, BufReader},
+};
+
+fn main() -> Result<(), DynError> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() <= 2 {
+        eprintln!("usage: {} regex file", args[0]);
+        return Err("invalid arguments".into());
+    } else {
+        match_file(&args[1], &args[2])?;
+    }
+
+    Ok(())
+}
+
+/// ファイルをオープンし、行ごとにマッチングを行う
+///
+/// マッチした行を出力する
+fn match_file(expr: &str, file: &str) -> Result<(), DynError> {
+    let regex = Regex::new(expr)?;
+    let f = File::open(file)?;
+    let reader = BufReader::new(f);
+
+    for line in reader.lines() {
+        let line = line?;
+        if regex.is_match(&line) {
+            println!("{line}");
+        }
+    }
+
+    Ok(())
 }